@@ -0,0 +1,69 @@
+//! Times size_and_hash on a large file, to compare reading it with mapping
+//! it into memory:
+//!
+//!     $ cargo bench --bench hash
+//!     $ cargo bench --bench hash --features mmap
+//!
+//! OSTDL_BENCH_FILE names the file to hash instead of a generated one, e.g.
+//! on a network filesystem. The first round is with whatever is cached.
+
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom, Write};
+use std::time::{Duration, Instant};
+
+use ostdl::size_and_hash;
+
+/// How many times the file is hashed
+const ROUNDS: u32 = 1000;
+
+/// The size of the generated file, sparse apart from its ends
+const SIZE: u64 = 4 << 30;
+
+fn main() {
+    let (path, generated) = match env::var_os("OSTDL_BENCH_FILE") {
+        Some(path) => (path, false),
+        None => {
+            let path = env::temp_dir().join(format!("ostdl-bench-{}", std::process::id()));
+            let mut file = File::create(&path).expect("cannot create the file to hash");
+            file.write_all(&[0x5a; 1 << 16]).unwrap();
+            file.seek(SeekFrom::Start(SIZE - (1 << 16))).unwrap();
+            file.write_all(&[0xa5; 1 << 16]).unwrap();
+            (OsString::from(path), true)
+        }
+    };
+
+    let start = Instant::now();
+    let (size, hash) = size_and_hash(&path).expect("cannot hash the file");
+    let first = start.elapsed();
+
+    let mut total = Duration::default();
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        size_and_hash(&path).unwrap();
+        total += start.elapsed();
+    }
+
+    if generated {
+        let _ = fs::remove_file(&path);
+    }
+
+    println!(
+        "{}: {} bytes, hash {:016x}, {}",
+        path.to_string_lossy(),
+        size,
+        hash,
+        if cfg!(feature = "mmap") {
+            "mapped"
+        } else {
+            "read"
+        }
+    );
+    println!(
+        "first: {:.1}us, then {:.1}us on average of {}",
+        first.as_secs_f64() * 1e6,
+        total.as_secs_f64() * 1e6 / f64::from(ROUNDS),
+        ROUNDS
+    );
+}
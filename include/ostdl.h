@@ -0,0 +1,56 @@
+/* C interface of ostdl, a downloader for opensubtitles.org.
+ * Build the shared library with `cargo build --release --features capi`. */
+
+#ifndef OSTDL_H
+#define OSTDL_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum {
+    OSTDL_OK = 0,
+    OSTDL_INVALID_ARGUMENT = 1,
+    OSTDL_IO = 2,
+    OSTDL_API = 3,
+    OSTDL_NETWORK = 4,
+    OSTDL_OUT_OF_RANGE = 5,
+} ostdl_status;
+
+typedef struct OstdlSession ostdl_session;
+typedef struct OstdlSearch ostdl_search;
+
+/* Message of the last error on this thread, or NULL. Valid until the
+ * next failing call on the same thread. */
+const char *ostdl_last_error(void);
+
+/* OSDb size and hash of a file. */
+ostdl_status ostdl_hash(const char *path, uint64_t *size, uint64_t *hash);
+
+/* Logs in; free the session with ostdl_session_free. */
+ostdl_status ostdl_session_new(ostdl_session **out);
+void ostdl_session_free(ostdl_session *session);
+
+/* Searches subtitles for a file in comma separated languages ("eng,hun");
+ * free the result with ostdl_search_free. */
+ostdl_status ostdl_search_new(const ostdl_session *session, const char *path,
+                              const char *langs, ostdl_search **out);
+void ostdl_search_free(ostdl_search *search);
+
+/* Accessors for the search results; strings are owned by the search. */
+size_t ostdl_search_count(const ostdl_search *search);
+const char *ostdl_search_lang(const ostdl_search *search, size_t idx);
+const char *ostdl_search_format(const ostdl_search *search, size_t idx);
+double ostdl_search_score(const ostdl_search *search, size_t idx);
+
+/* Downloads the idx-th subtitle of a search into path. */
+ostdl_status ostdl_download(const ostdl_search *search, size_t idx, const char *path);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* OSTDL_H */
@@ -1,55 +1,758 @@
 use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
-use xmlrpc::{Request, Value};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::{debug, info, trace};
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use xmlrpc::{Request, Transport, Value};
 
-use crate::error::{Error, E_INV_RESP};
+use crate::error::{print_if_err, Error, E_INV_RESP};
+use crate::net::{api_url, check_http, client, retrying, Failure};
+use crate::quota::{self, Quota};
+use crate::rest;
+use crate::tokens::{self, Tokens};
 
 /// opensubtitles XML-RPC API entry point
 pub(crate) const OST_API_URL: &str = "https://api.opensubtitles.org/xml-rpc";
 
+/// The entry point for the VIP members, without ads and with higher limits
+pub(crate) const OST_VIP_API_URL: &str = "https://vip-api.opensubtitles.org/xml-rpc";
+
+/// Whether the requests go to the VIP entry points
+static VIP: AtomicBool = AtomicBool::new(false);
+
+/// Switches to the VIP entry points of the APIs, for the sessions of VIP
+/// members. The entry point set in the net settings still takes
+/// precedence.
+pub fn use_vip_endpoint() {
+    VIP.store(true, Ordering::Relaxed);
+}
+
+/// Whether the VIP entry points are used
+pub(crate) fn vip_endpoint() -> bool {
+    VIP.load(Ordering::Relaxed)
+}
+
+/// The XML-RPC entry point in use
+fn xmlrpc_url() -> &'static str {
+    api_url().unwrap_or(if vip_endpoint() {
+        OST_VIP_API_URL
+    } else {
+        OST_API_URL
+    })
+}
+
+/// The user agent used when none is configured
+pub const DEFAULT_USER_AGENT: &str = "opensubtitles-download 1.0";
+
+/// Hands an already received response body to the XML-RPC parser
+struct Received(Vec<u8>);
+
+impl Transport for Received {
+    type Stream = Cursor<Vec<u8>>;
+
+    fn transmit(
+        self,
+        _request: &Request<'_>,
+    ) -> Result<Self::Stream, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Cursor::new(self.0))
+    }
+}
+
+/// The status of the response if it tells to try again later
+fn transient_status(v: &Value) -> Option<&str> {
+    let status = v.as_struct()?.get("status")?.as_str()?;
+
+    if status.starts_with("429") || status.starts_with('5') {
+        Some(status)
+    } else {
+        None
+    }
+}
+
+/// Calls the XML-RPC method, returning the result. Transient failures,
+/// including the ones the server reports in the status, are retried.
+pub(crate) async fn call(request: &Request<'_>) -> Result<Value, Error> {
+    let mut body = Vec::new();
+    request.write_as_xml(&mut body)?;
+
+    retrying(true, || {
+        let req = client()
+            .post(xmlrpc_url())
+            .header(CONTENT_TYPE, "text/xml; charset=utf-8")
+            .body(body.clone());
+        async move {
+            let data = check_http(req.send().await)?
+                .error_for_status()
+                .map_err(Error::from)?
+                .bytes()
+                .await
+                .map_err(Error::from)?;
+            let value = request.call(Received(data.to_vec())).map_err(Error::from)?;
+
+            trace!("xmlrpc response: {:?}", value);
+            match transient_status(&value) {
+                Some(status) => Err(Failure::Transient(
+                    Error::Ost(format!("xmlrpc request failed: {}", status).into()),
+                    None,
+                )),
+                None => Ok(value),
+            }
+        }
+    })
+    .await
+    .map_err(|e| e.at(xmlrpc_url()))
+}
+
+/// The error of an invalid response of the XML-RPC entry point
+pub(crate) fn invalid_response() -> Error {
+    E_INV_RESP.at(xmlrpc_url())
+}
+
 /// To simplify definitions using the XML-RPC "struct" type
 type OstDataMap = BTreeMap<String, Value>;
 
+/// The statuses of the responses refusing the credentials, the session or
+/// the user agent
+const AUTH_STATUSES: &[&str] = &["401", "406", "411", "414", "415"];
+
 /// Converts an XML-RPC response into an OstDatamap
 pub(crate) fn val_to_response(v: &Value) -> Result<&OstDataMap, Error> {
-    let resp = v.as_struct().ok_or(E_INV_RESP)?;
+    let resp = v.as_struct().ok_or_else(invalid_response)?;
 
     let status = resp
         .get("status")
         .and_then(Value::as_str)
-        .ok_or(E_INV_RESP)?;
+        .ok_or_else(invalid_response)?;
 
     if status.starts_with("200") {
         Ok(resp)
+    } else if status.starts_with("407") {
+        quota::set(0, None);
+        Err(Error::Quota(format!("xmlrpc request failed: {}", status).into()).at(xmlrpc_url()))
+    } else if AUTH_STATUSES.iter().any(|code| status.starts_with(code)) {
+        Err(Error::Auth(format!("xmlrpc request failed: {}", status).into()).at(xmlrpc_url()))
     } else {
-        Err(Error::Ost(
-            format!("xmlrpc request failed: {}", status).into(),
-        ))
+        Err(Error::Ost(format!("xmlrpc request failed: {}", status).into()).at(xmlrpc_url()))
     }
 }
 
-/// Creates the body of the search request
-pub(crate) fn make_req(lang: &str, size: u64, hash: u64) -> Value {
+/// The maximum number of hits the server returns for a search
+pub(crate) const SEARCH_LIMIT: usize = 1000;
+
+/// Creates the options of the search request, asking for as many hits
+/// as possible
+pub(crate) fn make_search_opts() -> Value {
     let mut m = BTreeMap::new();
-    m.insert("sublanguageid".into(), Value::String(lang.to_string()));
-    m.insert("moviehash".into(), Value::String(format!("{:x}", hash)));
-    m.insert("moviebytesize".into(), Value::String(size.to_string()));
+    m.insert("limit".into(), Value::Int(SEARCH_LIMIT as i32));
 
     Value::Struct(m)
 }
 
-/// logs into OpenSubtitles API and returns the access token
-pub(crate) fn get_token() -> Result<String, Error> {
-    let resp = Request::new("LogIn")
-        .arg("")
-        .arg("")
+/// What to search the subtitles by
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    /// The size and the OSDb hash of the video
+    pub size_hash: Option<(u64, u64)>,
+    /// The IMDb id of the movie or episode
+    pub imdb_id: Option<u64>,
+    /// The title to search for, replacing the hash
+    pub text: Option<String>,
+    /// The file name of the video, as released
+    pub tag: Option<String>,
+    /// The season of the episode searched for by title or IMDb id
+    pub season: Option<u32>,
+    /// The episode searched for by title or IMDb id
+    pub episode: Option<u32>,
+    /// The local video, for the providers hashing it their own way
+    pub video: Option<PathBuf>,
+}
+
+impl Query {
+    /// A query by the size and the hash of the video
+    pub fn hash(size: u64, hash: u64) -> Query {
+        Query {
+            size_hash: Some((size, hash)),
+            ..Query::default()
+        }
+    }
+
+    /// A name for the subtitles of a text query when there is no video,
+    /// e.g. Show Name S02E05
+    pub fn name(&self) -> Option<String> {
+        let mut name = self.text.clone()?;
+        match (self.season, self.episode) {
+            (Some(s), Some(e)) => name.push_str(&format!(" S{:02}E{:02}", s, e)),
+            (Some(s), None) => name.push_str(&format!(" S{:02}", s)),
+            (None, Some(e)) => name.push_str(&format!(" E{:02}", e)),
+            (None, None) => {}
+        }
+
+        Some(name)
+    }
+}
+
+/// Parses an IMDb id, with or without the tt prefix
+pub fn parse_imdb_id(id: &str) -> Result<u64, Error> {
+    let digits = id.strip_prefix("tt").unwrap_or(id);
+
+    match digits.parse() {
+        Ok(id) if digits.bytes().all(|b| b.is_ascii_digit()) => Ok(id),
+        _ => Err(Error::Ost(format!("invalid IMDb id: {}", id).into())),
+    }
+}
+
+/// Creates the bodies of the search request, one per criterion of the
+/// query as the server only matches by one of them per body
+pub(crate) fn make_reqs(lang: &str, query: &Query) -> Vec<Value> {
+    let mut reqs = Vec::new();
+
+    let new_req = || {
+        let mut m = BTreeMap::new();
+        m.insert("sublanguageid".into(), Value::String(lang.to_string()));
+        m
+    };
+
+    let add_episode = |m: &mut OstDataMap| {
+        if let Some(season) = query.season {
+            m.insert("season".into(), Value::String(season.to_string()));
+        }
+        if let Some(episode) = query.episode {
+            m.insert("episode".into(), Value::String(episode.to_string()));
+        }
+    };
+
+    if let Some((size, hash)) = query.size_hash {
+        let mut m = new_req();
+        m.insert("moviehash".into(), Value::String(format!("{:016x}", hash)));
+        m.insert("moviebytesize".into(), Value::String(size.to_string()));
+        reqs.push(Value::Struct(m));
+    }
+
+    if let Some(imdb_id) = query.imdb_id {
+        let mut m = new_req();
+        m.insert("imdbid".into(), Value::String(format!("{:07}", imdb_id)));
+        add_episode(&mut m);
+        reqs.push(Value::Struct(m));
+    }
+
+    if let Some(ref tag) = query.tag {
+        let mut m = new_req();
+        m.insert("tag".into(), Value::String(tag.clone()));
+        reqs.push(Value::Struct(m));
+    }
+
+    if let Some(ref text) = query.text {
+        let mut m = new_req();
+        m.insert("query".into(), Value::String(text.clone()));
+        add_episode(&mut m);
+        reqs.push(Value::Struct(m));
+    }
+
+    reqs
+}
+
+/// A user comment on a subtitle
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub user: String,
+    pub text: String,
+    pub created: String,
+}
+
+/// Converts a comment returned by GetComments
+fn val_to_comment(v: &Value) -> Option<Comment> {
+    let data = v.as_struct()?;
+    let field = |name| data.get(name).and_then(Value::as_str).unwrap_or("");
+
+    Some(Comment {
+        user: field("UserNickName").into(),
+        text: field("Comment")
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" "),
+        created: field("Created").into(),
+    })
+}
+
+/// Fetches the comments of the given subtitles, keyed by subtitle id
+pub async fn get_comments(
+    ids: &[&str],
+    token: &str,
+) -> Result<BTreeMap<String, Vec<Comment>>, Error> {
+    let ids: Vec<Value> = ids.iter().map(|&id| Value::from(id)).collect();
+    let req = Request::new("GetComments")
+        .arg(token)
+        .arg(Value::Array(ids));
+    let resp = call(&req).await?;
+
+    let mut comments = BTreeMap::new();
+    // "data" is false when there are no comments at all
+    if let Some(data) = val_to_response(&resp)?
+        .get("data")
+        .and_then(Value::as_struct)
+    {
+        for (key, list) in data {
+            let list = list.as_array().ok_or_else(invalid_response)?;
+            comments.insert(
+                key.trim_start_matches('_').to_string(),
+                list.iter().filter_map(val_to_comment).collect(),
+            );
+        }
+    }
+
+    Ok(comments)
+}
+
+/// The rating of a subtitle, from 1 to 10, and the number of votes it is
+/// based on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rating {
+    pub score: f64,
+    pub votes: u64,
+}
+
+/// Votes for the subtitle with a score from 1 to 10, returning its new
+/// rating. Only registered users can vote.
+pub async fn vote(id: &str, score: u8, token: &str) -> Result<Rating, Error> {
+    let mut ballot = BTreeMap::new();
+    ballot.insert("idsubtitle".to_string(), Value::from(id));
+    ballot.insert("score".to_string(), Value::Int(score.into()));
+    let req = Request::new("SubtitlesVote")
+        .arg(token)
+        .arg(Value::Struct(ballot));
+    let resp = call(&req).await?;
+
+    let data = val_to_response(&resp)?
+        .get("data")
+        .and_then(Value::as_struct)
+        .ok_or_else(invalid_response)?;
+    let score = match data.get("SubRating") {
+        Some(Value::Double(score)) => Some(*score),
+        Some(Value::String(score)) => score.parse().ok(),
+        _ => None,
+    };
+
+    Ok(Rating {
+        score: score.ok_or_else(invalid_response)?,
+        votes: data.get("SubSumVotes").and_then(number).unwrap_or(0),
+    })
+}
+
+/// Comments on the subtitle where the other users see it, flagging it as
+/// bad if asked to. Only registered users can comment.
+pub async fn add_comment(id: &str, text: &str, bad: bool, token: &str) -> Result<(), Error> {
+    let mut comment = BTreeMap::new();
+    comment.insert("idsubtitle".to_string(), Value::from(id));
+    comment.insert("comment".to_string(), Value::from(text));
+    if bad {
+        comment.insert("badsubtitle".to_string(), Value::Int(1));
+    }
+    let req = Request::new("AddComment")
+        .arg(token)
+        .arg(Value::Struct(comment));
+    let resp = call(&req).await?;
+    val_to_response(&resp)?;
+
+    Ok(())
+}
+
+/// Asks the server which of the subtitle files, by the MD5 hashes of
+/// their content in hex, it has already, e.g. before uploading them. The
+/// ids of the files on the server are returned by hash, None if it does
+/// not have the file.
+pub async fn check_sub_hashes(
+    hashes: &[String],
+    token: &str,
+) -> Result<BTreeMap<String, Option<String>>, Error> {
+    let list: Vec<Value> = hashes.iter().map(|h| Value::from(h.as_str())).collect();
+    let req = Request::new("CheckSubHash")
+        .arg(token)
+        .arg(Value::Array(list));
+    let resp = call(&req).await?;
+
+    let data = val_to_response(&resp)?
+        .get("data")
+        .and_then(Value::as_struct)
+        .ok_or_else(invalid_response)?;
+
+    Ok(hashes
+        .iter()
+        .map(|hash| {
+            // 0 when the server does not have it
+            let id = data
+                .get(hash)
+                .and_then(number)
+                .filter(|&id| id > 0)
+                .map(|id| id.to_string());
+            (hash.clone(), id)
+        })
+        .collect())
+}
+
+/// The hash of a video along with the movie it is of
+#[derive(Debug, Clone)]
+pub struct MovieHash {
+    pub hash: u64,
+    pub size: u64,
+    pub imdb_id: u64,
+    /// The name of the video file
+    pub file_name: String,
+}
+
+/// Adds the hashes of the videos to the database with the IMDb ids of
+/// their movies, so that they are found by the hash from then on,
+/// returning the hashes accepted. Only registered users can add them.
+pub async fn insert_movie_hashes(hashes: &[MovieHash], token: &str) -> Result<Vec<String>, Error> {
+    let list = hashes
+        .iter()
+        .map(|h| {
+            let mut m = BTreeMap::new();
+            m.insert(
+                "moviehash".into(),
+                Value::String(format!("{:016x}", h.hash)),
+            );
+            m.insert("moviebytesize".into(), Value::String(h.size.to_string()));
+            m.insert("imdbid".into(), Value::String(h.imdb_id.to_string()));
+            m.insert("moviefilename".into(), Value::String(h.file_name.clone()));
+            Value::Struct(m)
+        })
+        .collect();
+    let req = Request::new("InsertMovieHash")
+        .arg(token)
+        .arg(Value::Array(list));
+    let resp = call(&req).await?;
+
+    let accepted = val_to_response(&resp)?
+        .get("data")
+        .and_then(Value::as_struct)
+        .and_then(|data| data.get("accepted_moviehashes"))
+        .and_then(Value::as_array)
+        .map(|hashes| {
+            hashes
+                .iter()
+                .filter_map(Value::as_str)
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(accepted)
+}
+
+/// Reports the match of the hash of a video with a subtitle as wrong, e.g.
+/// when the subtitle is for another cut, by the IDSubMovieFile of the
+/// match returned by the search
+pub async fn report_wrong_hash(movie_file_id: &str, token: &str) -> Result<(), Error> {
+    let req = Request::new("ReportWrongMovieHash")
+        .arg(token)
+        .arg(movie_file_id);
+    let resp = call(&req).await?;
+    val_to_response(&resp)?;
+
+    Ok(())
+}
+
+/// Downloads the subtitle file with the DownloadSubtitles call, by the id
+/// of the file rather than of the subtitle, returning the gzipped data
+pub async fn download_file(file_id: &str, token: &str) -> Result<Vec<u8>, Error> {
+    let req = Request::new("DownloadSubtitles")
+        .arg(token)
+        .arg(Value::Array(vec![Value::from(file_id)]));
+    let resp = call(&req).await?;
+
+    let encoded = val_to_response(&resp)?
+        .get("data")
+        .and_then(Value::as_array)
+        .and_then(|files| files.first())
+        .and_then(Value::as_struct)
+        .and_then(|file| file.get("data"))
+        .and_then(Value::as_str)
+        .ok_or_else(invalid_response)?;
+
+    // the base64 data may be wrapped into lines
+    let encoded: String = encoded.split_whitespace().collect();
+    STANDARD.decode(encoded).map_err(|_| invalid_response())
+}
+
+/// A number in the XML-RPC response, which may be sent as a string
+fn number(v: &Value) -> Option<u64> {
+    match v {
+        Value::Int(n) if *n >= 0 => Some(*n as u64),
+        Value::Int64(n) if *n >= 0 => Some(*n as u64),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+/// The downloads left for the day as told by ServerInfo, counted by the
+/// user or by the IP address of anonymous sessions
+async fn xmlrpc_quota() -> Result<Option<Quota>, Error> {
+    let resp = call(&Request::new("ServerInfo")).await?;
+    let limits = match resp
+        .as_struct()
+        .and_then(|info| info.get("download_limits"))
+        .and_then(Value::as_struct)
+    {
+        Some(limits) => limits,
+        None => return Ok(None),
+    };
+
+    let limit = limits.get("client_24h_download_limit").and_then(number);
+    let count = limits.get("client_24h_download_count").and_then(number);
+    let remaining = match (
+        limits.get("client_download_quota").and_then(number),
+        limit,
+        count,
+    ) {
+        (Some(quota), _, _) => quota,
+        (None, Some(limit), Some(count)) => limit.saturating_sub(count),
+        _ => return Ok(None),
+    };
+
+    Ok(Some(Quota { remaining, limit }))
+}
+
+/// Asks the server how many subtitles can still be downloaded today,
+/// remembering it for the downloads, see the quota module. None if the
+/// server doesn't tell.
+pub async fn download_quota(session: &Session) -> Result<Option<Quota>, Error> {
+    let quota = match session.backend {
+        Backend::XmlRpc => xmlrpc_quota().await?,
+        Backend::Rest { ref api_key } => rest::quota(session, api_key).await?,
+    };
+    if let Some(quota) = quota {
+        quota::set(quota.remaining, quota.limit);
+    }
+
+    Ok(quota)
+}
+
+/// A language the server has subtitles in, as listed by GetSubLanguages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubLanguage {
+    /// The id used by the API, e.g. eng
+    pub id: String,
+    /// The two letter code, e.g. en
+    pub iso639: String,
+    /// The English name
+    pub name: String,
+}
+
+/// Lists the languages the server has subtitles in, which needs no login
+pub async fn get_sub_languages() -> Result<Vec<SubLanguage>, Error> {
+    let req = Request::new("GetSubLanguages").arg("en");
+    let resp = call(&req).await?;
+
+    let data = val_to_response(&resp)?
+        .get("data")
+        .and_then(Value::as_array)
+        .ok_or_else(invalid_response)?;
+    let langs = data
+        .iter()
+        .filter_map(|lang| {
+            let lang = lang.as_struct()?;
+            let field = |name| lang.get(name).and_then(Value::as_str);
+            Some(SubLanguage {
+                id: field("SubLanguageID")?.into(),
+                iso639: field("ISO639").unwrap_or_default().into(),
+                name: field("LanguageName").unwrap_or_default().into(),
+            })
+        })
+        .collect();
+
+    Ok(langs)
+}
+
+/// Username and password of a registered user
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Which API to talk to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Backend {
+    /// The XML-RPC API of api.opensubtitles.org
+    XmlRpc,
+    /// The REST API of api.opensubtitles.com, with the API key
+    Rest { api_key: String },
+}
+
+/// A logged in session
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// The access token to pass to the API calls
+    pub token: String,
+    /// Whether logged in as a registered user, who gets a higher quota
+    pub authenticated: bool,
+    /// Whether the user is a VIP member, see use_vip_endpoint
+    pub vip: bool,
+    /// The API the session belongs to
+    pub backend: Backend,
+    /// The user agent to identify as
+    pub user_agent: String,
+}
+
+impl Session {
+    /// A session of the XML-RPC API with a token obtained elsewhere
+    pub fn from_token(token: String) -> Session {
+        Session {
+            token,
+            authenticated: false,
+            vip: false,
+            backend: Backend::XmlRpc,
+            user_agent: DEFAULT_USER_AGENT.into(),
+        }
+    }
+}
+
+/// logs into the API with the user agent, as a registered user if
+/// credentials are given, anonymously otherwise
+pub async fn login(
+    backend: &Backend,
+    creds: Option<&Credentials>,
+    user_agent: &str,
+) -> Result<Session, Error> {
+    match backend {
+        Backend::XmlRpc => xmlrpc_login(creds, user_agent).await,
+        Backend::Rest { ref api_key } => rest::login(api_key, creds, user_agent).await,
+    }
+}
+
+/// Asks the server whether it still accepts the token of the session,
+/// keeping the token alive for the XML-RPC API
+pub(crate) async fn token_valid(session: &Session) -> Result<bool, Error> {
+    match session.backend {
+        Backend::XmlRpc => {
+            let req = Request::new("NoOperation").arg(session.token.as_str());
+            let resp = call(&req).await?;
+            Ok(val_to_response(&resp).is_ok())
+        }
+        Backend::Rest { ref api_key } => rest::token_valid(session, api_key).await,
+    }
+}
+
+/// How often a session has to be kept alive: XML-RPC tokens expire after
+/// 15 minutes without requests
+pub const KEEP_ALIVE: Duration = Duration::from_secs(10 * 60);
+
+/// Keeps the token of a session alive in the background until dropped,
+/// as a batch of files can take longer to search and download than the
+/// token lives unused
+pub struct Heartbeat(tokio::task::JoinHandle<()>);
+
+impl Heartbeat {
+    /// Starts keeping the session alive
+    pub fn start(session: &Session) -> Heartbeat {
+        Heartbeat(beat(session.clone()))
+    }
+
+    /// Keeps the session given alive instead, e.g. after logging in again
+    pub fn restart(&mut self, session: &Session) {
+        self.0.abort();
+        self.0 = beat(session.clone());
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Checks the token of the session every KEEP_ALIVE, which keeps it alive
+fn beat(session: Session) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(KEEP_ALIVE).await;
+            match token_valid(&session).await {
+                Ok(true) => debug!("kept the session alive"),
+                // logged in again by keep_alive before the next batch
+                Ok(false) => debug!("the session has expired"),
+                Err(e) => debug!("cannot keep the session alive: {}", e),
+            }
+        }
+    })
+}
+
+/// Keeps the session alive during long runs, logging in again with the
+/// same credentials if the server has forgotten the token anyway
+pub async fn keep_alive(session: &mut Session, creds: Option<&Credentials>) -> Result<(), Error> {
+    if !token_valid(session).await? {
+        info!("the session has expired, logging in again");
+        *session = login(&session.backend, creds, &session.user_agent).await?;
+    }
+
+    Ok(())
+}
+
+/// Logs in like login, but reuses the token of an earlier run as long as
+/// the server accepts it. Problems with the cache of the tokens are only
+/// reported.
+pub async fn login_cached(
+    backend: &Backend,
+    creds: Option<&Credentials>,
+    user_agent: &str,
+) -> Result<Session, Error> {
+    let loaded = Tokens::load();
+    print_if_err(&loaded);
+    let mut tokens = loaded.unwrap_or_default();
+    let key = tokens::key(backend, creds, user_agent);
+
+    let session = match tokens.get(&key, backend, user_agent) {
+        Some(session) if token_valid(&session).await? => {
+            debug!("reusing the token of an earlier run");
+            session
+        }
+        _ => login(backend, creds, user_agent).await?,
+    };
+
+    // anonymous REST sessions have no token
+    if !session.token.is_empty() {
+        tokens.insert(key, &session);
+        print_if_err(&tokens.save());
+    }
+
+    Ok(session)
+}
+
+/// logs into OpenSubtitles XML-RPC API
+async fn xmlrpc_login(creds: Option<&Credentials>, user_agent: &str) -> Result<Session, Error> {
+    let (username, password) = creds.map_or(("", ""), |c| (&c.username, &c.password));
+
+    let req = Request::new("LogIn")
+        .arg(username)
+        .arg(password)
         .arg("en")
-        .arg("opensubtitles-download 1.0")
-        .call_url(OST_API_URL)?;
+        .arg(user_agent);
+    let resp = call(&req).await?;
 
-    val_to_response(&resp)?
+    let resp = val_to_response(&resp)?;
+    let token = resp
         .get("token")
         .and_then(Value::as_str)
         .map(String::from)
-        .ok_or(E_INV_RESP)
+        .ok_or_else(invalid_response)?;
+    // e.g. "vip member"
+    let vip = resp
+        .get("data")
+        .and_then(Value::as_struct)
+        .and_then(|data| data.get("UserRank"))
+        .and_then(Value::as_str)
+        .is_some_and(|rank| rank.to_lowercase().contains("vip"));
+
+    Ok(Session {
+        token,
+        authenticated: creds.is_some(),
+        vip,
+        backend: Backend::XmlRpc,
+        user_agent: user_agent.to_string(),
+    })
 }
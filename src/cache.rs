@@ -0,0 +1,344 @@
+//! The search results and the downloaded subtitles kept between runs, so
+//! that the runs over the same library don't search for all the files
+//! again, nor download the same subtitles again
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::api::{Comment, Query};
+use crate::error::Error;
+use crate::paths::cache_dir;
+use crate::provider::SubtitleProvider;
+use crate::subtitle::{md5_matches, Sub, Subs};
+
+/// How long the results are kept by default
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The results of a search and when it was made
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    subs: Subs,
+    /// In seconds since the epoch
+    time: u64,
+}
+
+/// The current time in seconds since the epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// The results of the searches by hash, keyed by the providers, the hash,
+/// the size and the languages searched for. Only the searches that found
+/// something are kept, so that new subtitles are found as soon as they
+/// are uploaded.
+#[derive(Debug, Default)]
+pub struct SearchCache {
+    path: Option<PathBuf>,
+    /// Whether the results kept are ignored, only the new ones being kept
+    refresh: bool,
+    entries: Mutex<BTreeMap<String, Entry>>,
+}
+
+impl SearchCache {
+    /// Loads the results younger than the ttl from the cache directory,
+    /// none if the file does not exist yet. With refresh all of them are
+    /// searched for again.
+    pub fn load(ttl: Duration, refresh: bool) -> Result<SearchCache, Error> {
+        let path = cache_dir()
+            .ok_or("cannot find the cache directory")?
+            .join("searches.json");
+
+        let mut entries: BTreeMap<String, Entry> = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))
+                .map_err(|e| Error::Ost(format!("invalid search cache: {}", e).into()))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let now = now();
+        entries.retain(|_, entry| now.saturating_sub(entry.time) < ttl.as_secs());
+
+        Ok(SearchCache {
+            path: Some(path),
+            refresh,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// A cache neither used nor saved
+    pub fn disabled() -> SearchCache {
+        SearchCache {
+            refresh: true,
+            ..SearchCache::default()
+        }
+    }
+
+    /// The key of the results of the query, if they are kept: the ones of
+    /// the searches by hash
+    fn key(provider: &str, query: &Query, langs: &str) -> Option<String> {
+        if query.text.is_some() {
+            return None;
+        }
+        let (size, hash) = query.size_hash?;
+        let mut key = format!("{} {:016x} {} {}", provider, hash, size, langs);
+        if let Some(imdb_id) = query.imdb_id {
+            key = format!("{} tt{:07}", key, imdb_id);
+        }
+
+        Some(key)
+    }
+
+    fn get(&self, key: &str) -> Option<Subs> {
+        if self.refresh {
+            return None;
+        }
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.get(key).map(|entry| entry.subs.clone())
+    }
+
+    fn insert(&self, key: String, subs: &[Sub]) {
+        if subs.is_empty() {
+            return;
+        }
+        let entry = Entry {
+            subs: subs.to_vec(),
+            time: now(),
+        };
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.insert(key, entry);
+    }
+
+    /// Writes the results back into the cache directory
+    pub fn save(&self) -> Result<(), Error> {
+        if let Some(ref path) = self.path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let file = BufWriter::new(File::create(path)?);
+            let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            serde_json::to_writer(file, &*entries)
+                .map_err(|e| Error::Ost(format!("cannot write search cache: {}", e).into()))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The downloaded subtitles, by the MD5 hash of their content when known,
+/// by the provider and their file id otherwise. They are kept as they are
+/// fetched, before any processing.
+#[derive(Debug, Default)]
+pub struct DownloadCache {
+    dir: Option<PathBuf>,
+}
+
+/// Replaces the characters that may not be in file names
+fn file_name(key: &str) -> String {
+    key.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+impl DownloadCache {
+    /// The subtitles kept in the cache directory
+    pub fn open() -> Result<DownloadCache, Error> {
+        let dir = cache_dir()
+            .ok_or("cannot find the cache directory")?
+            .join("subtitles");
+
+        Ok(DownloadCache { dir: Some(dir) })
+    }
+
+    /// A cache neither used nor saved
+    pub fn disabled() -> DownloadCache {
+        DownloadCache::default()
+    }
+
+    /// Where the subtitle is kept
+    fn path(&self, sub: &Sub) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        let key = if sub.md5.len() == 32 && sub.md5.chars().all(|c| c.is_ascii_hexdigit()) {
+            format!("md5-{}", sub.md5.to_lowercase())
+        } else if !sub.file_id.is_empty() {
+            format!("{}-{}", sub.provider, sub.file_id)
+        } else {
+            format!("{}-{}", sub.provider, sub.id)
+        };
+
+        Some(dir.join(file_name(&key)))
+    }
+
+    /// The content of the subtitle if it is kept and intact
+    fn get(&self, sub: &Sub) -> Option<Vec<u8>> {
+        let data = fs::read(self.path(sub)?).ok()?;
+        if data.is_empty() || !md5_matches(sub, &data) {
+            return None;
+        }
+
+        Some(data)
+    }
+
+    /// Keeps the content of the subtitle, problems only being logged
+    fn insert(&self, sub: &Sub, data: &[u8]) {
+        let path = match self.path(sub) {
+            Some(path) => path,
+            None => return,
+        };
+        // written aside first, so that the parallel downloads never see a
+        // partial file
+        let partial = path.with_extension("partial");
+        let res = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&partial, data))
+            .and_then(|()| fs::rename(&partial, &path));
+        if let Err(e) = res {
+            debug!("cannot keep subtitle {} in the cache: {}", sub.id, e);
+        }
+    }
+}
+
+/// Both of the caches
+#[derive(Debug, Default)]
+pub struct Caches {
+    pub searches: SearchCache,
+    pub downloads: DownloadCache,
+}
+
+impl Caches {
+    /// Caches neither used nor saved
+    pub fn disabled() -> Caches {
+        Caches {
+            searches: SearchCache::disabled(),
+            downloads: DownloadCache::disabled(),
+        }
+    }
+}
+
+/// A provider answering the searches by hash and the downloads from the
+/// caches when it can, asking the other provider otherwise
+pub struct Cached<'a> {
+    inner: Box<dyn SubtitleProvider + 'a>,
+    caches: &'a Caches,
+}
+
+impl<'a> Cached<'a> {
+    pub fn new(inner: Box<dyn SubtitleProvider + 'a>, caches: &'a Caches) -> Cached<'a> {
+        Cached { inner, caches }
+    }
+
+    fn key(&self, query: &Query, langs: &str) -> Option<String> {
+        SearchCache::key(self.inner.name(), query, langs)
+    }
+
+    /// The results kept for the query
+    fn cached(&self, query: &Query, langs: &str) -> Option<Subs> {
+        self.key(query, langs)
+            .and_then(|key| self.caches.searches.get(&key))
+    }
+
+    /// Keeps the results of the query
+    fn store(&self, query: &Query, langs: &str, subs: &[Sub]) {
+        if let Some(key) = self.key(query, langs) {
+            self.caches.searches.insert(key, subs);
+        }
+    }
+}
+
+impl SubtitleProvider for Cached<'_> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn search<'a>(
+        &'a self,
+        query: &'a Query,
+        langs: &'a str,
+    ) -> BoxFuture<'a, Result<Subs, Error>> {
+        async move {
+            if let Some(subs) = self.cached(query, langs) {
+                debug!("search results of {:?} found in the cache", query.size_hash);
+                return Ok(subs);
+            }
+            let subs = self.inner.search(query, langs).await?;
+            self.store(query, langs, &subs);
+
+            Ok(subs)
+        }
+        .boxed()
+    }
+
+    fn search_batch<'a>(
+        &'a self,
+        queries: &'a [Query],
+        langs: &'a str,
+    ) -> Option<BoxFuture<'a, Result<Vec<Subs>, Error>>> {
+        let batch = self.inner.search_batch(queries, langs)?;
+        // a batch costs one request however many of the queries are in it
+        let cached: Option<Vec<Subs>> = queries
+            .iter()
+            .map(|query| self.cached(query, langs))
+            .collect();
+
+        Some(match cached {
+            Some(found) => {
+                debug!("search results of the batch found in the cache");
+                async move { Ok(found) }.boxed()
+            }
+            None => async move {
+                let found = batch.await?;
+                for (query, subs) in queries.iter().zip(&found) {
+                    self.store(query, langs, subs);
+                }
+
+                Ok(found)
+            }
+            .boxed(),
+        })
+    }
+
+    fn fetch<'a>(&'a self, sub: &'a Sub) -> BoxFuture<'a, Result<Vec<u8>, Error>> {
+        async move {
+            if let Some(data) = self.caches.downloads.get(sub) {
+                debug!("subtitle {} found in the cache", sub.id);
+                return Ok(data);
+            }
+            let data = self.inner.fetch(sub).await?;
+            // the corrupted ones are fetched again
+            if md5_matches(sub, &data) {
+                self.caches.downloads.insert(sub, &data);
+            }
+
+            Ok(data)
+        }
+        .boxed()
+    }
+
+    fn comments<'a>(
+        &'a self,
+        ids: &'a [&'a str],
+    ) -> BoxFuture<'a, Result<BTreeMap<String, Vec<Comment>>, Error>> {
+        self.inner.comments(ids)
+    }
+
+    fn report_wrong<'a>(&'a self, sub: &'a Sub) -> BoxFuture<'a, Result<(), Error>> {
+        self.inner.report_wrong(sub)
+    }
+}
@@ -0,0 +1,104 @@
+//! The configuration file, ~/.config/ostdl/config.toml by default
+//!
+//! It can set the defaults of the command line options, e.g.
+//!
+//! ```toml
+//! langs = "eng,hun"
+//! all = false
+//! output-dir = "/media/subtitles"
+//! credential-command = "pass show opensubtitles"
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::paths::config_dir;
+
+/// The settings in the configuration file, all of them optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub langs: Option<String>,
+    pub all: Option<bool>,
+    pub skip_existing: Option<bool>,
+    pub skip_embedded: Option<String>,
+    pub min_score: Option<f64>,
+    pub sort_by: Option<String>,
+    pub hearing_impaired: Option<String>,
+    pub include_forced: Option<bool>,
+    pub match_fps: Option<String>,
+    pub require_release_match: Option<bool>,
+    pub trusted_only: Option<bool>,
+    pub allow_machine_translated: Option<bool>,
+    pub formats: Option<String>,
+    pub extensions: Option<String>,
+    pub output_dir: Option<PathBuf>,
+    pub mux: Option<bool>,
+    pub remove_muxed: Option<bool>,
+    pub name_template: Option<String>,
+    pub mark_sdh: Option<bool>,
+    pub default_lang: Option<String>,
+    pub utf8: Option<bool>,
+    pub line_endings: Option<String>,
+    pub strip_ads: Option<bool>,
+    pub ad_patterns: Option<Vec<String>>,
+    pub strip_bom: Option<bool>,
+    pub convert: Option<String>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub credential_command: Option<String>,
+    pub user_agent: Option<String>,
+    pub api_url: Option<String>,
+    pub proxy: Option<String>,
+    pub ca_cert: Option<PathBuf>,
+    pub insecure: Option<bool>,
+    pub retries: Option<u32>,
+    pub cache_ttl: Option<u32>,
+    pub no_cache: Option<bool>,
+    pub connect_timeout: Option<u32>,
+    pub timeout: Option<u32>,
+    pub rpc_download: Option<bool>,
+    pub vip_endpoint: Option<bool>,
+    pub api: Option<String>,
+    pub providers: Option<String>,
+    pub provider_cmd: Option<PathBuf>,
+    pub api_key: Option<String>,
+    pub lang_suffix: Option<String>,
+    pub lang_map: Option<String>,
+    pub trakt_user: Option<String>,
+    pub trakt_client_id: Option<String>,
+    pub retry_missing: Option<String>,
+    pub retry_for: Option<String>,
+    pub exec: Option<String>,
+    pub metadata: Option<bool>,
+    pub contribute_hashes: Option<bool>,
+}
+
+impl Config {
+    /// Loads the configuration from the given file, or from the default
+    /// location if none is given. Only the default file may be missing.
+    pub fn load(path: Option<&Path>) -> Result<Config, Error> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match config_dir() {
+                Some(dir) => (dir.join("config.toml"), false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(Config::default())
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        toml::from_str(&text)
+            .map_err(|e| Error::Ost(format!("{}: {}", path.to_string_lossy(), e).into()))
+    }
+}
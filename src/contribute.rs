@@ -0,0 +1,77 @@
+//! Contributing the hashes of the videos found by their names rather than
+//! their hashes with --contribute-hashes, so that the next search by the
+//! hash finds them
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use log::{debug, info, warn};
+
+use crate::api::{self, MovieHash, Session};
+use crate::error::Error;
+use crate::hash::{self, size_and_hash};
+use crate::subtitle::Subs;
+
+/// The IMDb id of the movie the subtitles found for a video are mostly
+/// for, if none of them was found by the hash of the video
+pub fn guess_imdb(subs: &Subs) -> Option<u64> {
+    if subs.iter().any(|sub| !sub.movie_file_id.is_empty()) {
+        return None;
+    }
+
+    let mut counts: HashMap<u64, usize> = HashMap::new();
+    for id in subs.iter().filter_map(|sub| sub.imdb_id) {
+        *counts.entry(id).or_default() += 1;
+    }
+    // the best ranked one of the most common ones
+    let most = counts.values().copied().max()?;
+    subs.iter()
+        .filter_map(|sub| sub.imdb_id)
+        .find(|id| counts[id] == most)
+}
+
+/// Adds the hashes of the videos only found by their names to the
+/// database, with the IMDb ids guessed from the subtitles found. The
+/// failures are only logged.
+pub async fn contribute(files: &[PathBuf], found: &[Result<Subs, Error>], session: &Session) {
+    let mut hashes = Vec::new();
+    for (file, subs) in files.iter().zip(found) {
+        let imdb_id = match subs.as_ref().ok().and_then(guess_imdb) {
+            Some(imdb_id) => imdb_id,
+            None => continue,
+        };
+        let name = file.to_string_lossy();
+        if hash::is_url(&name) {
+            continue;
+        }
+        // too small to have a hash, or gone
+        let (size, hash) = match size_and_hash(file.as_os_str()) {
+            Ok(size_and_hash) => size_and_hash,
+            Err(e) => {
+                debug!("{}: {}", name, e);
+                continue;
+            }
+        };
+        debug!("{}: contributing its hash for tt{:07}", name, imdb_id);
+        hashes.push(MovieHash {
+            hash,
+            size,
+            imdb_id,
+            file_name: file
+                .file_name()
+                .map_or_else(|| name.to_string(), |f| f.to_string_lossy().into_owned()),
+        });
+    }
+    if hashes.is_empty() {
+        return;
+    }
+
+    match api::insert_movie_hashes(&hashes, &session.token).await {
+        Ok(accepted) => info!(
+            "contributed the hashes of {} of {} videos found by their names",
+            accepted.len(),
+            hashes.len()
+        ),
+        Err(e) => warn!("cannot contribute the hashes: {}", e),
+    }
+}
@@ -0,0 +1,368 @@
+//! Converting the downloaded subtitles to another format, e.g. a MicroDVD
+//! .sub file to SRT for the players handling only that. The text is kept
+//! as bytes, in whatever encoding the subtitle came in.
+
+use std::str::{self, FromStr};
+
+use crate::error::Error;
+
+/// How long a MicroDVD subtitle without an end frame is shown
+const DEFAULT_DURATION_MS: u64 = 3000;
+
+/// The byte order mark some subtitles start with
+const BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// The formats the subtitles can be converted to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Srt,
+}
+
+impl FromStr for Format {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Format, Self::Err> {
+        match s {
+            "srt" => Ok(Format::Srt),
+            _ => Err("invalid format to convert to, use srt"),
+        }
+    }
+}
+
+impl Format {
+    /// The extension of the files in the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Srt => "srt",
+        }
+    }
+}
+
+/// Whether the subtitles in the format, as named by the server, can be
+/// converted
+pub fn can_convert(from: &str) -> bool {
+    matches!(from.to_ascii_lowercase().as_str(), "sub" | "ssa" | "ass")
+}
+
+/// Whether the format counts frames instead of time, so the frame rate is
+/// needed to convert it
+pub fn is_frame_based(from: &str) -> bool {
+    from.eq_ignore_ascii_case("sub")
+}
+
+/// A conversion of a downloaded subtitle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conversion {
+    /// The format converted to
+    pub to: Format,
+    /// The frame rate to convert the frames with, unless the subtitle
+    /// tells its own
+    pub fps: Option<f64>,
+}
+
+impl Conversion {
+    /// Converts the subtitle, which is in the format named by the server
+    pub fn apply(&self, from: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let (bom, data) = match data.strip_prefix(BOM) {
+            Some(data) => (BOM, data),
+            None => (&b""[..], data),
+        };
+        let mut cues = match from.to_ascii_lowercase().as_str() {
+            "sub" => parse_microdvd(data, self.fps)?,
+            "ssa" | "ass" => parse_ssa(data),
+            _ => {
+                return Err(Error::Convert(
+                    format!("cannot convert {} subtitles", from).into(),
+                ))
+            }
+        };
+        if cues.is_empty() {
+            return Err(Error::Convert(
+                format!("no subtitles found in the {} file", from).into(),
+            ));
+        }
+        cues.sort_by_key(|cue| cue.start);
+
+        let eol: &[u8] = if data.windows(2).any(|w| w == b"\r\n") {
+            b"\r\n"
+        } else {
+            b"\n"
+        };
+        let mut out = bom.to_vec();
+        match self.to {
+            Format::Srt => write_srt(&mut out, &cues, eol),
+        }
+        Ok(out)
+    }
+}
+
+/// A subtitle shown from the start until the end, in milliseconds. The
+/// lines of the text are separated by \n.
+#[derive(Debug)]
+struct Cue {
+    start: u64,
+    end: u64,
+    text: Vec<u8>,
+}
+
+/// The lines of the data, without the line endings
+fn lines(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    data.split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+}
+
+/// Parses a number written in ASCII
+fn number<T: FromStr>(bytes: &[u8]) -> Option<T> {
+    str::from_utf8(bytes).ok()?.trim().parse().ok()
+}
+
+/// Splits `{value}rest` into the value and the rest
+fn braced(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    let line = line.strip_prefix(b"{")?;
+    let end = line.iter().position(|&b| b == b'}')?;
+    Some((&line[..end], &line[end + 1..]))
+}
+
+/// Parses a MicroDVD subtitle, e.g. `{100}{150}First line|Second line`,
+/// timed in frames of the frame rate given in its first line, e.g.
+/// `{1}{1}23.976`, or else the one given
+fn parse_microdvd(data: &[u8], fps: Option<f64>) -> Result<Vec<Cue>, Error> {
+    let mut frames = Vec::new();
+    let mut own_fps = None;
+
+    for line in lines(data) {
+        let Some((start, rest)) = braced(line.trim_ascii_start()) else {
+            continue;
+        };
+        let Some((end, text)) = braced(rest) else {
+            continue;
+        };
+        let Some(start) = number::<u64>(start) else {
+            continue;
+        };
+        let end = number::<u64>(end);
+        if frames.is_empty() && own_fps.is_none() && start <= 1 && end.is_some_and(|e| e <= 1) {
+            if let Some(fps) = number::<f64>(text).filter(|f| f.is_finite() && *f > 0f64) {
+                own_fps = Some(fps);
+                continue;
+            }
+        }
+        frames.push((start, end, microdvd_text(text)));
+    }
+
+    let fps = match own_fps.or(fps) {
+        Some(fps) => fps,
+        None if frames.is_empty() => return Ok(Vec::new()),
+        None => {
+            return Err(Error::Convert(
+                "the frame rate to convert the MicroDVD subtitle with is unknown".into(),
+            ))
+        }
+    };
+    let ms = |frame: u64| (frame as f64 * 1000f64 / fps).round() as u64;
+
+    Ok(frames
+        .into_iter()
+        .filter(|(_, _, text)| !text.is_empty())
+        .map(|(start, end, text)| Cue {
+            start: ms(start),
+            end: end.map_or(ms(start) + DEFAULT_DURATION_MS, ms),
+            text,
+        })
+        .collect())
+}
+
+/// The text of a MicroDVD subtitle: the lines are separated by |, the
+/// formatting is in braces, e.g. {y:i} for italics. Lines starting with /
+/// are in italics too.
+fn microdvd_text(text: &[u8]) -> Vec<u8> {
+    let all_italic = contains_tag(text, b"Y:i");
+    let mut out = Vec::new();
+
+    for (i, line) in text.split(|&b| b == b'|').enumerate() {
+        let italic = all_italic || contains_tag(line, b"y:i") || line.starts_with(b"/");
+        let line = line.strip_prefix(b"/").unwrap_or(line);
+        let mut plain = Vec::new();
+        let mut rest = line;
+        while let Some(start) = rest.iter().position(|&b| b == b'{') {
+            plain.extend_from_slice(&rest[..start]);
+            rest = match rest[start..].iter().position(|&b| b == b'}') {
+                Some(end) => &rest[start + end + 1..],
+                None => &[],
+            };
+        }
+        plain.extend_from_slice(rest);
+
+        if i > 0 {
+            out.push(b'\n');
+        }
+        if italic && !plain.is_empty() {
+            out.extend_from_slice(b"<i>");
+            out.extend_from_slice(&plain);
+            out.extend_from_slice(b"</i>");
+        } else {
+            out.extend_from_slice(&plain);
+        }
+    }
+
+    out.trim_ascii().to_vec()
+}
+
+/// Whether the MicroDVD text has the formatting tag, e.g. {y:i} or
+/// {y:i,b}
+fn contains_tag(text: &[u8], tag: &[u8]) -> bool {
+    text.windows(tag.len() + 1)
+        .any(|w| w[0] == b'{' && &w[1..] == tag)
+}
+
+/// Parses an SSA or ASS subtitle, taking the Dialogue lines of the Events
+/// section, in the order of its Format line
+fn parse_ssa(data: &[u8]) -> Vec<Cue> {
+    // the default format of both, SSA names the first field Marked
+    let mut fields: Vec<String> = [
+        "layer", "start", "end", "style", "name", "marginl", "marginr", "marginv", "effect", "text",
+    ]
+    .iter()
+    .map(|f| f.to_string())
+    .collect();
+    let mut in_events = false;
+    let mut cues = Vec::new();
+
+    for line in lines(data) {
+        let line = line.trim_ascii();
+        if line.starts_with(b"[") {
+            in_events = line.eq_ignore_ascii_case(b"[events]");
+            continue;
+        }
+        if !in_events {
+            continue;
+        }
+        if let Some(format) = strip_prefix_ignore_case(line, b"format:") {
+            fields = String::from_utf8_lossy(format)
+                .split(',')
+                .map(|f| f.trim().to_lowercase())
+                .collect();
+        } else if let Some(dialogue) = strip_prefix_ignore_case(line, b"dialogue:") {
+            let values: Vec<&[u8]> = dialogue.splitn(fields.len(), |&b| b == b',').collect();
+            let field = |name: &str| {
+                fields
+                    .iter()
+                    .position(|f| f == name)
+                    .and_then(|i| values.get(i))
+                    .copied()
+            };
+            let (Some(start), Some(end), Some(text)) = (
+                field("start").and_then(ssa_time),
+                field("end").and_then(ssa_time),
+                field("text"),
+            ) else {
+                continue;
+            };
+            let text = ssa_text(text);
+            if !text.is_empty() {
+                cues.push(Cue { start, end, text });
+            }
+        }
+    }
+
+    cues
+}
+
+fn strip_prefix_ignore_case<'a>(line: &'a [u8], prefix: &[u8]) -> Option<&'a [u8]> {
+    (line.len() >= prefix.len() && line[..prefix.len()].eq_ignore_ascii_case(prefix))
+        .then(|| line[prefix.len()..].trim_ascii_start())
+}
+
+/// Parses an SSA time, e.g. 0:01:02.50, into milliseconds
+fn ssa_time(time: &[u8]) -> Option<u64> {
+    let time = str::from_utf8(time).ok()?.trim();
+    let mut parts = time.splitn(3, ':');
+    let hours: u64 = parts.next()?.parse().ok()?;
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+
+    Some((hours * 3600 + minutes * 60) * 1000 + (seconds * 1000f64).round() as u64)
+}
+
+/// The text of an SSA subtitle: \N breaks the line, \h is a hard space,
+/// the overrides are in braces, e.g. {\i1} starts italics. Only the
+/// italics and bold are kept.
+fn ssa_text(text: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut open: Vec<&[u8]> = Vec::new();
+    let mut i = 0;
+
+    while i < text.len() {
+        match text[i] {
+            b'{' => {
+                let end = text[i..]
+                    .iter()
+                    .position(|&b| b == b'}')
+                    .map_or(text.len(), |end| i + end);
+                for tag in text[i + 1..end].split(|&b| b == b'\\') {
+                    let (name, on): (&[u8], bool) = match tag {
+                        b"i1" => (b"i", true),
+                        b"i0" => (b"i", false),
+                        b"b1" => (b"b", true),
+                        b"b0" => (b"b", false),
+                        _ => continue,
+                    };
+                    let is_open = open.contains(&name);
+                    if on && !is_open {
+                        out.extend_from_slice(&[b"<", name, b">"].concat());
+                        open.push(name);
+                    } else if !on && is_open {
+                        out.extend_from_slice(&[b"</", name, b">"].concat());
+                        open.retain(|&t| t != name);
+                    }
+                }
+                i = end + 1;
+            }
+            b'\\' if matches!(text.get(i + 1), Some(b'N' | b'n')) => {
+                out.push(b'\n');
+                i += 2;
+            }
+            b'\\' if text.get(i + 1) == Some(&b'h') => {
+                out.push(b' ');
+                i += 2;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    for name in open.iter().rev() {
+        out.extend_from_slice(&[b"</", *name, b">"].concat());
+    }
+
+    out.trim_ascii().to_vec()
+}
+
+/// Formats the time in milliseconds as SRT does, e.g. 00:01:02,500
+fn srt_time(ms: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Writes the cues as SRT, with the line ending given
+fn write_srt(out: &mut Vec<u8>, cues: &[Cue], eol: &[u8]) {
+    for (i, cue) in cues.iter().enumerate() {
+        out.extend_from_slice(format!("{}", i + 1).as_bytes());
+        out.extend_from_slice(eol);
+        out.extend_from_slice(
+            format!("{} --> {}", srt_time(cue.start), srt_time(cue.end)).as_bytes(),
+        );
+        out.extend_from_slice(eol);
+        for line in cue.text.split(|&b| b == b'\n') {
+            out.extend_from_slice(line);
+            out.extend_from_slice(eol);
+        }
+        out.extend_from_slice(eol);
+    }
+}
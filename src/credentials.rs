@@ -0,0 +1,44 @@
+//! Obtaining the login credentials from an external program
+
+use std::process::Command;
+
+use crate::api::Credentials;
+use crate::error::Error;
+
+/// Runs the command through the shell and parses its output.
+///
+/// The output follows the `pass` convention: the first line is the
+/// password, the username is given in a later `login:`, `username:` or
+/// `user:` line.
+pub fn from_command(cmd: &str) -> Result<Credentials, Error> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").arg("/C").arg(cmd).output()?
+    } else {
+        Command::new("sh").arg("-c").arg(cmd).output()?
+    };
+
+    if !output.status.success() {
+        return Err(Error::Ost(
+            format!("credential command failed: {}", output.status).into(),
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    let password = lines.next().unwrap_or("").to_string();
+
+    let username = lines
+        .filter_map(|l| {
+            let mut kv = l.splitn(2, ':');
+            let key = kv.next()?.trim().to_lowercase();
+            let value = kv.next()?.trim();
+            match key.as_str() {
+                "login" | "username" | "user" => Some(value.to_string()),
+                _ => None,
+            }
+        })
+        .next()
+        .ok_or("credential command printed no username")?;
+
+    Ok(Credentials { username, password })
+}
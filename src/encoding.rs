@@ -0,0 +1,75 @@
+//! Transcoding the downloaded subtitles to UTF-8, as many of them come in
+//! the legacy encoding of their language, e.g. cp1250
+
+use std::borrow::Cow;
+use std::str;
+
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use encoding_rs::{Encoding, UTF_8};
+use log::debug;
+
+use crate::lang;
+
+/// The UTF-8 byte order mark
+const BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// The top level domain of the country of the languages whose two letter
+/// code is not the same, which the guess of the encoding is based on
+const TLDS: &[(&str, &str)] = &[
+    ("be", "by"),
+    ("cs", "cz"),
+    ("da", "dk"),
+    ("el", "gr"),
+    ("et", "ee"),
+    ("fa", "ir"),
+    ("he", "il"),
+    ("ja", "jp"),
+    ("ko", "kr"),
+    ("sr", "rs"),
+    ("sv", "se"),
+    ("uk", "ua"),
+    ("vi", "vn"),
+    ("zh", "cn"),
+];
+
+/// The top level domain of the country where the language is spoken, by
+/// its id
+fn tld(lang: &str) -> Option<&'static str> {
+    let alpha2 = lang::find(lang)?.alpha2;
+    let tld = TLDS
+        .iter()
+        .find(|(code, _)| *code == alpha2)
+        .map_or(alpha2, |(_, tld)| tld);
+    Some(tld)
+}
+
+/// Transcodes the subtitle to UTF-8 from the encoding declared by the
+/// server, e.g. CP1250, or else from the one guessed from the content and
+/// the language id. The declared encoding and the language may be empty if
+/// unknown. Valid UTF-8 is left as it is.
+pub fn to_utf8<'a>(data: &'a [u8], declared: &str, lang: &str) -> Cow<'a, [u8]> {
+    if str::from_utf8(data.strip_prefix(BOM).unwrap_or(data)).is_ok() {
+        return Cow::Borrowed(data);
+    }
+
+    // the server declares UTF-8 and ASCII for anything, then it is a guess
+    let declared = Encoding::for_label(declared.trim().as_bytes())
+        .filter(|&e| e != UTF_8 && !declared.to_lowercase().contains("ascii"));
+    let encoding = Encoding::for_bom(data)
+        .map(|(encoding, _)| encoding)
+        .or(declared)
+        .unwrap_or_else(|| {
+            let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+            detector.feed(data, true);
+            detector.guess(tld(lang).map(str::as_bytes), Utf8Detection::Deny)
+        });
+
+    let (text, used, had_errors) = encoding.decode(data);
+    debug!(
+        "transcoding the subtitle from {} to UTF-8{}",
+        used.name(),
+        if had_errors { ", with errors" } else { "" }
+    );
+
+    Cow::Owned(text.into_owned().into_bytes())
+}
@@ -1,23 +1,220 @@
 use std::borrow::Cow;
 use std::borrow::Cow::Borrowed;
-use std::io;
+use std::path::{Path, PathBuf};
+use std::{error, fmt, io};
 
 use xmlrpc::{Error as RequestError, Fault};
+use zip::result::ZipError;
 
 /// A commonly used Error
-pub(crate) const E_INV_RESP: Error = Error::Ost(Borrowed("invalid xml-rpc response"));
+pub const E_INV_RESP: Error = Error::Ost(Borrowed("invalid xml-rpc response"));
+
+/// The same for the REST API
+pub const E_INV_REST_RESP: Error = Error::Ost(Borrowed("invalid REST API response"));
+
+/// The same for the Trakt API
+pub const E_INV_TRAKT_RESP: Error = Error::Ost(Borrowed("invalid Trakt API response"));
 
 /// All the errors that can occur
 #[derive(Debug)]
-pub(crate) enum Error {
+pub enum Error {
+    /// Local file I/O failed
     Io(io::Error),
+    /// The API returned an error or something unexpected
     Ost(Cow<'static, str>),
+    /// The XML-RPC request failed
     XmlRpcRequest(RequestError),
+    /// The XML-RPC server returned a fault
     XmlRpcFault(Fault),
+    /// An HTTP request failed
     Reqwest(reqwest::Error),
+    /// Writing the zip archive failed
+    Zip(ZipError),
+    /// Converting the subtitle to another format failed
+    Convert(Cow<'static, str>),
+    /// What was downloaded is not a subtitle in the expected format
+    Invalid(Cow<'static, str>),
+    /// Decompressing the downloaded subtitle failed
+    Decompress(io::Error),
+    /// No more subtitles can be downloaded until the quota resets
+    Quota(Cow<'static, str>),
+    /// The server refused the credentials or the session
+    Auth(Cow<'static, str>),
+    /// One of the above, with what was being done when it occurred
+    Context(Box<Context>),
+}
+
+/// What was being done with a file when an error occurred
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Hashing the video
+    Hash,
+    /// Searching for the subtitles of the video
+    Search,
+    /// Downloading a subtitle
+    Download,
+    /// Writing a subtitle
+    Write,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Operation::Hash => "hashing",
+            Operation::Search => "searching for",
+            Operation::Download => "downloading",
+            Operation::Write => "writing",
+        })
+    }
+}
+
+/// An error with the context it occurred in, as much as is known: e.g.
+/// `searching for movie.mkv (https://api.opensubtitles.org/xml-rpc):
+/// invalid xml-rpc response`
+#[derive(Debug)]
+pub struct Context {
+    /// What was being done
+    pub operation: Option<Operation>,
+    /// The file it was done with: the video hashed or searched for, or the
+    /// subtitle downloaded or written
+    pub file: Option<PathBuf>,
+    /// The URL requested
+    pub url: Option<String>,
+    pub error: Error,
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(operation) = self.operation {
+            parts.push(operation.to_string());
+        }
+        if let Some(ref file) = self.file {
+            parts.push(file.to_string_lossy().into_owned());
+        }
+        if let Some(ref url) = self.url {
+            parts.push(if parts.is_empty() {
+                url.clone()
+            } else {
+                format!("({})", url)
+            });
+        }
+        if parts.is_empty() {
+            write!(f, "{}", self.error)
+        } else {
+            write!(f, "{}: {}", parts.join(" "), self.error)
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Ost(ref e) => write!(f, "{}", e),
+            Error::Io(ref e) => write!(f, "{}", e),
+            Error::XmlRpcRequest(ref e) => write!(f, "{}", e),
+            Error::XmlRpcFault(ref e) => write!(f, "{}", e),
+            Error::Reqwest(ref e) => write!(f, "{}", e),
+            Error::Zip(ref e) => write!(f, "{}", e),
+            Error::Convert(ref e) => write!(f, "{}", e),
+            Error::Invalid(ref e) => write!(f, "{}", e),
+            Error::Decompress(ref e) => write!(f, "cannot decompress the subtitle: {}", e),
+            Error::Quota(ref e) => write!(f, "{}", e),
+            Error::Auth(ref e) => write!(f, "{}", e),
+            Error::Context(ref c) => write!(f, "{}", c),
+        }
+    }
+}
+
+// The message of the wrapped errors is already the message of the Error,
+// so their source is its source, as with thiserror's transparent errors.
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(ref e) | Error::Decompress(ref e) => e.source(),
+            Error::XmlRpcRequest(ref e) => e.source(),
+            Error::XmlRpcFault(ref e) => e.source(),
+            Error::Reqwest(ref e) => e.source(),
+            Error::Zip(ref e) => e.source(),
+            Error::Context(ref c) => error::Error::source(&c.error),
+            Error::Ost(_)
+            | Error::Convert(_)
+            | Error::Invalid(_)
+            | Error::Quota(_)
+            | Error::Auth(_) => None,
+        }
+    }
 }
 
-/// Converting all sub-errors into Error.
+impl Error {
+    /// The message of the error followed by the ones of its causes, e.g.
+    /// `error sending request for url (...): client error (Connect): tcp
+    /// connect error: Connection refused`
+    pub fn describe(&self) -> String {
+        let mut message = self.to_string();
+        let mut source = error::Error::source(self);
+        while let Some(e) = source {
+            message = format!("{}: {}", message, e);
+            source = e.source();
+        }
+
+        message
+    }
+
+    /// Whether the error is a hard one, reaching the server failed or it
+    /// refused the session, rather than a problem with a single file or
+    /// subtitle, e.g. a broken download
+    pub fn is_remote(&self) -> bool {
+        matches!(
+            self.inner(),
+            Error::XmlRpcRequest(_) | Error::XmlRpcFault(_) | Error::Reqwest(_) | Error::Auth(_)
+        )
+    }
+
+    /// The error without its context
+    pub fn inner(&self) -> &Error {
+        match self {
+            Error::Context(ref c) => c.error.inner(),
+            _ => self,
+        }
+    }
+
+    /// The context of the error, added if it has none yet
+    fn into_context(self) -> Box<Context> {
+        match self {
+            Error::Context(c) => c,
+            error => Box::new(Context {
+                operation: None,
+                file: None,
+                url: None,
+                error,
+            }),
+        }
+    }
+
+    /// Adds what was being done and with which file to the error, unless
+    /// it is known already
+    pub fn context(self, operation: Operation, file: &Path) -> Error {
+        let mut context = self.into_context();
+        context.operation.get_or_insert(operation);
+        context.file.get_or_insert_with(|| file.to_path_buf());
+        Error::Context(context)
+    }
+
+    /// Adds the URL requested to the error, unless it is known already.
+    /// The URL is then left out of the message of the HTTP errors.
+    pub fn at(self, url: &str) -> Error {
+        let mut context = self.into_context();
+        context.error = match context.error {
+            Error::Reqwest(e) => Error::Reqwest(e.without_url()),
+            error => error,
+        };
+        context.url.get_or_insert_with(|| url.to_string());
+        Error::Context(context)
+    }
+}
+
+// Converting all sub-errors into Error.
 
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Error {
@@ -49,20 +246,20 @@ impl From<reqwest::Error> for Error {
     }
 }
 
-/// Prints an error to stderr
-pub(crate) fn print_err(err: String) {
-    eprintln!("{}", err);
+impl From<ZipError> for Error {
+    fn from(e: ZipError) -> Error {
+        Error::Zip(e)
+    }
 }
 
-/// If the input is an Error then prints it to stderr
-pub(crate) fn print_if_err<T>(res: &Result<T, Error>) {
+/// Logs an error
+pub fn print_err(err: String) {
+    log::error!("{}", err);
+}
+
+/// If the input is an Error then logs it, with its causes
+pub fn print_if_err<T>(res: &Result<T, Error>) {
     if let Err(ref err) = res {
-        match err {
-            Error::Ost(ref e) => eprintln!("{}", e.to_string()),
-            Error::Io(ref e) => eprintln!("{}", e.to_string()),
-            Error::XmlRpcRequest(ref e) => eprintln!("{}", e.to_string()),
-            Error::XmlRpcFault(ref e) => eprintln!("{}", e.to_string()),
-            Error::Reqwest(ref e) => eprintln!("{}", e.to_string()),
-        }
+        log::error!("{}", err.describe());
     }
 }
@@ -0,0 +1,194 @@
+//! Providers run as external commands, for the sources ostdl doesn't know
+//!
+//! The command is run with `search` or `download` as its argument, reading
+//! a JSON object from stdin. For `search` it is the query:
+//!
+//! ```text
+//! {"langs": ["eng"], "path": "/media/movie.mkv", "size": 1234, "hash": "8e245d9679d31e12",
+//!  "imdb_id": 133093, "query": null, "season": null, "episode": null, "tag": "movie.mkv"}
+//! ```
+//!
+//! the fields not known being null, the hash being the OSDb hash of the
+//! video. It prints a JSON array of the subtitles found:
+//!
+//! ```text
+//! [{"id": "42", "lang": "eng", "format": "srt", "score": 9.5, "url": "https://...",
+//!   "release": "Movie.2019.1080p.WEB-DL", "uploader": "someone", "downloads": 100,
+//!   "hearing_impaired": false, "forced": false}]
+//! ```
+//!
+//! only id and lang being required. For `download` it reads the subtitle
+//! as found, and prints its content, which may be gzipped or zipped. A
+//! non-zero exit status is an error, described by what the command printed
+//! to stderr.
+//!
+//! The subtitles are fetched from the provider that found them, which is
+//! named after the file of the command.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::debug;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::api::Query;
+use crate::error::Error;
+use crate::lang;
+use crate::payload;
+use crate::provider::SubtitleProvider;
+use crate::subtitle::{Sub, Subs};
+
+/// A subtitle as printed by the command
+#[derive(Deserialize)]
+struct Found {
+    id: String,
+    lang: String,
+    #[serde(default)]
+    format: Option<String>,
+    #[serde(default)]
+    score: f64,
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    release: String,
+    #[serde(default)]
+    uploader: String,
+    #[serde(default)]
+    downloads: u64,
+    #[serde(default)]
+    hearing_impaired: bool,
+    #[serde(default)]
+    forced: bool,
+}
+
+/// Runs the command with the argument, writing the input to its stdin and
+/// returning what it printed to stdout
+fn run(program: &Path, arg: &str, input: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let mut child = Command::new(program)
+        .arg(arg)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            io::Error::new(e.kind(), format!("cannot run {}: {}", program.display(), e))
+        })?;
+    // the command may not read all of it
+    let _ = child.stdin.take().expect("stdin is piped").write_all(input);
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!(
+            "{} ({})",
+            stderr.trim(),
+            output.status
+        )));
+    }
+
+    Ok(output.stdout)
+}
+
+/// A provider run as an external command
+#[derive(Debug, Clone)]
+pub struct External {
+    program: PathBuf,
+    name: String,
+}
+
+impl External {
+    /// The provider running the program, named after its file
+    pub fn new(program: &Path) -> External {
+        let name = program
+            .file_name()
+            .map_or_else(|| program.to_string_lossy(), |name| name.to_string_lossy())
+            .into_owned();
+
+        External {
+            program: program.to_path_buf(),
+            name,
+        }
+    }
+
+    /// Runs the command in the background, as it may take long
+    async fn call(&self, arg: &'static str, input: Value) -> Result<Vec<u8>, Error> {
+        let program = self.program.clone();
+        debug!("running {} {}: {}", program.display(), arg, input);
+        let input = input.to_string().into_bytes();
+        let output = tokio::task::spawn_blocking(move || run(&program, arg, &input))
+            .await
+            .map_err(io::Error::other)??;
+
+        Ok(output)
+    }
+}
+
+impl SubtitleProvider for External {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn search<'a>(
+        &'a self,
+        query: &'a Query,
+        langs: &'a str,
+    ) -> BoxFuture<'a, Result<Subs, Error>> {
+        async move {
+            let input = json!({
+                "langs": langs.split(',').collect::<Vec<_>>(),
+                "path": query.video.as_ref().map(|v| v.to_string_lossy()),
+                "size": query.size_hash.map(|(size, _)| size),
+                "hash": query.size_hash.map(|(_, hash)| format!("{:016x}", hash)),
+                "imdb_id": query.imdb_id,
+                "query": query.text,
+                "season": query.season,
+                "episode": query.episode,
+                "tag": query.tag,
+            });
+            let output = self.call("search", input).await?;
+            let found: Vec<Found> = serde_json::from_slice(&output).map_err(|e| {
+                Error::Ost(format!("invalid output of {}: {}", self.name, e).into())
+            })?;
+
+            Ok(found
+                .into_iter()
+                .map(|found| Sub {
+                    lang: lang::resolve(&found.lang).map_or(found.lang, String::from),
+                    id: found.id,
+                    url: found.url,
+                    score: found.score,
+                    format: found.format.unwrap_or_else(|| "srt".into()),
+                    release: found.release,
+                    uploader: found.uploader,
+                    downloads: found.downloads,
+                    hearing_impaired: found.hearing_impaired,
+                    forced: found.forced,
+                    cd: 1,
+                    cds: 1,
+                    provider: self.name.clone(),
+                    ..Sub::default()
+                })
+                .collect())
+        }
+        .boxed()
+    }
+
+    fn fetch<'a>(&'a self, sub: &'a Sub) -> BoxFuture<'a, Result<Vec<u8>, Error>> {
+        async move {
+            let input = json!({
+                "id": sub.id,
+                "lang": sub.lang,
+                "format": sub.format,
+                "url": sub.url,
+            });
+            let output = self.call("download", input).await?;
+
+            payload::unpack(&output, &sub.format)
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,263 @@
+//! C interface, built with `--features capi` (see include/ostdl.h)
+//!
+//! Every fallible function returns an `OstdlStatus`; the message of the
+//! last error on the calling thread is available via `ostdl_last_error`.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, OsStr};
+use std::os::raw::c_char;
+use std::ptr;
+
+use crate::api::{login, Backend, Session, DEFAULT_USER_AGENT};
+use crate::error::Error;
+use crate::hash::size_and_hash;
+use crate::runtime::block_on;
+use crate::subtitle::{download_to_file, find_subtitles, Sub};
+
+/// Status codes returned by the C interface
+#[repr(C)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OstdlStatus {
+    Ok = 0,
+    InvalidArgument = 1,
+    Io = 2,
+    Api = 3,
+    Network = 4,
+    OutOfRange = 5,
+}
+
+/// A logged in session
+pub struct OstdlSession {
+    session: Session,
+}
+
+/// The result of a search; the strings are kept alive by the handle
+pub struct OstdlSearch {
+    subs: Vec<Sub>,
+    langs: Vec<CString>,
+    formats: Vec<CString>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(msg: String) {
+    let msg = CString::new(msg).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+impl From<Error> for OstdlStatus {
+    fn from(e: Error) -> OstdlStatus {
+        let status = match e.inner() {
+            Error::Io(_) | Error::Zip(_) => OstdlStatus::Io,
+            Error::Ost(_)
+            | Error::XmlRpcFault(_)
+            | Error::Convert(_)
+            | Error::Invalid(_)
+            | Error::Decompress(_)
+            | Error::Quota(_)
+            | Error::Auth(_)
+            | Error::Context(_) => OstdlStatus::Api,
+            Error::XmlRpcRequest(_) | Error::Reqwest(_) => OstdlStatus::Network,
+        };
+        set_last_error(e.describe());
+        status
+    }
+}
+
+/// The error for NULL pointer arguments
+fn null_argument() -> OstdlStatus {
+    set_last_error("null argument".into());
+    OstdlStatus::InvalidArgument
+}
+
+/// Converts a C string argument into a &str
+unsafe fn arg_str<'a>(s: *const c_char) -> Result<&'a str, OstdlStatus> {
+    if s.is_null() {
+        return Err(null_argument());
+    }
+    CStr::from_ptr(s).to_str().map_err(|_| {
+        set_last_error("argument is not valid UTF-8".into());
+        OstdlStatus::InvalidArgument
+    })
+}
+
+/// Turns a Result into a status, storing the error message
+fn status<T>(res: Result<T, OstdlStatus>) -> OstdlStatus {
+    match res {
+        Ok(_) => OstdlStatus::Ok,
+        Err(status) => status,
+    }
+}
+
+/// Returns the message of the last error on this thread, or NULL.
+/// The string is valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn ostdl_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
+
+/// Calculates the OSDb size and hash of a file
+///
+/// # Safety
+/// `path` must be a valid C string, `size` and `hash` valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn ostdl_hash(
+    path: *const c_char,
+    size: *mut u64,
+    hash: *mut u64,
+) -> OstdlStatus {
+    status((|| {
+        let path = arg_str(path)?;
+        if size.is_null() || hash.is_null() {
+            return Err(null_argument());
+        }
+        let (s, h) = size_and_hash(OsStr::new(path)).map_err(Error::from)?;
+        *size = s;
+        *hash = h;
+        Ok(())
+    })())
+}
+
+/// Logs into the API, storing the new session into `out`
+///
+/// # Safety
+/// `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn ostdl_session_new(out: *mut *mut OstdlSession) -> OstdlStatus {
+    status((|| {
+        if out.is_null() {
+            return Err(null_argument());
+        }
+        let session = block_on(login(&Backend::XmlRpc, None, DEFAULT_USER_AGENT))?;
+        *out = Box::into_raw(Box::new(OstdlSession { session }));
+        Ok(())
+    })())
+}
+
+/// Frees a session
+///
+/// # Safety
+/// `session` must be NULL or a pointer returned by `ostdl_session_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ostdl_session_free(session: *mut OstdlSession) {
+    if !session.is_null() {
+        drop(Box::from_raw(session));
+    }
+}
+
+/// Searches for the subtitles of a file in the given (comma separated)
+/// languages, storing the result into `out`
+///
+/// # Safety
+/// `session` must be a live session, `path` and `langs` valid C strings
+/// and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn ostdl_search_new(
+    session: *const OstdlSession,
+    path: *const c_char,
+    langs: *const c_char,
+    out: *mut *mut OstdlSearch,
+) -> OstdlStatus {
+    status((|| {
+        if session.is_null() || out.is_null() {
+            return Err(null_argument());
+        }
+        let path = arg_str(path)?;
+        let langs = arg_str(langs)?;
+        let subs = block_on(find_subtitles(OsStr::new(path), langs, &(*session).session))?;
+        let to_c = |s: &str| CString::new(s).unwrap_or_default();
+        let search = OstdlSearch {
+            langs: subs.iter().map(|s| to_c(&s.lang)).collect(),
+            formats: subs.iter().map(|s| to_c(&s.format)).collect(),
+            subs,
+        };
+        *out = Box::into_raw(Box::new(search));
+        Ok(())
+    })())
+}
+
+/// Frees a search result
+///
+/// # Safety
+/// `search` must be NULL or a pointer returned by `ostdl_search_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ostdl_search_free(search: *mut OstdlSearch) {
+    if !search.is_null() {
+        drop(Box::from_raw(search));
+    }
+}
+
+/// Returns the number of subtitles found
+///
+/// # Safety
+/// `search` must be a live search result.
+#[no_mangle]
+pub unsafe extern "C" fn ostdl_search_count(search: *const OstdlSearch) -> usize {
+    search.as_ref().map_or(0, |s| s.subs.len())
+}
+
+/// Returns the language of the idx-th subtitle, or NULL if out of range
+///
+/// # Safety
+/// `search` must be a live search result.
+#[no_mangle]
+pub unsafe extern "C" fn ostdl_search_lang(
+    search: *const OstdlSearch,
+    idx: usize,
+) -> *const c_char {
+    search
+        .as_ref()
+        .and_then(|s| s.langs.get(idx))
+        .map_or(ptr::null(), |s| s.as_ptr())
+}
+
+/// Returns the format of the idx-th subtitle, or NULL if out of range
+///
+/// # Safety
+/// `search` must be a live search result.
+#[no_mangle]
+pub unsafe extern "C" fn ostdl_search_format(
+    search: *const OstdlSearch,
+    idx: usize,
+) -> *const c_char {
+    search
+        .as_ref()
+        .and_then(|s| s.formats.get(idx))
+        .map_or(ptr::null(), |s| s.as_ptr())
+}
+
+/// Returns the score of the idx-th subtitle, or NaN if out of range
+///
+/// # Safety
+/// `search` must be a live search result.
+#[no_mangle]
+pub unsafe extern "C" fn ostdl_search_score(search: *const OstdlSearch, idx: usize) -> f64 {
+    search
+        .as_ref()
+        .and_then(|s| s.subs.get(idx))
+        .map_or(f64::NAN, |s| s.score)
+}
+
+/// Downloads the idx-th subtitle into the given file
+///
+/// # Safety
+/// `search` must be a live search result and `path` a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn ostdl_download(
+    search: *const OstdlSearch,
+    idx: usize,
+    path: *const c_char,
+) -> OstdlStatus {
+    status((|| {
+        let search = search.as_ref().ok_or_else(null_argument)?;
+        let path = arg_str(path)?;
+        let sub = search.subs.get(idx).ok_or_else(|| {
+            set_last_error(format!("no subtitle with index {}", idx));
+            OstdlStatus::OutOfRange
+        })?;
+        block_on(download_to_file(&sub.url, OsStr::new(path)))?;
+        Ok(())
+    })())
+}
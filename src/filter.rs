@@ -0,0 +1,263 @@
+//! Which of the subtitles found are acceptable at all, and which of them
+//! are the best
+
+use std::cmp::Ordering;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::probe;
+use crate::release::Release;
+use crate::subtitle::Sub;
+
+/// How close the frame rates have to be to be the same, e.g. 23.976 and
+/// 23.98
+const FPS_TOLERANCE: f64 = 0.01;
+
+/// What the best subtitles are best at
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SortBy {
+    /// The score given by the server
+    #[default]
+    Score,
+    /// The number of downloads
+    Downloads,
+    /// The rating given by the users
+    Rating,
+    /// The newest first
+    Date,
+}
+
+impl FromStr for SortBy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<SortBy, Self::Err> {
+        match s {
+            "score" => Ok(SortBy::Score),
+            "downloads" => Ok(SortBy::Downloads),
+            "rating" => Ok(SortBy::Rating),
+            "date" => Ok(SortBy::Date),
+            _ => Err("invalid sort order, use score, downloads, rating or date"),
+        }
+    }
+}
+
+/// How a kind of subtitles, e.g. the ones for the hearing impaired, is
+/// treated
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Wanted {
+    /// Never downloaded
+    Excluded,
+    /// Downloaded like any other
+    #[default]
+    Accepted,
+    /// Ranked before the others
+    Preferred,
+    /// The only ones downloaded
+    Required,
+}
+
+impl Wanted {
+    /// Whether a subtitle of the kind, or not of the kind, is accepted
+    fn accepts(self, is_kind: bool) -> bool {
+        match self {
+            Wanted::Excluded => !is_kind,
+            Wanted::Accepted | Wanted::Preferred => true,
+            Wanted::Required => is_kind,
+        }
+    }
+
+    /// Orders a subtitle of the kind and one not, the preferred first
+    fn compare(self, a_is_kind: bool, b_is_kind: bool) -> Ordering {
+        if self == Wanted::Preferred {
+            b_is_kind.cmp(&a_is_kind)
+        } else {
+            Ordering::Equal
+        }
+    }
+}
+
+/// The requirements the subtitles have to meet to be downloaded, and how
+/// they are ranked. The default accepts all of them but the forced and the
+/// machine translated ones, ranked by score.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    /// The lowest score accepted
+    pub min_score: Option<f64>,
+    /// What the best subtitles are best at
+    pub sort_by: SortBy,
+    /// The subtitles for the hearing impaired
+    pub hearing_impaired: Wanted,
+    /// The forced subtitles, translating only the foreign parts. They are
+    /// ranked apart from the full ones, never preferred to them.
+    pub forced: Wanted,
+    /// The subtitles timed for the frame rate of the video
+    pub fps_match: Wanted,
+    /// The frame rate of the video, set per file by `for_file`
+    pub video_fps: Option<f64>,
+    /// The subtitles made for the same release as the video
+    pub release_match: Wanted,
+    /// The release of the video, set per file by `for_file`
+    pub video_release: Release,
+    /// The subtitles uploaded by trusted users
+    pub trusted: Wanted,
+    /// The subtitles translated by a machine, which often outscore the
+    /// ones translated by people
+    pub machine_translated: Wanted,
+    /// The formats accepted, e.g. srt, the preferred first. All of them
+    /// if empty.
+    pub formats: Vec<String>,
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter {
+            min_score: None,
+            sort_by: SortBy::Score,
+            hearing_impaired: Wanted::Accepted,
+            forced: Wanted::Excluded,
+            fps_match: Wanted::Preferred,
+            video_fps: None,
+            release_match: Wanted::Preferred,
+            video_release: Release::default(),
+            trusted: Wanted::Accepted,
+            machine_translated: Wanted::Excluded,
+            formats: Vec::new(),
+        }
+    }
+}
+
+/// Orders two numbers, higher or non-NaN first
+fn descending(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        _ => b.partial_cmp(&a).unwrap(),
+    }
+}
+
+impl Filter {
+    /// The filter for the subtitles found for the video, knowing its
+    /// release, and its frame rate if the frame rates matter and the
+    /// subtitles tell theirs
+    pub fn for_file(&self, fname: &OsStr, subs: &[Sub]) -> Filter {
+        let mut filter = self.clone();
+        let name = Path::new(fname).file_name().unwrap_or(fname);
+        filter.video_release = Release::parse(&name.to_string_lossy());
+        if self.fps_match != Wanted::Accepted
+            && subs.iter().any(|s| s.fps.is_some())
+            && Path::new(fname).exists()
+        {
+            filter.video_fps = probe::fps(fname);
+        }
+        filter
+    }
+
+    /// Whether the subtitle is timed for the frame rate of the video, as
+    /// far as known
+    fn fps_matches(&self, sub: &Sub) -> bool {
+        match (self.video_fps, sub.fps) {
+            (Some(video), Some(sub)) => (video - sub).abs() < FPS_TOLERANCE,
+            _ => true,
+        }
+    }
+
+    /// Whether the subtitle was made for the release of the video, as far
+    /// as known
+    fn release_matches(&self, sub: &Sub) -> bool {
+        self.video_release.matches(&Release::parse(&sub.release))
+    }
+
+    /// The rank of the format of the subtitle among the formats accepted
+    fn format_rank(&self, sub: &Sub) -> Option<usize> {
+        self.formats
+            .iter()
+            .position(|f| f.eq_ignore_ascii_case(&sub.format))
+    }
+
+    /// Whether the subtitle meets the requirements
+    pub fn accepts(&self, sub: &Sub) -> bool {
+        self.min_score.is_none_or(|min| sub.score >= min)
+            && self.hearing_impaired.accepts(sub.hearing_impaired)
+            && self.forced.accepts(sub.forced)
+            && self.fps_match.accepts(self.fps_matches(sub))
+            && self.release_match.accepts(self.release_matches(sub))
+            && self.trusted.accepts(sub.trusted)
+            && self.machine_translated.accepts(sub.machine_translated)
+            && (self.formats.is_empty() || self.format_rank(sub).is_some())
+    }
+
+    /// Orders two subtitles, the better first: the preferred kinds, then
+    /// as sorted by. Ties are broken by the score, then by the format.
+    pub fn compare(&self, a: &Sub, b: &Sub) -> Ordering {
+        let preferred = self
+            .fps_match
+            .compare(self.fps_matches(a), self.fps_matches(b))
+            .then(
+                self.release_match
+                    .compare(self.release_matches(a), self.release_matches(b)),
+            )
+            .then(
+                self.hearing_impaired
+                    .compare(a.hearing_impaired, b.hearing_impaired),
+            );
+        let order = match self.sort_by {
+            SortBy::Score => Ordering::Equal,
+            SortBy::Downloads => b.downloads.cmp(&a.downloads),
+            SortBy::Rating => descending(a.rating, b.rating),
+            // the dates are formatted so that they sort as text
+            SortBy::Date => b.added.cmp(&a.added),
+        };
+        preferred
+            .then(order)
+            .then_with(|| descending(a.score, b.score))
+            .then_with(|| self.format_rank(a).cmp(&self.format_rank(b)))
+    }
+
+    /// Why none of the subtitles found were accepted, naming the
+    /// requirements some of them failed, for the messages
+    pub fn describe(&self, found: &[&Sub]) -> String {
+        let failed = |meets: &dyn Fn(&Sub) -> bool| found.iter().any(|s| !meets(s));
+        let kind = |wanted: Wanted, kind: &str| match wanted {
+            Wanted::Excluded => format!("is not {}", kind),
+            _ => format!("is {}", kind),
+        };
+
+        let mut reqs = Vec::new();
+        if let Some(min) = self.min_score {
+            if failed(&|s| s.score >= min) {
+                reqs.push(format!("scores at least {:.1}", min));
+            }
+        }
+        if failed(&|s| self.hearing_impaired.accepts(s.hearing_impaired)) {
+            reqs.push(kind(self.hearing_impaired, "for the hearing impaired"));
+        }
+        if failed(&|s| self.forced.accepts(s.forced)) {
+            reqs.push(kind(self.forced, "forced"));
+        }
+        if failed(&|s| self.fps_match.accepts(self.fps_matches(s))) {
+            let fps = self.video_fps.unwrap_or_default();
+            reqs.push(format!("is timed for {:.3} fps", fps));
+        }
+        if failed(&|s| self.release_match.accepts(self.release_matches(s))) {
+            let release = &self.video_release;
+            let name = release
+                .group
+                .as_deref()
+                .or(release.source)
+                .unwrap_or_default();
+            reqs.push(format!("is for the {} release", name));
+        }
+        if failed(&|s| self.trusted.accepts(s.trusted)) {
+            reqs.push(kind(self.trusted, "from a trusted uploader"));
+        }
+        if failed(&|s| self.machine_translated.accepts(s.machine_translated)) {
+            reqs.push(kind(self.machine_translated, "machine translated"));
+        }
+        if !self.formats.is_empty() && failed(&|s| self.format_rank(s).is_some()) {
+            reqs.push(format!("is {}", self.formats.join(" or ")));
+        }
+        reqs.join(" and ")
+    }
+}
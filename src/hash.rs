@@ -1,40 +1,316 @@
+use std::convert::TryInto;
 use std::ffi::OsStr;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{self, Read, Seek, SeekFrom};
 use std::num::Wrapping;
-use std::{io, mem};
+
+use percent_encoding::percent_decode_str;
+use reqwest::header::{CONTENT_LENGTH, RANGE};
+use reqwest::{StatusCode, Url};
+use tokio::sync::Semaphore;
+
+use crate::error::Error;
+use crate::net::{client, send};
 
 const CHUNKSIZE: usize = 65536;
 const CHUNKSIZE_U64: u64 = CHUNKSIZE as u64;
 
-/// Calculates the hashes for a block
-fn hash_block(mut file: &File) -> Result<Wrapping<u64>, io::Error> {
-    let mut buf = [0u8; CHUNKSIZE];
+/// The size of the smallest file the server searches by its hash, the two
+/// blocks hashed
+pub const MIN_SIZE: u64 = 2 * CHUNKSIZE_U64;
 
-    file.read_exact(&mut buf)?;
+/// How many local files are hashed at the same time, more would only make
+/// the disks seek back and forth
+const HASH_JOBS: usize = 4;
 
-    let buf_u64: [u64; CHUNKSIZE / 8] = unsafe { mem::transmute(buf) };
+/// Calculates the hash of the next block of 64 KiB read: the wrapping sum
+/// of its little endian u64 values, the part of it past the end of a short
+/// file counting as zeros
+pub fn hash_block<R: Read>(mut reader: R) -> Result<u64, io::Error> {
+    let mut buf = [0u8; CHUNKSIZE];
 
-    let hash = buf_u64
-        .iter()
-        .fold(Wrapping(0), |sum, &i| sum + Wrapping(i));
+    let mut len = 0;
+    while len < CHUNKSIZE {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
 
-    Ok(hash)
+    let hash = buf
+        .chunks_exact(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+        .fold(Wrapping(0), |sum, i| sum + Wrapping(i));
+
+    Ok(hash.0)
 }
 
 // Calculates the file hash using the algo described at
 // http://trac.opensubtitles.org/projects/opensubtitles/wiki/HashSourceCodes
+// Files shorter than a block are hashed as they are, both blocks being the
+// whole file.
+// With the mmap feature the file is mapped into memory, falling back to
+// reading it if that fails.
 pub fn size_and_hash(path: &OsStr) -> Result<(u64, u64), io::Error> {
-    let mut file = File::open(path)?;
-    let c1 = hash_block(&file)?;
-    let fsize = file.seek(SeekFrom::End(0))?;
-    let seekto = if fsize > CHUNKSIZE_U64 {
-        fsize - CHUNKSIZE_U64
-    } else {
-        0
+    let file = File::open(path)?;
+
+    #[cfg(feature = "mmap")]
+    match mapped_size_and_hash(&file) {
+        Ok(size_and_hash) => return Ok(size_and_hash),
+        Err(e) => log::debug!("{}: cannot map, reading: {}", path.to_string_lossy(), e),
+    }
+
+    size_and_hash_of(file)
+}
+
+/// Calculates the size and hash of the file as size_and_hash does, mapped
+/// into memory: only the pages of the two blocks are read, with no seeking
+/// back and forth, which stalls on network filesystems
+#[cfg(feature = "mmap")]
+fn mapped_size_and_hash(file: &File) -> Result<(u64, u64), io::Error> {
+    // the mapping is only read, and for a moment; if the file is truncated
+    // meanwhile the process gets a SIGBUS, as with any mapping
+    let map = unsafe { memmap2::Mmap::map(file)? };
+    let c1 = hash_block(&map[..map.len().min(CHUNKSIZE)])?;
+    let c2 = hash_block(&map[map.len().saturating_sub(CHUNKSIZE)..])?;
+    let fsize = map.len() as u64;
+
+    Ok((fsize, (Wrapping(fsize) + Wrapping(c1) + Wrapping(c2)).0))
+}
+
+/// Calculates the size and hash of the data read, as size_and_hash does
+fn size_and_hash_of<R: Read + Seek>(mut reader: R) -> Result<(u64, u64), io::Error> {
+    let c1 = hash_block(&mut reader)?;
+    let fsize = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(fsize.saturating_sub(CHUNKSIZE_U64)))?;
+    let c2 = hash_block(&mut reader)?;
+
+    Ok((fsize, (Wrapping(fsize) + Wrapping(c1) + Wrapping(c2)).0))
+}
+
+/// Whether the input is the URL of a remote file, e.g. on a media server
+/// sharing the videos over HTTP or WebDAV
+pub fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// The decoded name of the file at the URL, e.g. Some Movie.mkv for
+/// http://server/videos/Some%20Movie.mkv?auth=x
+pub fn url_file_name(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let name = url.path_segments()?.next_back()?;
+    let name = percent_decode_str(name).decode_utf8_lossy();
+
+    (!name.is_empty()).then(|| name.into_owned())
+}
+
+/// Fetches the block of the remote file of the size given starting at the
+/// offset, with a range request
+async fn fetch_block(url: &str, start: u64, size: u64) -> Result<Vec<u8>, Error> {
+    let end = (start + CHUNKSIZE_U64).min(size);
+    if end == start {
+        return Ok(Vec::new());
+    }
+
+    let req = client()
+        .get(url)
+        .header(RANGE, format!("bytes={}-{}", start, end - 1));
+    let res = send(req).await?.error_for_status()?;
+    // a server ignoring the range sends the whole file, which is only
+    // fine if the block is the whole file
+    if res.status() != StatusCode::PARTIAL_CONTENT && (start, end) != (0, size) {
+        return Err("the server does not support range requests".into());
+    }
+
+    let mut block = res.bytes().await?.to_vec();
+    block.truncate(CHUNKSIZE);
+    Ok(block)
+}
+
+/// Calculates the size and hash of the remote file as size_and_hash does,
+/// with a HEAD request for the size and range requests for the blocks,
+/// without downloading the rest of the file
+pub async fn url_size_and_hash(url: &str) -> Result<(u64, u64), Error> {
+    let res = send(client().head(url)).await?.error_for_status()?;
+    let size: u64 = res
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse().ok())
+        .ok_or("the server does not tell the size of the file")?;
+
+    let head = fetch_block(url, 0, size).await?;
+    let tail = fetch_block(url, size.saturating_sub(CHUNKSIZE_U64), size).await?;
+    let c1 = hash_block(&head[..])?;
+    let c2 = hash_block(&tail[..])?;
+
+    Ok((size, (Wrapping(size) + Wrapping(c1) + Wrapping(c2)).0))
+}
+
+/// Calculates the size and hash of the file as size_and_hash does, on the
+/// threads for blocking work, so the requests in progress don't wait on
+/// the disk meanwhile. At most HASH_JOBS files are hashed at a time.
+async fn background_size_and_hash(path: &OsStr) -> Result<(u64, u64), Error> {
+    static SLOTS: Semaphore = Semaphore::const_new(HASH_JOBS);
+    let _slot = SLOTS
+        .acquire()
+        .await
+        .expect("the semaphore is never closed");
+
+    let path = path.to_os_string();
+    let size_and_hash = tokio::task::spawn_blocking(move || size_and_hash(&path))
+        .await
+        .map_err(io::Error::other)??;
+    Ok(size_and_hash)
+}
+
+/// Calculates the size and hash of the file, or of the remote file if it
+/// is a URL, to search its subtitles with. Fails with an InvalidInput
+/// error if the file is smaller than MIN_SIZE, which the server has no
+/// hashes of.
+pub async fn search_hash(path: &OsStr) -> Result<(u64, u64), Error> {
+    let (size, hash) = match path.to_str().filter(|p| is_url(p)) {
+        Some(url) => url_size_and_hash(url).await?,
+        None => background_size_and_hash(path).await?,
     };
-    file.seek(SeekFrom::Start(seekto))?;
-    let c2 = hash_block(&file)?;
+    if size < MIN_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "file too small for hash search",
+        )
+        .into());
+    }
+
+    Ok((size, hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    /// Data of the size given with the bytes given at its start and end
+    fn data(size: usize, head: &[u8], tail: &[u8]) -> Cursor<Vec<u8>> {
+        let mut data = vec![0u8; size];
+        data[..head.len()].copy_from_slice(head);
+        data[size - tail.len()..].copy_from_slice(tail);
+        Cursor::new(data)
+    }
+
+    #[test]
+    fn block_is_little_endian() {
+        let block = data(CHUNKSIZE, &[1, 2, 3, 4, 5, 6, 7, 8], &[]);
+        assert_eq!(hash_block(block).unwrap(), 0x0807_0605_0403_0201);
+    }
+
+    #[test]
+    fn block_sum_wraps() {
+        let block = data(CHUNKSIZE, &[0xff; 16], &[]);
+        assert_eq!(hash_block(block).unwrap(), u64::MAX - 1);
+    }
+
+    #[test]
+    fn short_block_is_padded_with_zeros() {
+        assert_eq!(hash_block(data(3, &[1, 2, 3], &[])).unwrap(), 0x03_0201);
+    }
+
+    #[test]
+    fn zeros_hash_to_the_size() {
+        assert_eq!(
+            size_and_hash_of(data(3 * CHUNKSIZE, &[], &[])).unwrap(),
+            (0x30000, 0x30000)
+        );
+    }
+
+    #[test]
+    fn first_and_last_blocks_are_hashed() {
+        let hashed = data(3 * CHUNKSIZE, &[1], &[0, 0, 0, 0, 0, 0, 0, 2]);
+        assert_eq!(
+            size_and_hash_of(hashed).unwrap(),
+            (0x30000, 0x30000 + 1 + (2 << 56))
+        );
+    }
+
+    #[test]
+    fn short_file_is_hashed_twice() {
+        assert_eq!(size_and_hash_of(data(8, &[1], &[])).unwrap(), (8, 8 + 2));
+    }
+
+    #[test]
+    fn blocks_overlap_below_two_blocks() {
+        let hashed = data(CHUNKSIZE + 8, &[1, 0, 0, 0, 0, 0, 0, 0, 4], &[2]);
+        assert_eq!(
+            size_and_hash_of(hashed).unwrap(),
+            (0x10008, 0x10008 + 1 + 4 + 4 + (2 << 56))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn mapped_is_the_same_as_read() {
+        let path = std::env::temp_dir().join(format!("ostdl-hash-{}", std::process::id()));
+        let data: Vec<u8> = (0..3 * CHUNKSIZE + 5).map(|i| (i * 7) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let mapped = mapped_size_and_hash(&File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mapped, size_and_hash_of(Cursor::new(data)).unwrap());
+    }
+
+    /// A file in the temporary directory, unique to the test
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("ostdl-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn generated_file() {
+        let path = temp_path("generated");
+        let data: Vec<u8> = (0..3 * CHUNKSIZE + 5).map(|i| (i * 7) as u8).collect();
+        std::fs::write(&path, data).unwrap();
+
+        let hashed = size_and_hash(path.as_os_str());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(hashed.unwrap(), (196_613, 0x0040_7fc0_0042_6005));
+    }
+
+    // Files over 4 GiB, like dummy.bin, without the disk space: only the
+    // head and the tail are written, the rest is a hole reading as zeros
+
+    #[test]
+    fn sparse_file_over_4_gib() {
+        let path = temp_path("sparse");
+        let mut file = File::create(&path).unwrap();
+        file.set_len(4_295_032_832).unwrap();
+        file.write_all(b"ostdl-head").unwrap();
+        file.seek(SeekFrom::End(-10)).unwrap();
+        file.write_all(b"ostdl-tail").unwrap();
+        drop(file);
+
+        let hashed = size_and_hash(path.as_os_str());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(hashed.unwrap(), (4_295_032_832, 0x4540_8ee1_91e2_3c44));
+    }
+
+    // The samples published with the algorithm, to be downloaded into the
+    // crate root, see the link at size_and_hash
+
+    #[test]
+    #[ignore = "needs breakdance.avi"]
+    fn breakdance_avi() {
+        assert_eq!(
+            size_and_hash(OsStr::new("breakdance.avi")).unwrap(),
+            (12_909_756, 0x8e24_5d96_79d3_1e12)
+        );
+    }
 
-    Ok((fsize, (Wrapping(fsize) + c1 + c2).0))
+    #[test]
+    #[ignore = "needs dummy.bin"]
+    fn dummy_bin() {
+        assert_eq!(
+            size_and_hash(OsStr::new("dummy.bin")).unwrap(),
+            (4_295_032_832, 0x61f7_751f_c2a7_2bfb)
+        );
+    }
 }
@@ -0,0 +1,95 @@
+//! The record of the downloaded subtitles, used for upgrading them
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::paths::data_dir;
+
+/// A downloaded subtitle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// The subtitle id
+    pub id: String,
+    /// The score it had when downloaded
+    pub score: f64,
+    /// When it was downloaded, in seconds since the epoch
+    pub time: u64,
+}
+
+/// The downloaded subtitles keyed by their absolute path
+#[derive(Debug, Default)]
+pub struct History {
+    path: Option<PathBuf>,
+    entries: BTreeMap<String, Entry>,
+}
+
+/// The key of a subtitle file
+fn key(path: &Path) -> Option<String> {
+    fs::canonicalize(path)
+        .ok()
+        .map(|p| p.to_string_lossy().into_owned())
+}
+
+impl History {
+    /// Loads the history from the data directory, an empty one if it does
+    /// not exist yet
+    pub fn load() -> Result<History, Error> {
+        let path = data_dir()
+            .ok_or("cannot find the data directory")?
+            .join("history.json");
+
+        let entries = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))
+                .map_err(|e| Error::Ost(format!("invalid history file: {}", e).into()))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(History {
+            path: Some(path),
+            entries,
+        })
+    }
+
+    /// Returns the entry of the subtitle file
+    pub fn get(&self, path: &Path) -> Option<&Entry> {
+        key(path).and_then(|k| self.entries.get(&k))
+    }
+
+    /// Records the subtitle just written to the path
+    pub fn record(&mut self, path: &Path, id: &str, score: f64) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        if let Some(k) = key(path) {
+            let entry = Entry {
+                id: id.to_string(),
+                score,
+                time,
+            };
+            self.entries.insert(k, entry);
+        }
+    }
+
+    /// Writes the history back into the data directory
+    pub fn save(&self) -> Result<(), Error> {
+        if let Some(ref path) = self.path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let file = BufWriter::new(File::create(path)?);
+            serde_json::to_writer_pretty(file, &self.entries)
+                .map_err(|e| Error::Ost(format!("cannot write history: {}", e).into()))?;
+        }
+
+        Ok(())
+    }
+}
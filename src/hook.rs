@@ -0,0 +1,107 @@
+//! The command run after each subtitle written, e.g. to have a media
+//! server scan the video again or to send a notification
+//!
+//! The command is run through the shell, with `{video}`, `{subtitle}`,
+//! `{lang}` and `{score}` replaced by the values quoted for the shell. They
+//! are also in the environment as `OSTDL_VIDEO`, `OSTDL_SUBTITLE`,
+//! `OSTDL_LANG` and `OSTDL_SCORE`.
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use log::{debug, warn};
+
+use crate::error::Error;
+use crate::report::Reporter;
+use crate::subtitle::Sub;
+
+/// Quotes the value for the shell running the command
+fn quote(value: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+/// Runs the command for the subtitle written for the video, failing if it
+/// cannot be started or exits with an error
+pub fn run(cmd: &str, video: &Path, path: &Path, sub: &Sub) -> Result<(), Error> {
+    let video = video.to_string_lossy();
+    let path = path.to_string_lossy();
+    let score = format!("{:.1}", sub.score);
+    let line = cmd
+        .replace("{video}", &quote(&video))
+        .replace("{subtitle}", &quote(&path))
+        .replace("{lang}", &quote(&sub.lang))
+        .replace("{score}", &score);
+    debug!("running {}", line);
+
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(&line);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(&line);
+        command
+    };
+    // stdin may be the list of the files
+    let status = command
+        .stdin(Stdio::null())
+        .env("OSTDL_VIDEO", video.as_ref())
+        .env("OSTDL_SUBTITLE", path.as_ref())
+        .env("OSTDL_LANG", &sub.lang)
+        .env("OSTDL_SCORE", &score)
+        .status()?;
+    if !status.success() {
+        return Err(Error::Ost(
+            format!("--exec command failed: {}", status).into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Passes the results on to another reporter, running the command for
+/// each subtitle written. The failures of the command are only logged, the
+/// subtitle is there anyway.
+pub struct Hook<'a> {
+    inner: &'a mut dyn Reporter,
+    cmd: &'a str,
+}
+
+impl<'a> Hook<'a> {
+    pub fn new(inner: &'a mut dyn Reporter, cmd: &'a str) -> Hook<'a> {
+        Hook { inner, cmd }
+    }
+}
+
+impl Reporter for Hook<'_> {
+    fn error(&mut self, file: &Path, error: &Error) {
+        self.inner.error(file, error);
+    }
+
+    fn missing(&mut self, file: &Path, lang: &str, reason: Option<&str>) {
+        self.inner.missing(file, lang, reason);
+    }
+
+    fn kept(&mut self, file: &Path, lang: &str, path: &Path, reason: &str) {
+        self.inner.kept(file, lang, path, reason);
+    }
+
+    fn planned(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        self.inner.planned(file, path, sub);
+    }
+
+    fn saved(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        self.inner.saved(file, path, sub);
+        if let Err(e) = run(self.cmd, file, path, sub) {
+            warn!("{}: {}", path.to_string_lossy(), e);
+        }
+    }
+
+    fn done(&mut self, file: &Path) {
+        self.inner.done(file);
+    }
+}
@@ -0,0 +1,375 @@
+//! The languages known by opensubtitles.org
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::debug;
+
+use crate::api::{self, SubLanguage};
+use crate::error::Error;
+use crate::paths::cache_dir;
+
+/// How long the languages listed by the server are kept in the cache
+const SERVER_LANGUAGES_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A language as identified by opensubtitles.org
+#[derive(Debug)]
+pub struct Language {
+    /// The id used by the API, mostly ISO 639-2/B
+    pub id: &'static str,
+    /// The two letter code, mostly ISO 639-1
+    pub alpha2: &'static str,
+    /// The BCP 47 tag
+    pub bcp47: &'static str,
+    /// The English name
+    pub name: &'static str,
+}
+
+macro_rules! languages {
+    ($(($id:expr, $alpha2:expr, $bcp47:expr, $name:expr)),* $(,)*) => {
+        &[$(Language { id: $id, alpha2: $alpha2, bcp47: $bcp47, name: $name }),*]
+    };
+}
+
+/// All the languages supported by the API
+pub static LANGUAGES: &[Language] = languages![
+    ("afr", "af", "af", "Afrikaans"),
+    ("alb", "sq", "sq", "Albanian"),
+    ("ara", "ar", "ar", "Arabic"),
+    ("arg", "an", "an", "Aragonese"),
+    ("arm", "hy", "hy", "Armenian"),
+    ("ast", "at", "ast", "Asturian"),
+    ("aze", "az", "az", "Azerbaijani"),
+    ("baq", "eu", "eu", "Basque"),
+    ("bel", "be", "be", "Belarusian"),
+    ("ben", "bn", "bn", "Bengali"),
+    ("bos", "bs", "bs", "Bosnian"),
+    ("bre", "br", "br", "Breton"),
+    ("bul", "bg", "bg", "Bulgarian"),
+    ("bur", "my", "my", "Burmese"),
+    ("cat", "ca", "ca", "Catalan"),
+    ("chi", "zh", "zh-Hans", "Chinese (simplified)"),
+    ("zht", "zt", "zh-Hant", "Chinese (traditional)"),
+    ("zhe", "ze", "zh", "Chinese bilingual"),
+    ("hrv", "hr", "hr", "Croatian"),
+    ("cze", "cs", "cs", "Czech"),
+    ("dan", "da", "da", "Danish"),
+    ("dut", "nl", "nl", "Dutch"),
+    ("eng", "en", "en", "English"),
+    ("epo", "eo", "eo", "Esperanto"),
+    ("est", "et", "et", "Estonian"),
+    ("ext", "ex", "ext", "Extremaduran"),
+    ("fin", "fi", "fi", "Finnish"),
+    ("fre", "fr", "fr", "French"),
+    ("geo", "ka", "ka", "Georgian"),
+    ("ger", "de", "de", "German"),
+    ("gla", "gd", "gd", "Gaelic"),
+    ("gle", "ga", "ga", "Irish"),
+    ("glg", "gl", "gl", "Galician"),
+    ("ell", "el", "el", "Greek"),
+    ("heb", "he", "he", "Hebrew"),
+    ("hin", "hi", "hi", "Hindi"),
+    ("hun", "hu", "hu", "Hungarian"),
+    ("ice", "is", "is", "Icelandic"),
+    ("ibo", "ig", "ig", "Igbo"),
+    ("ind", "id", "id", "Indonesian"),
+    ("ita", "it", "it", "Italian"),
+    ("jpn", "ja", "ja", "Japanese"),
+    ("kan", "kn", "kn", "Kannada"),
+    ("kaz", "kk", "kk", "Kazakh"),
+    ("khm", "km", "km", "Khmer"),
+    ("kor", "ko", "ko", "Korean"),
+    ("kur", "ku", "ku", "Kurdish"),
+    ("lav", "lv", "lv", "Latvian"),
+    ("lit", "lt", "lt", "Lithuanian"),
+    ("ltz", "lb", "lb", "Luxembourgish"),
+    ("mac", "mk", "mk", "Macedonian"),
+    ("may", "ms", "ms", "Malay"),
+    ("mal", "ml", "ml", "Malayalam"),
+    ("mni", "ma", "mni", "Manipuri"),
+    ("mon", "mn", "mn", "Mongolian"),
+    ("mne", "me", "cnr", "Montenegrin"),
+    ("nor", "no", "no", "Norwegian"),
+    ("oci", "oc", "oc", "Occitan"),
+    ("per", "fa", "fa", "Persian"),
+    ("pol", "pl", "pl", "Polish"),
+    ("por", "pt", "pt", "Portuguese"),
+    ("pob", "pb", "pt-BR", "Portuguese (Brazil)"),
+    ("rum", "ro", "ro", "Romanian"),
+    ("rus", "ru", "ru", "Russian"),
+    ("scc", "sr", "sr", "Serbian"),
+    ("sin", "si", "si", "Sinhalese"),
+    ("slo", "sk", "sk", "Slovak"),
+    ("slv", "sl", "sl", "Slovenian"),
+    ("som", "so", "so", "Somali"),
+    ("spa", "es", "es", "Spanish"),
+    ("spn", "sp", "es-ES", "Spanish (Europe)"),
+    ("spl", "ea", "es-419", "Spanish (Latin America)"),
+    ("swa", "sw", "sw", "Swahili"),
+    ("swe", "sv", "sv", "Swedish"),
+    ("syr", "sy", "syr", "Syriac"),
+    ("tam", "ta", "ta", "Tamil"),
+    ("tat", "tt", "tt", "Tatar"),
+    ("tel", "te", "te", "Telugu"),
+    ("tgl", "tl", "tl", "Tagalog"),
+    ("tha", "th", "th", "Thai"),
+    ("tur", "tr", "tr", "Turkish"),
+    ("tuk", "tk", "tk", "Turkmen"),
+    ("ukr", "uk", "uk", "Ukrainian"),
+    ("urd", "ur", "ur", "Urdu"),
+    ("vie", "vi", "vi", "Vietnamese"),
+    ("wel", "cy", "cy", "Welsh"),
+];
+
+/// Other codes of the languages, by their API id: the ISO 639-2/T codes
+/// where the API uses the /B ones, and the withdrawn ISO 639-1 ones
+const ALIASES: &[(&str, &str)] = &[
+    ("ces", "cze"),
+    ("cym", "wel"),
+    ("deu", "ger"),
+    ("eus", "baq"),
+    ("fas", "per"),
+    ("fra", "fre"),
+    ("gre", "ell"),
+    ("hye", "arm"),
+    ("in", "ind"),
+    ("isl", "ice"),
+    ("iw", "heb"),
+    ("kat", "geo"),
+    ("mkd", "mac"),
+    ("msa", "may"),
+    ("mya", "bur"),
+    ("nld", "dut"),
+    ("ron", "rum"),
+    ("slk", "slo"),
+    ("sqi", "alb"),
+    ("srp", "scc"),
+    ("zho", "chi"),
+];
+
+/// Finds a language by its API id
+pub fn find(id: &str) -> Option<&'static Language> {
+    LANGUAGES.iter().find(|l| l.id == id)
+}
+
+/// Finds the API id of the language given by its id, two letter code,
+/// BCP 47 tag (e.g. pt-BR or pt_BR), another code of it or its English
+/// name, ignoring the case
+pub fn resolve(code: &str) -> Result<&'static str, Error> {
+    let code = code.trim();
+    let lower = code.to_lowercase().replace('_', "-");
+    LANGUAGES
+        .iter()
+        .find(|l| l.id == lower || l.alpha2 == lower || l.bcp47.to_lowercase() == lower)
+        .map(|l| l.id)
+        .or_else(|| {
+            ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == lower)
+                .map(|(_, id)| *id)
+        })
+        .or_else(|| {
+            LANGUAGES
+                .iter()
+                .find(|l| l.name.to_lowercase() == lower)
+                .map(|l| l.id)
+        })
+        .ok_or_else(|| {
+            Error::Ost(
+                format!(
+                    "unknown language: {}, see ostdl langs for the known ones",
+                    code
+                )
+                .into(),
+            )
+        })
+}
+
+/// Reads the languages kept in the cache file, unless it is older than a
+/// week
+fn read_cached(path: &Path) -> Option<Vec<SubLanguage>> {
+    let age = fs::metadata(path).ok()?.modified().ok()?.elapsed().ok()?;
+    if age >= SERVER_LANGUAGES_TTL {
+        return None;
+    }
+
+    serde_json::from_reader(BufReader::new(File::open(path).ok()?)).ok()
+}
+
+/// Writes the languages into the cache file
+fn write_cached(path: &Path, langs: &[SubLanguage]) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(file, langs)
+        .map_err(|e| Error::Ost(format!("cannot write language cache: {}", e).into()))
+}
+
+/// The languages the server has subtitles in, as listed by it in the last
+/// week, kept in the cache directory
+pub async fn server_languages() -> Result<Vec<SubLanguage>, Error> {
+    let path = cache_dir().map(|dir| dir.join("languages.json"));
+    if let Some(langs) = path.as_deref().and_then(read_cached) {
+        return Ok(langs);
+    }
+
+    let langs = api::get_sub_languages().await?;
+    if let Some(ref path) = path {
+        if let Err(e) = write_cached(path, &langs) {
+            debug!("cannot cache the languages: {}", e);
+        }
+    }
+
+    Ok(langs)
+}
+
+/// Checks that the server has subtitles in all the comma separated
+/// languages, given by their API ids
+pub fn check_known(langs: &str, known: &[SubLanguage]) -> Result<(), Error> {
+    match langs
+        .split(',')
+        .find(|lang| !known.iter().any(|k| k.id.eq_ignore_ascii_case(lang)))
+    {
+        Some(lang) => Err(Error::Ost(
+            format!("the server has no {} subtitles, see ostdl langs", lang).into(),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Resolves the comma separated languages into API ids, see resolve
+pub fn resolve_list(langs: &str) -> Result<String, Error> {
+    let ids = langs
+        .split(',')
+        .filter(|lang| !lang.trim().is_empty())
+        .map(resolve)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(ids.join(","))
+}
+
+/// Maps the API ids to BCP 47 tags, with user overrides on top of the
+/// built-in table
+#[derive(Default, Debug, Clone)]
+pub struct LangTags {
+    overrides: BTreeMap<String, String>,
+}
+
+impl LangTags {
+    /// Parses overrides given as `id=tag,id=tag`, e.g. `scc=sr-Latn`
+    pub fn parse(spec: &str) -> Result<LangTags, Error> {
+        let mut overrides = BTreeMap::new();
+        for pair in spec.split(',').filter(|p| !p.is_empty()) {
+            let mut kv = pair.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(id), Some(tag)) if !id.is_empty() && !tag.is_empty() => {
+                    overrides.insert(id.trim().to_string(), tag.trim().to_string());
+                }
+                _ => {
+                    return Err(Error::Ost(
+                        format!("invalid language mapping: {}", pair).into(),
+                    ))
+                }
+            }
+        }
+
+        Ok(LangTags { overrides })
+    }
+
+    /// Returns the BCP 47 tag of the language,
+    /// unknown languages are kept as they are
+    pub fn tag<'a>(&'a self, id: &'a str) -> &'a str {
+        match self.overrides.get(id) {
+            Some(tag) => tag,
+            None => find(id).map_or(id, |l| l.bcp47),
+        }
+    }
+}
+
+/// How the language is named in the output file names
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum LangSuffix {
+    /// The API id, e.g. `movie.pob.srt`
+    Code,
+    /// The BCP 47 tag, e.g. `movie.pt-BR.srt`
+    Bcp47,
+    /// The two letter ISO 639-1 code, the language of the BCP 47 tag, e.g.
+    /// `movie.pt.srt`
+    Alpha2,
+    /// The English name, without the spaces and parentheses players
+    /// choke on, e.g. `movie.English.srt` and `movie.PortugueseBrazil.srt`
+    Name,
+}
+
+/// The name of the language made safe for file names: its words
+/// capitalized and joined, e.g. PortugueseBrazil for Portuguese (Brazil)
+fn file_name(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars))
+                .into_iter()
+                .flatten()
+        })
+        .collect()
+}
+
+impl LangSuffix {
+    /// Returns the file name suffix for the language,
+    /// unknown languages are kept as they are
+    pub fn apply<'a>(self, id: &'a str, tags: &'a LangTags) -> Cow<'a, str> {
+        match (self, find(id)) {
+            (LangSuffix::Bcp47, _) => tags.tag(id).into(),
+            (LangSuffix::Alpha2, _) => tags.tag(id).split('-').next().unwrap_or(id).into(),
+            (LangSuffix::Name, Some(lang)) => file_name(lang.name).into(),
+            _ => id.into(),
+        }
+    }
+
+    /// Fails if two of the languages, comma separated API ids, get the
+    /// same suffix, e.g. por and pob as alpha2, as their subtitles would
+    /// overwrite each other
+    pub fn check(self, langs: &str, tags: &LangTags) -> Result<(), Error> {
+        let mut seen: BTreeMap<Cow<str>, &str> = BTreeMap::new();
+        for id in langs.split(',').filter(|id| !id.is_empty()) {
+            let suffix = self.apply(id, tags);
+            match seen.insert(suffix.clone(), id) {
+                Some(other) if other != id => {
+                    return Err(Error::Ost(
+                        format!(
+                            "{} and {} would both be named {} in the file names, use another --lang-suffix",
+                            other, id, suffix
+                        )
+                        .into(),
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for LangSuffix {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<LangSuffix, Self::Err> {
+        match s {
+            "code" => Ok(LangSuffix::Code),
+            "bcp47" => Ok(LangSuffix::Bcp47),
+            "alpha2" => Ok(LangSuffix::Alpha2),
+            "name" => Ok(LangSuffix::Name),
+            _ => Err("invalid language suffix style"),
+        }
+    }
+}
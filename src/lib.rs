@@ -0,0 +1,88 @@
+//! ostdl, a downloader for opensubtitles.org
+//!
+//! Besides the `ostdl` command line tool the crate can be used as a
+//! library to calculate the hash of a video, search for its subtitles and
+//! download them. The API is async, running on tokio:
+//!
+//! ```no_run
+//! use std::ffi::OsStr;
+//!
+//! use ostdl::{download, find_subtitles, get_lang, login, Backend, Filter, DEFAULT_USER_AGENT};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), ostdl::Error> {
+//! let session = login(&Backend::XmlRpc, None, DEFAULT_USER_AGENT).await?;
+//! let subs = find_subtitles(OsStr::new("movie.mkv"), "eng", &session).await?;
+//!
+//! if let Some(best) = get_lang(&subs, "eng", &Filter::default()).first() {
+//!     let content = download(best, &session).await?;
+//!     std::fs::write(format!("movie.eng.{}", best.format), content)?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The errors are an [`Error`] implementing `std::error::Error`, so they
+//! can be passed on with `?` as `Box<dyn std::error::Error + Send + Sync>`
+//! or into anyhow.
+
+pub mod api;
+pub mod cache;
+pub mod config;
+pub mod contribute;
+pub mod convert;
+pub mod credentials;
+pub mod encoding;
+pub mod error;
+pub mod external;
+pub mod filter;
+pub mod hash;
+pub mod history;
+pub mod hook;
+pub mod lang;
+pub mod metadata;
+pub mod mux;
+pub mod napiprojekt;
+pub mod net;
+pub mod options;
+pub mod output;
+pub mod paths;
+pub mod payload;
+pub mod postprocess;
+pub mod probe;
+pub mod progress;
+pub mod provider;
+pub mod quota;
+pub mod release;
+pub mod report;
+mod rest;
+pub mod retry;
+pub mod rpc;
+#[cfg(any(feature = "python", feature = "capi"))]
+mod runtime;
+pub mod scan;
+pub mod state;
+pub mod status;
+pub mod style;
+pub mod subtitle;
+pub mod template;
+pub mod timing;
+pub mod tokens;
+pub mod trakt;
+pub mod validate;
+pub mod vote;
+
+pub use crate::api::{login, Backend, Credentials, Query, Session, DEFAULT_USER_AGENT};
+pub use crate::error::Error;
+pub use crate::filter::Filter;
+pub use crate::hash::size_and_hash;
+pub use crate::provider::{OpenSubtitles, SubtitleProvider};
+pub use crate::subtitle::{
+    download, download_to_file, find_subtitles, get_lang, search_subtitles, Sub, Subs,
+};
+
+#[cfg(feature = "capi")]
+pub mod ffi;
+
+#[cfg(feature = "python")]
+mod python;
@@ -1,70 +1,1994 @@
-use clap::{crate_version, App, Arg, ArgMatches};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
-use crate::api::get_token;
-use crate::error::{print_if_err, Error};
-use crate::subtitle::{download_subtitles, Which};
+use clap::{crate_version, App, AppSettings, Arg, ArgMatches, SubCommand};
 
-mod api;
-mod error;
-mod hash;
-mod subtitle;
+use log::{debug, error, info, warn, Level, LevelFilter, Log, Metadata, Record};
+use ostdl::api::{
+    self, download_quota, keep_alive, login_cached, parse_imdb_id, use_vip_endpoint, Heartbeat,
+    KEEP_ALIVE,
+};
+use ostdl::cache::{self, Cached, Caches, DownloadCache, SearchCache};
+use ostdl::config::Config;
+use ostdl::contribute;
+use ostdl::credentials;
+use ostdl::error::{print_err, print_if_err};
+use ostdl::external::External;
+use ostdl::filter::Wanted;
+use ostdl::hash::{is_url, url_size_and_hash};
+use ostdl::history::History;
+use ostdl::hook::Hook;
+use ostdl::lang::{self, LangSuffix, LangTags, LANGUAGES};
+use ostdl::mux::{self, Track};
+use ostdl::net;
+use ostdl::options::Options;
+use ostdl::output::Output;
+use ostdl::paths::cache_dir;
+use ostdl::postprocess::{AdFilter, LineEnding, AD_PATTERNS};
+use ostdl::progress::{self, Progress};
+use ostdl::provider::{Providers, PROVIDERS};
+use ostdl::quota::{self, Quota};
+use ostdl::release::Release;
+use ostdl::report::{self, Outcome, Reporter, Tally};
+use ostdl::retry::{self, Queue, Recorder};
+use ostdl::rpc;
+use ostdl::scan;
+use ostdl::state::{self, State, Tracker};
+use ostdl::status::{self, Sidecar};
+use ostdl::style::{Painter, Style};
+use ostdl::subtitle::{
+    fetch_downloads, plan_downloads, print_comments, save_download, search_files, table_row,
+    Download, Which, SEARCH_BATCH, TABLE_HEADER,
+};
+use ostdl::template::{Field, NameTemplate};
+use ostdl::timing::Timings;
+use ostdl::trakt::Watchlist;
+use ostdl::vote::{self, Ballot};
+use ostdl::{
+    get_lang, login, size_and_hash, Backend, Credentials, Error, Filter, Query, Session,
+    DEFAULT_USER_AGENT,
+};
+
+/// Writes the log messages to stderr, the debugging ones with their level
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // the debugging messages of the libraries used are just noise
+        metadata.level() <= log::max_level()
+            && (metadata.level() <= Level::Info || metadata.target().starts_with("ostdl"))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        progress::clear();
+        let painter = Painter::stderr();
+        match record.level() {
+            Level::Error => eprintln!("{}", painter.paint(Style::Red, record.args())),
+            Level::Warn => eprintln!("{}", painter.paint(Style::Yellow, record.args())),
+            Level::Info => eprintln!("{}", record.args()),
+            level => eprintln!(
+                "{}",
+                painter.paint(Style::Dim, format!("[{}] {}", level, record.args()))
+            ),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sets up logging as verbose as asked for
+fn init_logging(args: &ArgMatches) {
+    let level = if args.is_present("quiet") {
+        LevelFilter::Error
+    } else {
+        match args.occurrences_of("verbose") {
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    };
+
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
 
 /// The real main
-fn real_main() -> Result<(), Error> {
-    let args = parse_arguments();
+async fn real_main() -> Result<Outcome, Error> {
+    let args = app().get_matches();
+    init_logging(args.subcommand().1.unwrap_or(&args));
 
-    let langs = args.value_of("langs").unwrap_or("eng");
+    let res = match args.subcommand() {
+        ("download", Some(sub_args)) => return download(sub_args).await,
+        ("search", Some(sub_args)) => search(sub_args).await,
+        ("hash", Some(sub_args)) => return hash(sub_args).await,
+        ("status", Some(sub_args)) => return status(sub_args),
+        ("langs", Some(_)) => langs().await,
+        ("login", Some(sub_args)) => login_command(sub_args).await,
+        ("vote", Some(sub_args)) => vote_command(sub_args).await,
+        ("report-wrong", Some(sub_args)) => report_wrong_command(sub_args).await,
+        ("comment", Some(sub_args)) => comment_command(sub_args).await,
+        ("check-subs", Some(sub_args)) => check_subs(sub_args).await,
+        ("retry", Some(sub_args)) => return retry_command(sub_args).await,
+        ("rpc", Some(sub_args)) => rpc_command(sub_args).await,
+        // download is the default, for backward compatibility
+        _ => return download(&args).await,
+    };
 
-    let which = if args.is_present("all") {
-        Which::All
+    // the other commands either fail or do all they were asked to
+    res.map(|()| Outcome::Complete)
+}
+
+/// Picks the files to download the subtitles for, listing the subtitles
+/// instead with --list
+async fn download(args: &ArgMatches<'_>) -> Result<Outcome, Error> {
+    if args.is_present("list") {
+        return search(args).await.map(|()| Outcome::Complete);
+    }
+
+    let opts = parse_arguments(args)?;
+
+    let mut files = input_files(args, opts.query.name(), &opts.extensions, false)?;
+
+    if opts.trakt_only && opts.trakt_user.is_none() {
+        return Err("--trakt-only needs a Trakt user, see --trakt-user".into());
+    }
+    if let Some(ref user) = opts.trakt_user {
+        let client_id = opts
+            .trakt_client_id
+            .as_ref()
+            .ok_or("--trakt-user needs a Trakt client id")?;
+        let watchlist = Watchlist::fetch(user, client_id).await?;
+        files = if opts.trakt_only {
+            Box::new(files.filter(move |f| watchlist.matches(f.as_os_str())))
+        } else {
+            // sorting needs all the files up front
+            let mut all: Vec<PathBuf> = files.collect();
+            all.sort_by_key(|f| !watchlist.matches(f.as_os_str()));
+            Box::new(all.into_iter())
+        };
+    }
+
+    // the bar needs the number of files, but the list on stdin may never end
+    let endless = args.value_of_os("files_from") == Some(OsStr::new("-"));
+    download_files(&opts, files, endless).await
+}
+
+/// Downloads the subtitles for the files, returning whether all of them
+/// were found
+async fn download_files(
+    opts: &Options,
+    mut files: Box<dyn Iterator<Item = PathBuf>>,
+    endless: bool,
+) -> Result<Outcome, Error> {
+    let mut state = opts.state.as_deref().map(State::open).transpose()?;
+    if let Some(ref mut state) = state {
+        let processed = state.take_processed();
+        if !processed.is_empty() {
+            info!("skipping the {} files processed before", processed.len());
+        }
+        files = Box::new(files.filter(move |f| !processed.contains(&state::key(f))));
+    }
+
+    let mut session = connect(opts).await?;
+    let mut checked = Instant::now();
+    let mut heartbeat = session.as_ref().map(Heartbeat::start);
+    if let Some(ref session) = session {
+        log_quota(session).await;
+    }
+    // the videos searched for by title are not hashed
+    let contributing = match session {
+        _ if !opts.contribute_hashes || opts.dry_run || opts.query.text.is_some() => false,
+        Some(ref session) if session.authenticated && session.backend == Backend::XmlRpc => true,
+        _ => {
+            warn!("--contribute-hashes needs a registered user of the XML-RPC API");
+            false
+        }
+    };
+
+    let mut output = match opts.archive {
+        Some(_) if opts.dry_run => Output::Nothing,
+        Some(ref path) => Output::archive(path.as_os_str())?,
+        None => {
+            if let (Some(ref dir), false) = (&opts.output_dir, opts.dry_run) {
+                fs::create_dir_all(dir)?;
+            }
+            Output::Files
+        }
+    };
+
+    let mut history = History::load().unwrap_or_else(|e| {
+        warn!("history not available: {}", e);
+        History::default()
+    });
+
+    let mut reporter: Box<dyn Reporter> = if opts.json {
+        Box::new(report::Json::new(opts.lang_tags.clone()))
     } else {
-        Which::Best
+        Box::new(report::Text::new(log::max_level() < LevelFilter::Info))
+    };
+    // the progress is for people watching, and would get in the way of
+    // the questions asked
+    if io::stderr().is_terminal()
+        && log::max_level() >= LevelFilter::Info
+        && opts.which != Which::Select
+    {
+        let total = if endless {
+            None
+        } else {
+            let all: Vec<PathBuf> = files.collect();
+            let total = all.len();
+            files = Box::new(all.into_iter());
+            Some(total)
+        };
+        reporter = Box::new(Progress::new(reporter, total));
+    }
+    if let Some(ref path) = opts.report {
+        reporter = Box::new(report::Csv::new(reporter, File::create(path)?)?);
+    }
+    let mut queue = opts.retry_missing.and_then(|_| {
+        Queue::load()
+            .map_err(|e| warn!("retry queue not available: {}", e))
+            .ok()
+    });
+    let mut recorder;
+    let inner: &mut dyn Reporter = match (opts.retry_missing, queue.as_mut()) {
+        (Some(interval), Some(queue)) => {
+            recorder = Recorder::new(reporter.as_mut(), queue, interval, opts.retry_for);
+            &mut recorder
+        }
+        _ => reporter.as_mut(),
+    };
+    let mut tracker;
+    let inner: &mut dyn Reporter = match (state.as_mut(), opts.dry_run) {
+        (Some(state), false) => {
+            tracker = Tracker::new(inner, state);
+            &mut tracker
+        }
+        _ => inner,
+    };
+    let mut hook;
+    let inner: &mut dyn Reporter = match opts.exec {
+        Some(ref cmd) => {
+            hook = Hook::new(inner, cmd);
+            &mut hook
+        }
+        None => inner,
+    };
+    // the subtitles picked are voted for at the end
+    let mut ballot = None;
+    let inner: &mut dyn Reporter = if opts.which == Which::Select && !opts.dry_run {
+        ballot.insert(Ballot::new(inner))
+    } else {
+        inner
+    };
+    let mut report = Tally::new(inner);
+
+    let caches = caches(opts);
+    let mut total = Timings::default();
+    let mut batch: Vec<PathBuf> = files.by_ref().take(SEARCH_BATCH).collect();
+    let mut timings = vec![Timings::default(); batch.len()];
+    let mut found = {
+        let provider = providers(opts, session.as_ref(), &caches)?;
+        search_files(&batch, &opts.query, &opts.langs, &provider, &mut timings).await
     };
 
-    let token = get_token()?;
+    while !batch.is_empty() {
+        if let (true, Some(ref session)) = (contributing, &session) {
+            contribute::contribute(&batch, &found, session).await;
+        }
+        let provider = providers(opts, session.as_ref(), &caches)?;
+        // the downloads of the whole batch are fetched in parallel, but
+        // written and reported in order
+        let mut downloads = Vec::new();
+        for (i, (fname, subs)) in batch.iter().zip(&found).enumerate() {
+            match subs {
+                Ok(subs) => downloads.extend(
+                    plan_downloads(
+                        i,
+                        fname.as_os_str(),
+                        subs,
+                        opts,
+                        &provider,
+                        &history,
+                        &mut report,
+                    )
+                    .await,
+                ),
+                Err(e) => report.error(fname, e),
+            }
+        }
+
+        if opts.dry_run {
+            for download in downloads.drain(..) {
+                report.planned(&batch[download.file], &download.path, download.sub);
+            }
+        }
+
+        // the next batch is searched for while this one is downloaded
+        let next_batch: Vec<PathBuf> = files.by_ref().take(SEARCH_BATCH).collect();
+        // the token expires when unused for long, e.g. while waiting for
+        // the files listed on stdin
+        drop(provider);
+        if let (Some(ref mut session), true) = (&mut session, checked.elapsed() >= KEEP_ALIVE) {
+            keep_alive(session, opts.credentials.as_ref()).await?;
+            if let Some(ref mut heartbeat) = heartbeat {
+                heartbeat.restart(session);
+            }
+            checked = Instant::now();
+        }
+        let provider = providers(opts, session.as_ref(), &caches)?;
+        let mut next_timings = vec![Timings::default(); next_batch.len()];
+        let (fetched, next_found) = tokio::join!(
+            fetch_downloads(&downloads, &provider, opts),
+            search_files(
+                &next_batch,
+                &opts.query,
+                &opts.langs,
+                &provider,
+                &mut next_timings
+            ),
+        );
+
+        let mut tracks = vec![Vec::new(); batch.len()];
+        // the files some subtitles of which could not be downloaded for the quota
+        let mut exhausted = Vec::new();
+        for (download, (data, time)) in downloads.iter().zip(fetched) {
+            let file_timings = &mut timings[download.file];
+            file_timings.download += time;
+            let res = data.and_then(|data| {
+                save_download(
+                    download,
+                    &data,
+                    opts,
+                    &mut output,
+                    &mut history,
+                    file_timings,
+                )
+            });
+            let fname = &batch[download.file];
+            match res {
+                Ok(()) => {
+                    report.saved(fname, &download.path, download.sub);
+                    if opts.mux {
+                        tracks[download.file].push(mux_track(download, opts));
+                    }
+                }
+                Err(e) => {
+                    if matches!(e.inner(), Error::Quota(_)) && !exhausted.contains(&download.file) {
+                        exhausted.push(download.file);
+                    }
+                    report.error(fname, &e);
+                }
+            }
+        }
+
+        for (fname, file_tracks) in batch.iter().zip(&tracks) {
+            if !file_tracks.is_empty() {
+                if let Err(e) = mux_into(fname, file_tracks, opts.remove_muxed) {
+                    report.error(fname, &e);
+                }
+            }
+        }
+
+        for (fname, file_timings) in batch.iter().zip(timings) {
+            report.done(fname);
+            if opts.timings {
+                file_timings.report(&fname.to_string_lossy());
+            }
+            total += file_timings;
+        }
+
+        if !exhausted.is_empty() {
+            let left: Vec<PathBuf> = exhausted
+                .iter()
+                .map(|&i| batch[i].clone())
+                .chain(next_batch)
+                .chain(files.by_ref())
+                .collect();
+            match save_left(&left) {
+                Ok(path) => error!(
+                    "the download limit is reached, {} files are left, listed in {} for --files-from once it resets",
+                    left.len(),
+                    path.to_string_lossy()
+                ),
+                Err(e) => error!(
+                    "the download limit is reached, cannot list the {} files left: {}",
+                    left.len(),
+                    e
+                ),
+            }
+            break;
+        }
+
+        batch = next_batch;
+        timings = next_timings;
+        found = next_found;
+    }
+    let outcome = report.outcome;
+    let summary = report.summary;
+    let written = ballot.map(|ballot| ballot.written).unwrap_or_default();
+    drop(reporter);
+    if let (Some(queue), false) = (queue, opts.dry_run) {
+        print_if_err(&queue.save());
+    }
+
+    output.finish()?;
+    if !opts.dry_run {
+        history.save()?;
+    }
+    print_if_err(&caches.searches.save());
+
+    match session {
+        Some(ref session) if session.authenticated && session.backend == Backend::XmlRpc => {
+            vote::ask(&written, session).await
+        }
+        _ if !written.is_empty() => debug!("only registered users can vote for the subtitles"),
+        _ => {}
+    }
+    summary.log();
+    if opts.timings {
+        total.report("total");
+    }
+    if let Some(quota) = quota::get() {
+        info!("{} downloads left today", quota.remaining);
+    }
+
+    Ok(outcome)
+}
+
+/// Searches for the languages queued by --retry-missing again for the
+/// files due, with --wait until none are left
+async fn retry_command(args: &ArgMatches<'_>) -> Result<Outcome, Error> {
+    let mut opts = parse_arguments(args)?;
+    opts.retry_missing.get_or_insert(retry::DEFAULT_INTERVAL);
+
+    let mut outcome = Outcome::Complete;
+    loop {
+        let mut queue = Queue::load()?;
+        for file in queue.expire() {
+            warn!(
+                "gave up searching for the subtitles of {}",
+                file.to_string_lossy()
+            );
+        }
+        let due = queue.take_due();
+        if !opts.dry_run {
+            queue.save()?;
+        }
+
+        // the languages missing differ by file
+        for (langs, files) in due {
+            opts.langs = langs;
+            let res = download_files(&opts, Box::new(files.into_iter()), false).await?;
+            outcome = outcome.max(res);
+        }
+
+        if !args.is_present("wait") {
+            break;
+        }
+        match Queue::load()?.next_due() {
+            Some(wait) => {
+                match wait.as_secs() {
+                    secs if secs < 60 => info!("searching again in {} seconds", secs),
+                    secs => info!("searching again in {} minutes", secs.div_ceil(60)),
+                }
+                tokio::time::sleep(wait).await;
+            }
+            None => break,
+        }
+    }
+
+    Ok(outcome)
+}
+
+/// Logs how many subtitles can still be downloaded today, if the server
+/// tells
+async fn log_quota(session: &Session) {
+    match download_quota(session).await {
+        Ok(Some(Quota {
+            remaining,
+            limit: Some(limit),
+        })) => debug!("{} of {} downloads left today", remaining, limit),
+        Ok(Some(quota)) => debug!("{} downloads left today", quota.remaining),
+        Ok(None) => {}
+        Err(e) => warn!("cannot check the download quota: {}", e),
+    }
+}
+
+/// Lists the files left when the download quota ran out in the cache
+/// directory, one per line, to go on with them once it resets
+fn save_left(left: &[PathBuf]) -> Result<PathBuf, Error> {
+    let dir = cache_dir().ok_or("cannot find the cache directory")?;
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("left.txt");
+
+    let mut out = BufWriter::new(File::create(&path)?);
+    for file in left {
+        writeln!(out, "{}", file.to_string_lossy())?;
+    }
+    out.flush()?;
+
+    Ok(path)
+}
+
+/// The track of the saved subtitle to mux into the video
+fn mux_track(download: &Download, opts: &Options) -> Track {
+    let sub = download.sub;
+    Track {
+        path: download.path.clone(),
+        lang: opts.lang_tags.tag(&sub.lang).to_string(),
+        forced: sub.forced,
+        hearing_impaired: sub.hearing_impaired,
+        default: opts.default_lang.as_deref() == Some(sub.lang.as_str()),
+    }
+}
+
+/// Muxes the saved subtitles into the video if it is an MKV file, removing
+/// them afterwards if asked to
+fn mux_into(video: &Path, tracks: &[Track], remove: bool) -> Result<(), Error> {
+    if !mux::can_mux(video) {
+        warn!(
+            "{}: not muxing the subtitles, only MKV videos are supported",
+            video.to_string_lossy()
+        );
+        return Ok(());
+    }
+    mux::mux(video, tracks)?;
+    info!("{}: subtitles muxed into it", video.to_string_lossy());
+    if remove {
+        for track in tracks {
+            fs::remove_file(&track.path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints a table of the subtitles found for the files, without
+/// downloading them
+async fn search(args: &ArgMatches<'_>) -> Result<(), Error> {
+    let opts = parse_arguments(args)?;
+    let session = connect(&opts).await?;
+
+    let caches = caches(&opts);
+    let provider = providers(&opts, session.as_ref(), &caches)?;
+
+    let mut files = input_files(args, opts.query.name(), &opts.extensions, false)?;
+    loop {
+        let batch: Vec<PathBuf> = files.by_ref().take(SEARCH_BATCH).collect();
+        if batch.is_empty() {
+            break;
+        }
+
+        let mut timings = vec![Timings::default(); batch.len()];
+        let found = search_files(&batch, &opts.query, &opts.langs, &provider, &mut timings).await;
+
+        for (fname, subs) in batch.iter().zip(found) {
+            let subs = match subs {
+                Ok(subs) => subs,
+                Err(e) => {
+                    print_err(format!("{}: {}", fname.to_string_lossy(), e));
+                    continue;
+                }
+            };
+            println!("{}", fname.to_string_lossy());
+            println!("{}", TABLE_HEADER);
+            let filter = opts.filter.for_file(fname.as_os_str(), &subs);
+            for lang in opts.langs.split(',') {
+                let lang_subs = get_lang(&subs, lang, &filter);
+                for (i, sub) in lang_subs.iter().enumerate() {
+                    println!("{}", table_row(lang, i + 1, sub));
+                }
+                if opts.with_comments && !lang_subs.is_empty() {
+                    let mut out = io::stdout();
+                    let comments = print_comments(fname, lang, &lang_subs, &provider, &mut out);
+                    print_if_err(&comments.await);
+                }
+            }
+        }
+    }
+    print_if_err(&caches.searches.save());
+
+    Ok(())
+}
+
+/// Prints the OSDb hash and the size of the files, the outcome being
+/// incomplete if some of them could not be hashed
+async fn hash(args: &ArgMatches<'_>) -> Result<Outcome, Error> {
+    let mut outcome = Outcome::Complete;
+    let extensions = args
+        .value_of("ext")
+        .map_or_else(scan::default_extensions, scan::parse_extensions);
+    // the files named are hashed whatever they are, e.g. the samples of
+    // the algorithm, only the directories are searched for videos
+    for fname in input_files(args, None, &extensions, true)? {
+        let size_and_hash = match fname.to_str().filter(|f| is_url(f)) {
+            Some(url) => url_size_and_hash(url).await,
+            None => size_and_hash(fname.as_os_str()).map_err(Error::from),
+        };
+        match size_and_hash {
+            Ok((size, hash)) => println!("{:016x}  {}  {}", hash, size, fname.to_string_lossy()),
+            Err(e) => {
+                print_err(format!("{}: {}", fname.to_string_lossy(), e));
+                outcome = Outcome::Incomplete;
+            }
+        }
+    }
+
+    Ok(outcome)
+}
+
+/// Prints which of the languages the videos have subtitles in next to
+/// them, without asking the servers
+fn status(args: &ArgMatches<'_>) -> Result<Outcome, Error> {
+    let config = Config::load(args.value_of_os("config").map(Path::new))?;
+    let langs = lang::resolve_list(
+        args.value_of("langs")
+            .or(config.langs.as_deref())
+            .unwrap_or("eng"),
+    )?;
+    let suffix: LangSuffix = config.lang_suffix.as_deref().unwrap_or("code").parse()?;
+    let tags = LangTags::parse(config.lang_map.as_deref().unwrap_or(""))?;
+    let output_dir = args
+        .value_of_os("output_dir")
+        .map(PathBuf::from)
+        .or(config.output_dir);
+    let extensions = args
+        .value_of("ext")
+        .or(config.extensions.as_deref())
+        .map_or_else(scan::default_extensions, scan::parse_extensions);
+
+    let painter = Painter::stdout();
+    let mut outcome = Outcome::Complete;
+    for video in input_files(args, None, &extensions, false)? {
+        let sidecars = status::sidecars(&video, output_dir.as_deref());
+        println!("{}", painter.paint(Style::Bold, video.to_string_lossy()));
+        for lang in langs.split(',') {
+            let label = painter.paint(Style::Cyan, format!("{:<4}", lang));
+            let found: Vec<&Sidecar> = sidecars
+                .iter()
+                .filter(|sidecar| sidecar.is_in(lang, suffix, &tags))
+                .collect();
+            if !found.is_empty() {
+                let paths: Vec<_> = found.iter().map(|s| s.path.to_string_lossy()).collect();
+                let status = painter.paint(Style::Green, "     ok");
+                println!("  {} {} {}", label, status, paths.join(", "));
+                continue;
+            }
+
+            outcome = Outcome::Incomplete;
+            let mut others: Vec<&str> = sidecars.iter().filter_map(Sidecar::lang).collect();
+            others.sort_unstable();
+            others.dedup();
+            let unknown = sidecars.iter().filter(|s| s.lang().is_none()).count();
+            let text = match (others.is_empty(), unknown) {
+                (true, 0) => "no subtitles".to_string(),
+                (true, _) => format!("only {} without a language", unknown),
+                (false, 0) => format!("only in {}", others.join(", ")),
+                (false, _) => format!(
+                    "only in {} and {} without a language",
+                    others.join(", "),
+                    unknown
+                ),
+            };
+            let status = painter.paint(Style::Yellow, "missing");
+            println!("  {} {} {}", label, status, painter.paint(Style::Dim, text));
+        }
+    }
+
+    Ok(outcome)
+}
+
+/// Prints the languages the server has subtitles in, or the built-in list
+/// of them if the server cannot be asked
+async fn langs() -> Result<(), Error> {
+    match lang::server_languages().await {
+        Ok(langs) => {
+            for lang in langs {
+                println!("{}  {}  {}", lang.id, lang.iso639, lang.name);
+            }
+        }
+        Err(e) => {
+            warn!("cannot list the languages of the server: {}", e);
+            for lang in LANGUAGES {
+                println!("{}  {}  {}", lang.id, lang.alpha2, lang.name);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Logs in, printing the token to use in other programs
+async fn login_command(args: &ArgMatches<'_>) -> Result<(), Error> {
+    let opts = parse_arguments(args)?;
+    net::configure(opts.net.clone());
+    let session = login(&opts.backend, opts.credentials.as_ref(), &opts.user_agent).await?;
+
+    if !session.authenticated {
+        warn!("logged in anonymously");
+    }
+    println!("{}", session.token);
+
+    Ok(())
+}
+
+/// Serves JSON-RPC requests on stdin/stdout, logging in as configured
+async fn rpc_command(args: &ArgMatches<'_>) -> Result<(), Error> {
+    let opts = parse_arguments(args)?;
+    net::configure(opts.net.clone());
+    let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+
+    rpc::serve(stdin, tokio::io::stdout(), &opts).await
+}
+
+/// Logs in to the XML-RPC API, which the calls other than searching and
+/// downloading are only in, failing unless the user is registered if the
+/// call is for registered users
+async fn xmlrpc_session(opts: &Options, action: &str, registered: bool) -> Result<Session, Error> {
+    net::configure(opts.net.clone());
+    if opts.backend != Backend::XmlRpc {
+        return Err(Error::Ost(
+            format!("{} needs the XML-RPC API", action).into(),
+        ));
+    }
+    if registered && opts.credentials.is_none() {
+        return Err(Error::Ost(
+            format!("{} needs a registered user, see --user", action).into(),
+        ));
+    }
+
+    login_cached(&opts.backend, opts.credentials.as_ref(), &opts.user_agent).await
+}
+
+/// Votes for the subtitle, printing its new rating
+async fn vote_command(args: &ArgMatches<'_>) -> Result<(), Error> {
+    let opts = parse_arguments(args)?;
+    let id = args.value_of("SUBTITLE_ID").unwrap_or_default();
+    let score = vote::parse_score(args.value_of("SCORE").unwrap_or_default())?;
+    let session = xmlrpc_session(&opts, "voting", true).await?;
+
+    let rating = api::vote(id, score, &session.token).await?;
+    println!("{} {:.1} ({} votes)", id, rating.score, rating.votes);
+
+    Ok(())
+}
+
+/// Reports the match of the hash of a video with a subtitle as wrong
+async fn report_wrong_command(args: &ArgMatches<'_>) -> Result<(), Error> {
+    let opts = parse_arguments(args)?;
+    let id = args.value_of("MOVIE_FILE_ID").unwrap_or_default();
+    let session = xmlrpc_session(&opts, "reporting", false).await?;
+
+    api::report_wrong_hash(id, &session.token).await?;
+    info!("reported the match {} as wrong", id);
+
+    Ok(())
+}
+
+/// Comments on the subtitle
+async fn comment_command(args: &ArgMatches<'_>) -> Result<(), Error> {
+    let opts = parse_arguments(args)?;
+    let id = args.value_of("SUBTITLE_ID").unwrap_or_default();
+    let text = args.value_of("TEXT").unwrap_or_default().trim();
+    if text.is_empty() {
+        return Err("the comment is empty".into());
+    }
+    let session = xmlrpc_session(&opts, "commenting", true).await?;
+
+    api::add_comment(id, text, args.is_present("bad"), &session.token).await?;
+    info!("commented on subtitle {}", id);
+
+    Ok(())
+}
+
+/// Prints which of the subtitle files the server has already, by the MD5
+/// hashes of their content
+async fn check_subs(args: &ArgMatches<'_>) -> Result<(), Error> {
+    let opts = parse_arguments(args)?;
+    let files: Vec<PathBuf> = args
+        .values_of_os("SUBTITLES")
+        .into_iter()
+        .flatten()
+        .map(PathBuf::from)
+        .collect();
+    let hashes = files
+        .iter()
+        .map(|file| match fs::read(file) {
+            Ok(data) => Ok(format!("{:x}", md5::compute(data))),
+            Err(e) => Err(Error::Ost(
+                format!("{}: {}", file.to_string_lossy(), e).into(),
+            )),
+        })
+        .collect::<Result<Vec<String>, Error>>()?;
+    let session = xmlrpc_session(&opts, "checking the subtitles", false).await?;
 
-    if let Some(files) = args.values_of_os("FILES") {
-        for fname in files {
-            let res = download_subtitles(fname, &langs, which, &token);
-            print_if_err(&res);
+    let found = api::check_sub_hashes(&hashes, &session.token).await?;
+    for (file, hash) in files.iter().zip(&hashes) {
+        match found.get(hash) {
+            Some(Some(id)) => println!(
+                "{}: already on the server, subtitle file {}",
+                file.to_string_lossy(),
+                id
+            ),
+            _ => println!("{}: not on the server", file.to_string_lossy()),
         }
     }
 
     Ok(())
 }
 
-fn parse_arguments<'a>() -> ArgMatches<'a> {
+/// The files to process: the ones given as arguments, then the ones
+/// listed in --files-from, expanded as they are consumed, skipping the
+/// files without the extensions unless all files are wanted. Without
+/// either the default is used, naming the subtitles when there is no video.
+fn input_files(
+    args: &ArgMatches,
+    default: Option<String>,
+    extensions: &[String],
+    all_files: bool,
+) -> Result<Box<dyn Iterator<Item = PathBuf>>, Error> {
+    if !args.is_present("FILES") && !args.is_present("files_from") {
+        return Ok(Box::new(default.map(PathBuf::from).into_iter()));
+    }
+
+    let args_files = args.values_of_os("FILES").into_iter().flatten();
+    let listed_files = match args.value_of_os("files_from") {
+        Some(path) => Some(scan::read_list(
+            open_list(Path::new(path))?,
+            args.is_present("null"),
+        )),
+        None => None,
+    };
+    let inputs = args_files
+        .map(PathBuf::from)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .chain(listed_files.into_iter().flatten());
+
+    Ok(Box::new(scan::expand(
+        inputs,
+        args.is_present("recursive"),
+        extensions.to_vec(),
+        all_files,
+    )))
+}
+
+/// Opens the file list, - meaning stdin
+fn open_list(path: &Path) -> Result<Box<dyn BufRead>, Error> {
+    if path == Path::new("-") {
+        Ok(Box::new(io::stdin().lock()))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+/// The providers to search as specified by the options, OpenSubtitles
+/// searching with the session, using the caches
+fn providers<'a>(
+    opts: &Options,
+    session: Option<&'a Session>,
+    caches: &'a Caches,
+) -> Result<Cached<'a>, Error> {
+    let mut providers = Providers::new(&opts.providers, session, opts.rpc_download)?;
+    if let Some(ref program) = opts.provider_cmd {
+        providers.add(Box::new(External::new(program)));
+    }
+
+    Ok(Cached::new(Box::new(providers), caches))
+}
+
+/// Loads the caches as specified by the options, problems with them only
+/// being reported
+fn caches(opts: &Options) -> Caches {
+    if opts.no_cache {
+        return Caches::disabled();
+    }
+
+    let searches = match opts.cache_ttl {
+        Some(ttl) => SearchCache::load(ttl, opts.refresh).unwrap_or_else(|e| {
+            warn!("search cache not available: {}", e);
+            SearchCache::disabled()
+        }),
+        None => SearchCache::disabled(),
+    };
+    let downloads = DownloadCache::open().unwrap_or_else(|e| {
+        warn!("download cache not available: {}", e);
+        DownloadCache::disabled()
+    });
+
+    Caches {
+        searches,
+        downloads,
+    }
+}
+
+/// Sets up the requests, checks that the server has subtitles in the
+/// languages asked for and logs in, reusing the token of an earlier run,
+/// switching to the VIP endpoint for VIP members. Without OpenSubtitles
+/// among the providers there is no session.
+async fn connect(opts: &Options) -> Result<Option<Session>, Error> {
+    net::configure(opts.net.clone());
+    if !opts.providers.iter().any(|p| p == "opensubtitles") {
+        return Ok(None);
+    }
+    if opts.backend == Backend::XmlRpc {
+        match lang::server_languages().await {
+            Ok(known) => lang::check_known(&opts.langs, &known)?,
+            Err(e) => warn!("cannot check the languages: {}", e),
+        }
+    }
+    let session = login_cached(&opts.backend, opts.credentials.as_ref(), &opts.user_agent).await?;
+    if session.vip && opts.vip_endpoint {
+        debug!("logged in as a VIP member, using the VIP endpoint");
+        use_vip_endpoint();
+    }
+
+    Ok(Some(session))
+}
+
+/// Parses the numeric argument if given
+fn parse_number(args: &ArgMatches, name: &str) -> Result<Option<u32>, Error> {
+    match args.value_of(name) {
+        Some(value) => match value.parse() {
+            Ok(n) => Ok(Some(n)),
+            Err(_) => Err(Error::Ost(format!("invalid {}: {}", name, value).into())),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The timeout given in seconds on the command line or in the
+/// configuration, 0 meaning none
+fn parse_timeout(
+    args: &ArgMatches,
+    name: &str,
+    config: Option<u32>,
+    default: Option<Duration>,
+) -> Result<Option<Duration>, Error> {
+    Ok(match parse_number(args, name)?.or(config) {
+        Some(0) => None,
+        Some(secs) => Some(Duration::from_secs(secs.into())),
+        None => default,
+    })
+}
+
+/// Merges the configuration file and the command line arguments, the
+/// latter taking precedence
+fn parse_arguments(args: &ArgMatches) -> Result<Options, Error> {
+    let config = Config::load(args.value_of_os("config").map(Path::new))?;
+
+    let which = if args.is_present("interactive") {
+        if args.value_of_os("files_from") == Some(OsStr::new("-")) {
+            return Err("--interactive reads the answers from stdin, not the files".into());
+        }
+        Which::Select
+    } else if args.is_present("all") {
+        Which::All
+    } else if let Some(top) = args.value_of("top") {
+        match top.parse() {
+            Ok(n) if n > 0 => Which::Top(n),
+            _ => return Err("invalid number of subtitles for --top".into()),
+        }
+    } else if args.is_present("best") || !config.all.unwrap_or(false) {
+        Which::Best
+    } else {
+        Which::All
+    };
+
+    let user = args.value_of("user").map(String::from).or(config.user);
+    let password = args
+        .value_of("password")
+        .map(String::from)
+        .or(config.password);
+    let credential_command = args
+        .value_of("credential_command")
+        .map(String::from)
+        .or(config.credential_command);
+    let credentials = match (user, credential_command) {
+        (Some(username), _) => Some(Credentials {
+            password: password.ok_or("no password given for the user")?,
+            username,
+        }),
+        (None, Some(cmd)) => Some(credentials::from_command(&cmd)?),
+        (None, None) => None,
+    };
+
+    let backend = match args.value_of("api").or(config.api.as_deref()) {
+        None | Some("xmlrpc") => Backend::XmlRpc,
+        Some("rest") => Backend::Rest {
+            api_key: args
+                .value_of("api_key")
+                .map(String::from)
+                .or(config.api_key)
+                .ok_or("the REST API needs an API key")?,
+        },
+        Some(_) => return Err("invalid API, use rest or xmlrpc".into()),
+    };
+
+    let suffix: LangSuffix = args
+        .value_of("lang_suffix")
+        .or(config.lang_suffix.as_deref())
+        .unwrap_or("code")
+        .parse()?;
+
+    let lang_map = args.value_of("lang_map").or(config.lang_map.as_deref());
+
+    let providers: Vec<String> = args
+        .value_of("providers")
+        .or(config.providers.as_deref())
+        .unwrap_or("opensubtitles")
+        .split(',')
+        .map(|p| p.trim().to_lowercase())
+        .collect();
+    if let Some(unknown) = providers.iter().find(|p| !PROVIDERS.contains(&p.as_str())) {
+        return Err(Error::Ost(
+            format!(
+                "unknown provider: {}, use {}",
+                unknown,
+                PROVIDERS.join(" or ")
+            )
+            .into(),
+        ));
+    }
+
+    let query = Query {
+        imdb_id: args.value_of("imdb").map(parse_imdb_id).transpose()?,
+        text: args.value_of("query").map(String::from),
+        season: parse_number(args, "season")?,
+        episode: parse_number(args, "episode")?,
+        ..Query::default()
+    };
+    if (query.season.is_some() || query.episode.is_some())
+        && query.text.is_none()
+        && query.imdb_id.is_none()
+    {
+        return Err("--season and --episode need --query or --imdb".into());
+    }
+
+    let retries = match args.value_of("retries") {
+        Some(retries) => retries.parse().map_err(|_| "invalid number of retries")?,
+        None => config.retries.unwrap_or(net::Settings::default().retries),
+    };
+
+    let connect_timeout = parse_timeout(
+        args,
+        "connect_timeout",
+        config.connect_timeout,
+        net::Settings::default().connect_timeout,
+    )?;
+    let timeout = parse_timeout(
+        args,
+        "timeout",
+        config.timeout,
+        net::Settings::default().timeout,
+    )?;
+
+    let name_template: Option<NameTemplate> = args
+        .value_of("name_template")
+        .or(config.name_template.as_deref())
+        .map(str::parse)
+        .transpose()?;
+    let filter = Filter {
+        min_score: match args.value_of("min_score") {
+            Some(score) => Some(score.parse().map_err(|_| "invalid minimum score")?),
+            None => config.min_score,
+        },
+        sort_by: args
+            .value_of("sort_by")
+            .or(config.sort_by.as_deref())
+            .unwrap_or("score")
+            .parse()?,
+        hearing_impaired: if args.is_present("hi") {
+            match args.value_of("hi") {
+                Some("require") => Wanted::Required,
+                _ => Wanted::Preferred,
+            }
+        } else if args.is_present("no_hi") {
+            Wanted::Excluded
+        } else {
+            match config.hearing_impaired.as_deref() {
+                None | Some("accept") => Wanted::Accepted,
+                Some("prefer") => Wanted::Preferred,
+                Some("require") => Wanted::Required,
+                Some("exclude") => Wanted::Excluded,
+                Some(_) => {
+                    return Err(
+                        "invalid hearing-impaired, use accept, prefer, require or exclude".into(),
+                    )
+                }
+            }
+        },
+        fps_match: match args.value_of("match_fps").or(config.match_fps.as_deref()) {
+            None | Some("prefer") => Wanted::Preferred,
+            Some("require") => Wanted::Required,
+            Some("off") => Wanted::Accepted,
+            Some(_) => return Err("invalid match-fps, use prefer, require or off".into()),
+        },
+        video_fps: None,
+        release_match: if args.is_present("require_release_match")
+            || config.require_release_match.unwrap_or(false)
+        {
+            Wanted::Required
+        } else {
+            Wanted::Preferred
+        },
+        video_release: Release::default(),
+        trusted: if args.is_present("trusted_only") || config.trusted_only.unwrap_or(false) {
+            Wanted::Required
+        } else {
+            Wanted::Accepted
+        },
+        machine_translated: if args.is_present("allow_machine_translated")
+            || config.allow_machine_translated.unwrap_or(false)
+        {
+            Wanted::Accepted
+        } else {
+            Wanted::Excluded
+        },
+        formats: args
+            .value_of("formats")
+            .or(config.formats.as_deref())
+            .map(|formats| {
+                formats
+                    .split(',')
+                    .map(|f| f.trim().to_lowercase())
+                    .filter(|f| !f.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        forced: if args.is_present("forced_only") {
+            Wanted::Required
+        } else if args.is_present("include_forced") || config.include_forced.unwrap_or(false) {
+            Wanted::Accepted
+        } else {
+            Wanted::Excluded
+        },
+    };
+
+    let mark_sdh = args.is_present("mark_sdh") || config.mark_sdh.unwrap_or(false);
+    let default_lang = args
+        .value_of("default_lang")
+        .or(config.default_lang.as_deref())
+        .map(lang::resolve)
+        .transpose()?
+        .map(String::from);
+
+    if let Some(ref template) = name_template {
+        if mark_sdh && !template.has(Field::Sdh) {
+            return Err(
+                "--name-template needs {sdh} to mark the subtitles for the hearing impaired".into(),
+            );
+        }
+        if default_lang.is_some() && !template.has(Field::Default) {
+            return Err("--name-template needs {default} to mark the default language".into());
+        }
+        if matches!(which, Which::All | Which::Top(_)) && !template.has(Field::Idx) {
+            return Err("--name-template needs {idx} to download more subtitles".into());
+        }
+        if filter.forced == Wanted::Accepted && !template.has(Field::Forced) {
+            return Err("--name-template needs {forced} to download forced subtitles too".into());
+        }
+    }
+
+    let cache_ttl = match parse_number(args, "cache_ttl")?.or(config.cache_ttl) {
+        Some(0) => None,
+        Some(hours) => Some(Duration::from_secs(u64::from(hours) * 60 * 60)),
+        None => Some(cache::DEFAULT_TTL),
+    };
+
+    let upgrade = if args.is_present("upgrade") {
+        let margin = args.value_of("upgrade_margin").unwrap_or("1.0");
+        Some(margin.parse().map_err(|_| "invalid upgrade margin")?)
+    } else {
+        None
+    };
+
+    // the subtitles in the archive are not next to the videos to mux them
+    let mux = (args.is_present("mux") || config.mux.unwrap_or(false))
+        && args.value_of_os("archive").is_none();
+    let skip_existing = !args.is_present("force")
+        && upgrade.is_none()
+        && (args.is_present("skip_existing") || config.skip_existing.unwrap_or(false));
+
+    let langs = lang::resolve_list(
+        args.value_of("langs")
+            .or(config.langs.as_deref())
+            .unwrap_or("eng"),
+    )?;
+    let lang_tags = LangTags::parse(lang_map.unwrap_or(""))?;
+    suffix.check(&langs, &lang_tags)?;
+
+    Ok(Options {
+        langs,
+        query,
+        which,
+        filter,
+        suffix,
+        lang_tags,
+        name_template,
+        mark_sdh,
+        default_lang,
+        utf8: args.is_present("utf8")
+            || (!args.is_present("no_utf8") && config.utf8.unwrap_or(true)),
+        line_ending: if args.is_present("crlf") {
+            Some(LineEnding::Crlf)
+        } else if args.is_present("lf") {
+            Some(LineEnding::Lf)
+        } else {
+            config.line_endings.as_deref().map(str::parse).transpose()?
+        },
+        strip_ads: if args.is_present("strip_ads") || config.strip_ads.unwrap_or(false) {
+            Some(match config.ad_patterns {
+                Some(ref patterns) => AdFilter::new(patterns)?,
+                None => AdFilter::new(AD_PATTERNS)?,
+            })
+        } else {
+            None
+        },
+        strip_bom: args.is_present("strip_bom") || config.strip_bom.unwrap_or(false),
+        convert: args
+            .value_of("convert")
+            .or(config.convert.as_deref())
+            .map(str::parse)
+            .transpose()?,
+        extensions: args
+            .value_of("ext")
+            .or(config.extensions.as_deref())
+            .map_or_else(scan::default_extensions, scan::parse_extensions),
+        output_dir: args
+            .value_of_os("output_dir")
+            .map(PathBuf::from)
+            .or(config.output_dir),
+        archive: args.value_of_os("archive").map(PathBuf::from),
+        mux,
+        remove_muxed: mux
+            && (args.is_present("remove_muxed") || config.remove_muxed.unwrap_or(false)),
+        backend,
+        providers,
+        provider_cmd: args
+            .value_of_os("provider_cmd")
+            .map(PathBuf::from)
+            .or(config.provider_cmd),
+        credentials,
+        user_agent: args
+            .value_of("user_agent")
+            .map(String::from)
+            .or(config.user_agent)
+            .unwrap_or_else(|| DEFAULT_USER_AGENT.into()),
+        net: net::Settings {
+            retries,
+            api_url: args
+                .value_of("api_url")
+                .map(String::from)
+                .or(config.api_url),
+            proxy: args
+                .value_of("proxy")
+                .or(config.proxy.as_deref())
+                .map(net::parse_proxy)
+                .transpose()?,
+            connect_timeout,
+            timeout,
+            ca_certs: match args
+                .value_of_os("ca_cert")
+                .map(PathBuf::from)
+                .or(config.ca_cert)
+            {
+                Some(path) => net::load_certs(&path)?,
+                None => Vec::new(),
+            },
+            insecure: args.is_present("insecure") || config.insecure.unwrap_or(false),
+        },
+        with_comments: args.is_present("with_comments"),
+        upgrade,
+        cache_ttl,
+        no_cache: args.is_present("no_cache") || config.no_cache.unwrap_or(false),
+        // upgrading is about the subtitles uploaded since
+        refresh: args.is_present("refresh") || upgrade.is_some(),
+        skip_existing,
+        skip_embedded: args
+            .value_of("skip_embedded")
+            .or(config.skip_embedded.as_deref())
+            .map(lang::resolve_list)
+            .transpose()?
+            .map(|langs| langs.split(',').map(String::from).collect())
+            .unwrap_or_default(),
+        dry_run: args.is_present("dry_run"),
+        json: args.is_present("json"),
+        rpc_download: args.is_present("rpc_download") || config.rpc_download.unwrap_or(false),
+        vip_endpoint: !args.is_present("no_vip") && config.vip_endpoint.unwrap_or(true),
+        jobs: match args.value_of("jobs") {
+            Some(jobs) => match jobs.parse() {
+                Ok(jobs) if jobs > 0 => jobs,
+                _ => return Err("invalid number of jobs".into()),
+            },
+            None => 1,
+        },
+        timings: args.is_present("timings"),
+        trakt_user: args
+            .value_of("trakt_user")
+            .map(String::from)
+            .or(config.trakt_user),
+        trakt_client_id: args
+            .value_of("trakt_client_id")
+            .map(String::from)
+            .or(config.trakt_client_id),
+        trakt_only: args.is_present("trakt_only"),
+        retry_missing: args
+            .value_of("retry_missing")
+            .or(config.retry_missing.as_deref())
+            .map(retry::parse_duration)
+            .transpose()?,
+        retry_for: args
+            .value_of("retry_for")
+            .or(config.retry_for.as_deref())
+            .map_or(Ok(retry::DEFAULT_DEADLINE), retry::parse_duration)?,
+        state: args.value_of_os("state").map(PathBuf::from),
+        exec: args.value_of("exec").map(String::from).or(config.exec),
+        metadata: args.is_present("metadata") || config.metadata.unwrap_or(false),
+        report: args.value_of_os("report").map(PathBuf::from),
+        contribute_hashes: args.is_present("contribute_hashes")
+            || config.contribute_hashes.unwrap_or(false),
+    })
+}
+
+/// The command line interface, downloading is the default when no
+/// subcommand is given
+fn app<'a, 'b>() -> App<'a, 'b> {
     App::new("Opensubtitles downloader")
         .version(crate_version!())
         .author("Istvan Szekeres <szekeres@iii.hu>")
         .about("Downloads subtitles from opensubtitles.org")
-        .arg(
-            Arg::with_name("langs")
-                .short("l")
-                .long("langs")
-                .help("Languages to download subtitles for, comma separated")
-                .required(false)
-                .takes_value(true),
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .args(&verbosity_args())
+        .args(&connection_args())
+        .args(&download_args())
+        .args(&filter_args())
+        .args(&query_args())
+        .args(&input_args())
+        .subcommand(
+            SubCommand::with_name("download")
+                .about("Downloads subtitles for the files (the default)")
+                .args(&connection_args())
+                .args(&download_args())
+                .args(&filter_args())
+                .args(&query_args())
+                .args(&input_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Lists the subtitles found for the files without downloading them")
+                .args(&connection_args())
+                .arg(langs_arg())
+                .arg(with_comments_arg())
+                .args(&filter_args())
+                .args(&query_args())
+                .args(&input_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("retry")
+                .about("Searches again for the subtitles queued by --retry-missing that are due")
+                .args(&connection_args())
+                .args(&download_args())
+                .args(&filter_args())
+                .arg(
+                    Arg::with_name("wait")
+                        .long("wait")
+                        .help("Keep running, searching for the files as they are due, until none are left")
+                        .required(false)
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Lists the languages the videos have subtitles in next to them, and the ones missing")
+                .arg(langs_arg())
+                .arg(
+                    Arg::with_name("output_dir")
+                        .short("o")
+                        .long("output-dir")
+                        .help("Look for the subtitles in this directory instead of next to the videos")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .args(&input_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("hash")
+                .about("Prints the OSDb hash and the size of the files")
+                .args(&input_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("langs").about("Lists the languages the server has subtitles in"),
+        )
+        .subcommand(
+            SubCommand::with_name("login")
+                .about("Logs in and prints the token")
+                .args(&connection_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("vote")
+                .about("Votes for a subtitle as a registered user, so that the good ones rank higher")
+                .args(&connection_args())
+                .arg(
+                    Arg::with_name("SUBTITLE_ID")
+                        .help("The id of the subtitle, as listed by search")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("SCORE")
+                        .help("The score, from 1 (bad) to 10 (perfect)")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("report-wrong")
+                .about("Reports a subtitle found by the hash of a video as not for the video, e.g. for another cut")
+                .args(&connection_args())
+                .arg(
+                    Arg::with_name("MOVIE_FILE_ID")
+                        .help("The id of the match of the hash with the subtitle (IDSubMovieFile), logged with -v")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("comment")
+                .about("Comments on a subtitle as a registered user, e.g. to tell the others it is out of sync")
+                .args(&connection_args())
+                .arg(
+                    Arg::with_name("SUBTITLE_ID")
+                        .help("The id of the subtitle, as listed by search")
+                        .required(true),
+                )
+                .arg(Arg::with_name("TEXT").help("The comment").required(true))
+                .arg(
+                    Arg::with_name("bad")
+                        .long("bad")
+                        .help("Flag the subtitle as bad too")
+                        .required(false)
+                        .takes_value(false),
+                ),
         )
-        .arg(
-            Arg::with_name("all")
-                .short("a")
-                .long("all")
-                .help("Download all the subtitles for the selected languages")
-                .required(false)
-                .takes_value(false),
+        .subcommand(
+            SubCommand::with_name("check-subs")
+                .about("Tells which of the subtitle files the server has already, e.g. before uploading them")
+                .args(&connection_args())
+                .arg(
+                    Arg::with_name("SUBTITLES")
+                        .help("The subtitle files")
+                        .multiple(true)
+                        .required(true),
+                ),
         )
-        .arg(
-            Arg::with_name("FILES")
-                .multiple(true)
-                .required(true)
-                .help("Files to download subtitles for"),
+        .subcommand(
+            SubCommand::with_name("rpc")
+                .about("Serves JSON-RPC requests on stdin/stdout, one per line")
+                .args(&connection_args()),
         )
-        .get_matches()
+}
+
+/// How much to tell about what is happening, for all the subcommands
+fn verbosity_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("verbose")
+            .short("v")
+            .long("verbose")
+            .help("Tell more about what is happening, -vv even more")
+            .multiple(true)
+            .global(true)
+            .takes_value(false),
+        Arg::with_name("quiet")
+            .short("q")
+            .long("quiet")
+            .help("Print only the errors")
+            .conflicts_with("verbose")
+            .global(true)
+            .takes_value(false),
+    ]
+}
+
+/// The languages to look for
+fn langs_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("langs")
+        .short("l")
+        .long("langs")
+        .help("Languages to download subtitles for, comma separated, e.g. eng,hun or en,pt-BR")
+        .required(false)
+        .takes_value(true)
+}
+
+/// Whether to show the comments of the candidates
+fn with_comments_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("with_comments")
+        .long("with-comments")
+        .help("Show the user comments of the top candidates")
+        .required(false)
+        .takes_value(false)
+}
+
+/// The arguments about how to connect to the API
+fn connection_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("config")
+            .short("c")
+            .long("config")
+            .help("Read the configuration from this file instead of ~/.config/ostdl/config.toml")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("providers")
+            .long("providers")
+            .help("Search these sources, comma separated: opensubtitles, napiprojekt [default: opensubtitles]")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("provider_cmd")
+            .long("provider-cmd")
+            .help("Search this command as a provider too, see the README for what it is run with")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("api")
+            .long("api")
+            .help("The API to use [default: xmlrpc]")
+            .possible_values(&["xmlrpc", "rest"])
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("api_url")
+            .long("api-url")
+            .env("OSTDL_API_URL")
+            .help("Use the API at this URL instead of the one of OpenSubtitles, e.g. a mirror")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("cache_ttl")
+            .long("cache-ttl")
+            .help("Keep the search results for this many hours, 0 for not at all [default: 24]")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("no_cache")
+            .long("no-cache")
+            .help("Neither use nor keep the search results and the subtitles of earlier runs")
+            .conflicts_with("refresh")
+            .required(false),
+        Arg::with_name("refresh")
+            .long("refresh")
+            .help("Search again instead of using the results of earlier runs")
+            .required(false),
+        Arg::with_name("connect_timeout")
+            .long("connect-timeout")
+            .help("Give up connecting after this many seconds, 0 for never [default: 30]")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("timeout")
+            .long("timeout")
+            .help("Give up a request after this many seconds, 0 for never [default: 120]")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("proxy")
+            .long("proxy")
+            .help("Make the requests through this proxy, e.g. socks5://localhost:1080")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("ca_cert")
+            .long("ca-cert")
+            .help("Trust the certificates in this PEM file too, e.g. the one of a corporate proxy")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("insecure")
+            .long("insecure")
+            .help("INSECURE: accept any certificate, leaving the connections open to eavesdropping")
+            .required(false),
+        Arg::with_name("no_vip")
+            .long("no-vip")
+            .help("Don't switch to the VIP endpoint when logged in as a VIP member")
+            .required(false),
+        Arg::with_name("user_agent")
+            .long("user-agent")
+            .env("OSTDL_USER_AGENT")
+            .help("Identify as this user agent, e.g. a registered one")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("api_key")
+            .long("api-key")
+            .env("OSTDL_API_KEY")
+            .hide_env_values(true)
+            .help("API key for the REST API")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("user")
+            .short("u")
+            .long("user")
+            .env("OSTDL_USER")
+            .help("Log in as this registered user instead of anonymously")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("password")
+            .short("p")
+            .long("password")
+            .env("OSTDL_PASSWORD")
+            .hide_env_values(true)
+            .help("Password of the registered user")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("credential_command")
+            .long("credential-command")
+            .help("Log in with the credentials printed by this command")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("retries")
+            .long("retries")
+            .help("How many times to retry failed requests, waiting longer and longer [default: 3]")
+            .required(false)
+            .takes_value(true),
+    ]
+}
+
+/// The arguments about what to search the subtitles by, besides the hash
+fn query_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("imdb")
+            .long("imdb")
+            .help("Search by this IMDb id too, e.g. tt0133093")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("query")
+            .long("query")
+            .help("Search by this title instead of the hash, the files only name the subtitles")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("season")
+            .long("season")
+            .help("The season of the episode searched for")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("episode")
+            .long("episode")
+            .help("The episode searched for")
+            .required(false)
+            .takes_value(true),
+    ]
+}
+
+/// The arguments choosing among the subtitles found
+fn filter_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("min_score")
+            .long("min-score")
+            .help("Never download subtitles scoring lower than this")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("sort_by")
+            .long("sort-by")
+            .help("What the best subtitles are best at: score, downloads, rating or date [default: score]")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("hi")
+            .long("hi")
+            .help("Prefer the subtitles for the hearing impaired, --hi=require for only those")
+            .possible_values(&["prefer", "require"])
+            .min_values(0)
+            .require_equals(true)
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("no_hi")
+            .long("no-hi")
+            .help("Never download subtitles for the hearing impaired")
+            .conflicts_with("hi")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("match_fps")
+            .long("match-fps")
+            .help("Prefer or require subtitles timed for the frame rate of the video, or off [default: prefer]")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("require_release_match")
+            .long("require-release-match")
+            .help("Download only subtitles made for the release of the video, e.g. the same group")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("trusted_only")
+            .long("trusted-only")
+            .help("Download only subtitles uploaded by administrators, trusted or gold members")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("allow_machine_translated")
+            .long("allow-machine-translated")
+            .help("Download subtitles translated by a machine too")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("formats")
+            .long("formats")
+            .help("Download only subtitles in these formats, the first preferred on ties, e.g. srt,ass")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("include_forced")
+            .long("include-forced")
+            .help("Download the best forced subtitles too, translating only the foreign parts")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("forced_only")
+            .long("forced-only")
+            .help("Download only forced subtitles")
+            .conflicts_with("include_forced")
+            .required(false)
+            .takes_value(false),
+    ]
+}
+
+/// The arguments selecting the files to process
+fn input_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("FILES")
+            .multiple(true)
+            .required_unless_one(&["files_from", "query"])
+            .help(
+                "Files (or glob patterns) to process, directories to look for videos in, \
+                 or URLs of remote videos",
+            ),
+        Arg::with_name("recursive")
+            .short("r")
+            .long("recursive")
+            .help("Look for videos in the subdirectories of the given directories too")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("ext")
+            .long("ext")
+            .help(
+                "The extensions of the videos, comma separated, the other files are skipped; \
+                 * for any [default: mkv,mp4,avi,...]",
+            )
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("files_from")
+            .long("files-from")
+            .help("Process the files listed in this file too, one per line, - for stdin")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("null")
+            .short("0")
+            .long("null")
+            .help("The paths in --files-from are separated by NUL characters, see find -print0")
+            .requires("files_from")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("state")
+            .long("state")
+            .help("Record the files processed in this file, and skip the ones recorded, to resume an interrupted run")
+            .required(false)
+            .takes_value(true),
+    ]
+}
+
+/// The arguments controlling what is downloaded and where
+fn download_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        langs_arg(),
+        Arg::with_name("all")
+            .short("a")
+            .long("all")
+            .help("Download all the subtitles for the selected languages")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("best")
+            .short("b")
+            .long("best")
+            .help("Download only the best subtitle, even if the config says all")
+            .conflicts_with("all")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("top")
+            .long("top")
+            .help("Download this many of the best subtitles for the selected languages")
+            .conflicts_with_all(&["all", "best"])
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("list")
+            .long("list")
+            .help("Only list the subtitles found, like the search subcommand")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("json")
+            .long("json")
+            .help("Print the results as a JSON object per file")
+            .conflicts_with("list")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("dry_run")
+            .short("n")
+            .long("dry-run")
+            .help("Only print which subtitles would be written where, with their scores and URLs")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("interactive")
+            .short("i")
+            .long("interactive")
+            .help("Show the candidates and pick the one to download for each file and language")
+            .conflicts_with_all(&["all", "best", "top"])
+            .required(false)
+            .takes_value(false),
+        with_comments_arg(),
+        Arg::with_name("jobs")
+            .short("j")
+            .long("jobs")
+            .help("How many subtitles to download at the same time [default: 1]")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("rpc_download")
+            .long("rpc-download")
+            .help("Download the subtitles with the DownloadSubtitles call of the XML-RPC API instead of their links")
+            .required(false),
+        Arg::with_name("timings")
+            .long("timings")
+            .help("Report the time spent hashing, searching, downloading and post-processing")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("upgrade")
+            .long("upgrade")
+            .help("Replace existing subtitles only with better scoring ones, keeping a .bak")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("skip_existing")
+            .long("skip-existing")
+            .help("Keep the subtitles already there instead of downloading them again")
+            .conflicts_with("upgrade")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("force")
+            .long("force")
+            .alias("overwrite")
+            .help("Overwrite the subtitles already there, even if the config says skip")
+            .conflicts_with_all(&["skip_existing", "upgrade"])
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("skip_embedded")
+            .long("skip-embedded")
+            .help("Don't download subtitles in these languages for the videos having them embedded, e.g. eng,hun")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("upgrade_margin")
+            .long("upgrade-margin")
+            .help("How much higher the score has to be for an upgrade [default: 1.0]")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("output_dir")
+            .short("o")
+            .long("output-dir")
+            .help("Write the subtitles into this directory instead of next to the videos")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("name_template")
+            .long("name-template")
+            .help("Name the subtitle files like this, e.g. {stem}.{lang2}.{format}, see the README")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("mark_sdh")
+            .long("mark-sdh")
+            .help("Add .sdh to the names of the subtitles for the hearing impaired, as Plex and Kodi expect")
+            .required(false),
+        Arg::with_name("default_lang")
+            .long("default-lang")
+            .help("Add .default to the names of the subtitles in this language, for the players to pick them")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("utf8")
+            .long("utf8")
+            .help("Transcode the subtitles to UTF-8 (the default), even if the config says not to")
+            .required(false)
+            .conflicts_with("no_utf8"),
+        Arg::with_name("no_utf8")
+            .long("no-utf8")
+            .help("Keep the subtitles in their original encoding instead of transcoding them to UTF-8")
+            .required(false),
+        Arg::with_name("crlf")
+            .long("crlf")
+            .help("Write the subtitles with Windows (CRLF) line endings")
+            .required(false)
+            .conflicts_with("lf"),
+        Arg::with_name("lf")
+            .long("lf")
+            .help("Write the subtitles with Unix (LF) line endings")
+            .required(false),
+        Arg::with_name("strip_ads")
+            .long("strip-ads")
+            .help("Remove the advertisements, e.g. the lines naming the site, from the SRT subtitles")
+            .required(false),
+        Arg::with_name("strip_bom")
+            .long("strip-bom")
+            .help("Remove the UTF-8 byte order mark from the subtitles")
+            .required(false),
+        Arg::with_name("convert")
+            .long("convert")
+            .help("Convert the MicroDVD, SSA and ASS subtitles to this format: srt")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("archive")
+            .long("archive")
+            .help("Collect the subtitles into this zip file instead of next to the videos")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("mux")
+            .long("mux")
+            .help("Mux the subtitles into the MKV videos with mkvmerge, keeping the files too")
+            .required(false)
+            .conflicts_with("archive"),
+        Arg::with_name("remove_muxed")
+            .long("remove-muxed")
+            .help("Remove the subtitle files once muxed into the videos")
+            .requires("mux")
+            .required(false),
+        Arg::with_name("lang_suffix")
+            .long("lang-suffix")
+            .help("How to name the language in the subtitle file names [default: code]")
+            .possible_values(&["code", "alpha2", "bcp47", "name"])
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("lang_map")
+            .long("lang-map")
+            .help("Override language tags, e.g. scc=sr-Latn,pob=pt-BR")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("trakt_user")
+            .long("trakt-user")
+            .help("Process files on this Trakt user's watchlist or recently collected first")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("trakt_client_id")
+            .long("trakt-client-id")
+            .env("TRAKT_CLIENT_ID")
+            .help("Trakt API client id")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("trakt_only")
+            .long("trakt-only")
+            .help("Only process the files matching the Trakt lists")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("report")
+            .long("report")
+            .help("Write the results into this CSV file too, a row per subtitle, language missing or error")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("metadata")
+            .long("metadata")
+            .help("Write the provider, id, score, uploader and release of each subtitle next to it, e.g. into movie.eng.srt.json")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("contribute_hashes")
+            .long("contribute-hashes")
+            .help("Add the hashes of the videos found by their names but not by their hashes to the database, as a registered user")
+            .required(false)
+            .takes_value(false),
+        Arg::with_name("exec")
+            .long("exec")
+            .help("Run this command after each subtitle written, with {video}, {subtitle}, {lang} and {score} replaced")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("retry_missing")
+            .long("retry-missing")
+            .help("Queue the languages not found to search for them again this often, e.g. 6h, see the retry subcommand")
+            .required(false)
+            .takes_value(true),
+        Arg::with_name("retry_for")
+            .long("retry-for")
+            .help("How long to keep searching for the languages queued, e.g. 3d [default: 7d]")
+            .required(false)
+            .takes_value(true),
+    ]
 }
 
 /// No, the other one is the real one.
-fn main() {
-    let res = real_main();
+#[tokio::main]
+async fn main() -> ExitCode {
+    let res = real_main().await;
     print_if_err(&res);
+    // errors getting this far are the hard ones, e.g. failing to log in
+    ExitCode::from(res.unwrap_or(Outcome::Failed) as u8)
 }
@@ -0,0 +1,72 @@
+//! The metadata written next to the downloaded subtitles with --metadata,
+//! e.g. `movie.eng.srt.json`, telling where each came from:
+//!
+//! ```text
+//! {"downloaded": "2024-05-06T11:00:00Z", "file_id": "1952345678", "format": "srt",
+//!  "id": "1954512345", "lang": "eng", "provider": "opensubtitles",
+//!  "release": "Movie.2019.1080p.WEB-DL", "score": 10.0, "uploader": "someone"}
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+
+use crate::subtitle::Sub;
+
+/// The path of the metadata of the subtitle written to the path
+pub fn path(subtitle: &Path) -> PathBuf {
+    let mut path = subtitle.as_os_str().to_os_string();
+    path.push(".json");
+    PathBuf::from(path)
+}
+
+/// Formats the seconds since the epoch as an RFC 3339 UTC timestamp
+fn utc(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let secs = secs % 86400;
+    // the civil date of the days since the epoch, see
+    // http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// The metadata of the subtitle downloaded now, as JSON
+pub fn to_json(sub: &Sub, format: &str) -> Vec<u8> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let info = json!({
+        "provider": sub.provider,
+        "id": sub.id,
+        "file_id": sub.file_id,
+        "lang": sub.lang,
+        "format": format,
+        "score": sub.score,
+        "uploader": sub.uploader,
+        "release": sub.release,
+        "downloaded": utc(now),
+    });
+
+    let mut data = serde_json::to_vec_pretty(&info).unwrap_or_default();
+    data.push(b'\n');
+    data
+}
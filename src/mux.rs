@@ -0,0 +1,82 @@
+//! Muxing the downloaded subtitles into the Matroska videos with mkvmerge,
+//! for the libraries kept as single files
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use log::debug;
+
+/// A subtitle to add to the video as a track
+#[derive(Debug, Clone)]
+pub struct Track {
+    /// The subtitle file
+    pub path: PathBuf,
+    /// The BCP 47 tag of its language
+    pub lang: String,
+    /// Whether it only translates the foreign parts
+    pub forced: bool,
+    /// Whether it is for the hearing impaired
+    pub hearing_impaired: bool,
+    /// Whether the players should pick it by default
+    pub default: bool,
+}
+
+/// Checks whether the subtitles can be muxed into the video: an existing
+/// Matroska file
+pub fn can_mux(video: &Path) -> bool {
+    video.is_file()
+        && video
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("mkv"))
+}
+
+/// The mkvmerge options of a flag of the first track of the next file
+fn flag(name: &str, set: bool) -> [String; 2] {
+    [
+        format!("--{}", name),
+        format!("0:{}", if set { 1 } else { 0 }),
+    ]
+}
+
+/// Adds the subtitles to the video as tracks, writing the result next to
+/// it and then replacing the video with it
+pub fn mux(video: &Path, tracks: &[Track]) -> Result<(), io::Error> {
+    let mut muxed = OsString::from(video.as_os_str());
+    muxed.push(".muxing");
+    let muxed = PathBuf::from(muxed);
+
+    let mut cmd = Command::new("mkvmerge");
+    cmd.arg("--quiet").arg("-o").arg(&muxed).arg(video);
+    for track in tracks {
+        cmd.args(["--language", &format!("0:{}", track.lang)])
+            .args(flag("forced-display-flag", track.forced))
+            .args(flag("hearing-impaired-flag", track.hearing_impaired))
+            .args(flag("default-track-flag", track.default))
+            .arg(&track.path);
+    }
+    debug!("running {:?}", cmd);
+
+    let output = cmd
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot run mkvmerge: {}", e)))?;
+    // 1 is for warnings, the file is written anyway
+    if !matches!(output.status.code(), Some(0 | 1)) {
+        let _ = fs::remove_file(&muxed);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let message = stdout
+            .lines()
+            .rfind(|line| !line.trim().is_empty())
+            .unwrap_or("")
+            .to_string();
+        return Err(io::Error::other(format!(
+            "mkvmerge failed ({}): {}",
+            output.status, message
+        )));
+    }
+
+    fs::rename(&muxed, video)
+}
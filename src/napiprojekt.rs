@@ -0,0 +1,182 @@
+//! Napiprojekt, a source of polish (and some english) subtitles, found by
+//! the MD5 hash of the first 10 MiB of the video
+//!
+//! The API returns the subtitle itself, so the ones found are kept until
+//! they are fetched, by whichever instance of the provider.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Mutex;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::debug;
+
+use crate::api::Query;
+use crate::error::Error;
+use crate::net::{client, send};
+use crate::provider::SubtitleProvider;
+use crate::subtitle::{Sub, Subs};
+
+/// Napiprojekt API entry point
+const NAPI_API_URL: &str = "http://napiprojekt.pl/api/api-napiprojekt3.php";
+
+/// How much of the video is hashed
+const HASH_SIZE: u64 = 10 << 20;
+
+/// The subtitles found and not fetched yet, by their ids
+static FOUND: Mutex<BTreeMap<String, Vec<u8>>> = Mutex::new(BTreeMap::new());
+
+/// The languages Napiprojekt has subtitles in: the ids and the codes of
+/// the API
+const NAPI_LANGS: &[(&str, &str)] = &[("pol", "PL"), ("eng", "ENG")];
+
+/// The MD5 hash of the first 10 MiB of the video in hex, the key of its
+/// subtitles on Napiprojekt
+fn napi_hash(path: &Path) -> Result<String, io::Error> {
+    let mut head = Vec::new();
+    File::open(path)?.take(HASH_SIZE).read_to_end(&mut head)?;
+
+    Ok(format!("{:x}", md5::compute(&head)))
+}
+
+/// The content of the element of the XML response, without the CDATA
+/// wrapper, if it is there
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", name))?;
+    let content = xml[start..end].trim();
+
+    Some(
+        content
+            .strip_prefix("<![CDATA[")
+            .and_then(|c| c.strip_suffix("]]>"))
+            .unwrap_or(content),
+    )
+}
+
+/// Guesses the format of the subtitle from its first line: Napiprojekt
+/// serves MicroDVD, MPL2 and SRT subtitles
+fn guess_format(data: &[u8]) -> &'static str {
+    let text = String::from_utf8_lossy(&data[..data.len().min(256)]);
+    match text
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .chars()
+        .next()
+    {
+        Some('{') => "sub",
+        Some('[') => "mpl",
+        _ => "srt",
+    }
+}
+
+/// Napiprojekt
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Napiprojekt;
+
+impl Napiprojekt {
+    /// Asks for the subtitle of the video with the hash in the language,
+    /// returning it if there is one
+    async fn request(&self, hash: &str, lang: &str) -> Result<Option<Vec<u8>>, Error> {
+        let form = [
+            ("mode", "1"),
+            ("client", "NapiProjekt"),
+            ("client_ver", "2.2.0.2399"),
+            ("downloaded_subtitles_id", hash),
+            ("downloaded_subtitles_txt", "1"),
+            ("downloaded_subtitles_lang", lang),
+        ];
+        let resp = send(client().post(NAPI_API_URL).form(&form)).await?;
+        let body = resp.text().await?;
+
+        if element(&body, "status") != Some("success") {
+            return Ok(None);
+        }
+        match element(&body, "content") {
+            Some(content) if !content.is_empty() => STANDARD
+                .decode(content.split_whitespace().collect::<String>())
+                .map(Some)
+                .map_err(|_| Error::Ost("invalid Napiprojekt response".into())),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl SubtitleProvider for Napiprojekt {
+    fn name(&self) -> &str {
+        "napiprojekt"
+    }
+
+    fn search<'a>(
+        &'a self,
+        query: &'a Query,
+        langs: &'a str,
+    ) -> BoxFuture<'a, Result<Subs, Error>> {
+        async move {
+            // the subtitles are only found by the hash of a local video
+            let video = match query.video {
+                Some(ref video) if query.text.is_none() => video.clone(),
+                _ => return Ok(Vec::new()),
+            };
+            let wanted: Vec<(&str, &str)> = NAPI_LANGS
+                .iter()
+                .copied()
+                .filter(|(id, _)| langs.split(',').any(|lang| lang == *id))
+                .collect();
+            if wanted.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let hash = tokio::task::spawn_blocking(move || napi_hash(&video))
+                .await
+                .map_err(io::Error::other)??;
+            let mut subs = Vec::new();
+            for (id, code) in wanted {
+                let data = match self.request(&hash, code).await? {
+                    Some(data) => data,
+                    None => continue,
+                };
+                debug!("napiprojekt has a {} subtitle for {}", id, hash);
+                let sub_id = format!("{}-{}", hash, code.to_lowercase());
+                subs.push(Sub {
+                    id: sub_id.clone(),
+                    url: NAPI_API_URL.into(),
+                    // the subtitle is made for the exact video
+                    score: 10f64,
+                    lang: id.into(),
+                    format: guess_format(&data).into(),
+                    cd: 1,
+                    cds: 1,
+                    provider: self.name().into(),
+                    ..Sub::default()
+                });
+                FOUND.lock().unwrap().insert(sub_id, data);
+            }
+
+            Ok(subs)
+        }
+        .boxed()
+    }
+
+    fn fetch<'a>(&'a self, sub: &'a Sub) -> BoxFuture<'a, Result<Vec<u8>, Error>> {
+        async move {
+            if let Some(data) = FOUND.lock().unwrap().remove(&sub.id) {
+                return Ok(data);
+            }
+            // fetched already
+            let (hash, code) = sub
+                .id
+                .rsplit_once('-')
+                .ok_or_else(|| Error::Ost(format!("invalid Napiprojekt id: {}", sub.id).into()))?;
+            self.request(hash, &code.to_uppercase())
+                .await?
+                .ok_or_else(|| Error::Ost(format!("subtitle {} is gone", sub.id).into()))
+        }
+        .boxed()
+    }
+}
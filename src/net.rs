@@ -0,0 +1,347 @@
+//! The HTTP layer shared by all the requests: a single client, pacing
+//! the requests and retrying transient failures with exponential backoff
+
+use std::collections::hash_map::RandomState;
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::fs;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{debug, warn};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Certificate, Client, Proxy, RequestBuilder, Response, StatusCode, Url};
+
+use crate::error::Error;
+
+/// The longest wait between two attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(32);
+
+/// The longest wait asked for with Retry-After that is waited out: a server
+/// asking for longer fails the request rather than stalling the run
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// The months of HTTP dates
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// How many requests may be made in a burst: opensubtitles allows about
+/// 40 requests per 10 seconds and bans the clients making more
+const BURST: f64 = 40.0;
+
+/// How many requests may be made per second in the long run
+const RATE: f64 = BURST / 10.0;
+
+/// How the requests are made
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// How many times a failed request is retried
+    pub retries: u32,
+    /// The entry point of the API instead of the one of OpenSubtitles,
+    /// e.g. a mirror or a test server
+    pub api_url: Option<String>,
+    /// The proxy to make the requests through instead of the ones in the
+    /// HTTP_PROXY, HTTPS_PROXY and ALL_PROXY environment variables
+    pub proxy: Option<Proxy>,
+    /// How long to wait for a connection, None for as long as it takes
+    pub connect_timeout: Option<Duration>,
+    /// How long a request may take in all, None for as long as it takes
+    pub timeout: Option<Duration>,
+    /// The certificates to trust besides the ones of the system, e.g. the
+    /// one of a proxy intercepting HTTPS
+    pub ca_certs: Vec<Certificate>,
+    /// Whether to accept any certificate, defeating the purpose of HTTPS
+    pub insecure: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            retries: 3,
+            api_url: None,
+            proxy: None,
+            connect_timeout: Some(Duration::from_secs(30)),
+            timeout: Some(Duration::from_secs(120)),
+            ca_certs: Vec::new(),
+            insecure: false,
+        }
+    }
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// Sets how the requests are made, before the first request. Later calls
+/// have no effect.
+pub fn configure(settings: Settings) {
+    let _ = SETTINGS.set(settings);
+}
+
+/// The settings in effect
+fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(Settings::default)
+}
+
+/// The entry point of the API set in the settings, if any
+pub(crate) fn api_url() -> Option<&'static str> {
+    settings().api_url.as_deref()
+}
+
+/// Parses the URL of a proxy, http://, https://, socks5:// or socks5h://
+/// for resolving the names through the proxy too
+pub fn parse_proxy(url: &str) -> Result<Proxy, Error> {
+    Proxy::all(url).map_err(|e| Error::Ost(format!("invalid proxy {}: {}", url, e).into()))
+}
+
+/// Loads the certificates of the PEM file to trust
+pub fn load_certs(path: &Path) -> Result<Vec<Certificate>, Error> {
+    let invalid = |e: &dyn Display| {
+        Error::Ost(format!("cannot load the certificates of {}: {}", path.display(), e).into())
+    };
+    let pem = fs::read(path).map_err(|e| invalid(&e))?;
+    let certs = Certificate::from_pem_bundle(&pem).map_err(|e| invalid(&e))?;
+    if certs.is_empty() {
+        return Err(invalid(&"no certificates in it"));
+    }
+
+    Ok(certs)
+}
+
+/// The HTTP client shared by all the requests, reusing the connections
+pub(crate) fn client() -> &'static Client {
+    static CLIENT: OnceLock<Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        let settings = settings();
+        let mut builder = Client::builder();
+        if let Some(ref proxy) = settings.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        if let Some(timeout) = settings.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = settings.timeout {
+            builder = builder.timeout(timeout);
+        }
+        for cert in &settings.ca_certs {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+        if settings.insecure {
+            warn!("not checking the certificates of the servers, the connections are not secure");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        builder.build().expect("the HTTP client can be built")
+    })
+}
+
+/// A token bucket pacing the requests
+struct Bucket {
+    /// The requests that can be made right away
+    tokens: f64,
+    /// When the tokens were last refilled
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Takes a token if there is one, otherwise tells how long to wait
+    /// for the next one
+    fn take(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * RATE).min(BURST);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - self.tokens) / RATE))
+        }
+    }
+}
+
+/// Waits until a request can be made without exceeding the rate limit
+async fn throttle() {
+    static BUCKET: OnceLock<Mutex<Bucket>> = OnceLock::new();
+    let bucket = BUCKET.get_or_init(|| {
+        Mutex::new(Bucket {
+            tokens: BURST,
+            updated: Instant::now(),
+        })
+    });
+
+    loop {
+        let wait = bucket.lock().unwrap_or_else(|e| e.into_inner()).take();
+        match wait {
+            Some(wait) => tokio::time::sleep(wait).await,
+            None => return,
+        }
+    }
+}
+
+/// Why an attempt failed
+pub(crate) enum Failure {
+    /// Retrying won't help
+    Fatal(Error),
+    /// Worth retrying, after the time the server asked for if any
+    Transient(Error, Option<Duration>),
+}
+
+impl From<Error> for Failure {
+    fn from(e: Error) -> Failure {
+        Failure::Fatal(e)
+    }
+}
+
+/// A random duration below the limit, to keep the clients retrying at
+/// the same time from hitting the server together
+fn jitter(limit: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(random % (limit.as_millis() as u64 + 1))
+}
+
+/// How long to wait before the next attempt: doubling from a second
+fn backoff(attempt: u32) -> Duration {
+    let wait = Duration::from_secs(1 << attempt.min(5)).min(MAX_BACKOFF);
+    wait + jitter(wait / 2)
+}
+
+/// Runs the attempts until one succeeds, fails for good or the retries
+/// run out. Every attempt is paced by the rate limit if limited, i.e. if
+/// it is made to OpenSubtitles.
+pub(crate) async fn retrying<T, F, Fut>(limited: bool, mut attempt: F) -> Result<T, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Failure>>,
+{
+    let retries = settings().retries;
+    let mut n = 0;
+
+    loop {
+        if limited {
+            throttle().await;
+        }
+        match attempt().await {
+            Ok(res) => return Ok(res),
+            Err(Failure::Fatal(e)) => return Err(e),
+            Err(Failure::Transient(e, _)) if n >= retries => return Err(e),
+            Err(Failure::Transient(e, Some(wait))) if wait > MAX_RETRY_AFTER => {
+                warn!(
+                    "{}, the server asks to wait {}s, not retrying",
+                    e,
+                    wait.as_secs()
+                );
+                return Err(e);
+            }
+            Err(Failure::Transient(e, wait)) => {
+                let wait = wait.unwrap_or_else(|| backoff(n));
+                warn!("{}, retrying in {:.1}s", e, wait.as_secs_f64());
+                tokio::time::sleep(wait).await;
+                n += 1;
+            }
+        }
+    }
+}
+
+/// The time the server asked to wait, given in seconds or as an HTTP date
+fn retry_after(resp: &Response) -> Option<Duration> {
+    let value = resp.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let until = UNIX_EPOCH + parse_http_date(value)?;
+    // a date already past means no wait
+    Some(
+        until
+            .duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Parses an HTTP date, e.g. Sun, 06 Nov 1994 08:49:37 GMT, into the time
+/// since the epoch
+fn parse_http_date(date: &str) -> Option<Duration> {
+    let mut parts = date.split_whitespace().skip(1);
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let time = parts
+        .next()?
+        .split(':')
+        .map(|n| n.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    if parts.next() != Some("GMT") || time.len() != 3 || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // the days since the epoch of the civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146_097 + doe - 719_468).ok()?;
+    Some(Duration::from_secs(
+        days * 86400 + time[0] * 3600 + time[1] * 60 + time[2],
+    ))
+}
+
+/// Sorts out the transient failures of a request: connection errors,
+/// timeouts, throttling and server errors
+pub(crate) fn check_http(res: reqwest::Result<Response>) -> Result<Response, Failure> {
+    if let Ok(ref resp) = res {
+        debug!("{} {}", resp.status(), resp.url());
+    }
+
+    match res {
+        Ok(resp)
+            if resp.status() == StatusCode::TOO_MANY_REQUESTS
+                || resp.status().is_server_error() =>
+        {
+            let wait = retry_after(&resp);
+            let err = resp.error_for_status().err().map(Error::from);
+            Err(Failure::Transient(
+                err.unwrap_or_else(|| "request failed".into()),
+                wait,
+            ))
+        }
+        Ok(resp) => Ok(resp),
+        Err(e) if e.is_connect() || e.is_timeout() || e.is_request() => {
+            Err(Failure::Transient(e.into(), None))
+        }
+        Err(e) => Err(Failure::Fatal(e.into())),
+    }
+}
+
+/// Whether the URL is of OpenSubtitles, whose rate limit is kept to: its
+/// API and download servers, or the API given with --api-url
+fn is_opensubtitles(url: &Url) -> bool {
+    let host = match url.host_str() {
+        Some(host) => host,
+        None => return false,
+    };
+    let api_host = api_url().and_then(|api| Url::parse(api).ok());
+
+    ["opensubtitles.org", "opensubtitles.com"]
+        .iter()
+        .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+        || api_host.is_some_and(|api| api.host_str() == Some(host))
+}
+
+/// Sends the request, retrying the transient failures
+pub(crate) async fn send(req: RequestBuilder) -> Result<Response, Error> {
+    let limited = req
+        .try_clone()
+        .and_then(|req| req.build().ok())
+        .is_some_and(|req| is_opensubtitles(req.url()));
+
+    retrying(limited, || {
+        let req = req.try_clone().expect("requests have no streaming body");
+        async move { check_http(req.send().await) }
+    })
+    .await
+}
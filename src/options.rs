@@ -0,0 +1,123 @@
+//! The options controlling how the subtitles are downloaded
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::api::{Backend, Credentials, Query};
+use crate::convert::Format;
+use crate::filter::Filter;
+use crate::lang::{LangSuffix, LangTags};
+use crate::net;
+use crate::postprocess::{AdFilter, LineEnding};
+use crate::subtitle::Which;
+use crate::template::NameTemplate;
+
+/// The settings of a run, merged from the configuration file and the
+/// command line
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Languages to download subtitles for, comma separated
+    pub langs: String,
+    /// What to search by besides the hash of the files
+    pub query: Query,
+    /// Only the best or all the subtitles
+    pub which: Which,
+    /// Which subtitles are acceptable at all
+    pub filter: Filter,
+    /// How the language is named in the file names
+    pub suffix: LangSuffix,
+    /// BCP 47 tags of the languages
+    pub lang_tags: LangTags,
+    /// How to name the subtitle files, stem.lang.format if not set
+    pub name_template: Option<NameTemplate>,
+    /// Whether to add .sdh to the names of the subtitles for the hearing
+    /// impaired
+    pub mark_sdh: bool,
+    /// The language whose subtitles get .default in their names
+    pub default_lang: Option<String>,
+    /// Whether to transcode the subtitles to UTF-8
+    pub utf8: bool,
+    /// The line endings to write the subtitles with, as they are if not set
+    pub line_ending: Option<LineEnding>,
+    /// Removes the advertisements from the subtitles if set
+    pub strip_ads: Option<AdFilter>,
+    /// Whether to remove the UTF-8 byte order mark
+    pub strip_bom: bool,
+    /// The format to convert the subtitles to, if any
+    pub convert: Option<Format>,
+    /// The extensions of the video files, the other files are skipped
+    pub extensions: Vec<String>,
+    /// Where to write the subtitles, next to the videos if not set
+    pub output_dir: Option<PathBuf>,
+    /// Collect the subtitles into this zip file instead
+    pub archive: Option<PathBuf>,
+    /// Whether to mux the subtitles into the MKV videos
+    pub mux: bool,
+    /// Whether to remove the subtitle files once muxed
+    pub remove_muxed: bool,
+    /// The API to use
+    pub backend: Backend,
+    /// The names of the providers to search, see the provider module
+    pub providers: Vec<String>,
+    /// The command to search as a provider too, see the external module
+    pub provider_cmd: Option<PathBuf>,
+    /// Log in as a registered user if set
+    pub credentials: Option<Credentials>,
+    /// The user agent to identify as
+    pub user_agent: String,
+    /// How the requests are made
+    pub net: net::Settings,
+    /// Whether to show the comments of the top candidates first
+    pub with_comments: bool,
+    /// In upgrade mode existing subtitles are only replaced when the new
+    /// one scores higher than the recorded one by at least this margin
+    pub upgrade: Option<f64>,
+    /// How long the search results are kept, None if they aren't
+    pub cache_ttl: Option<Duration>,
+    /// Whether to search again instead of using the results kept
+    pub refresh: bool,
+    /// Whether to leave the caches alone altogether
+    pub no_cache: bool,
+    /// The languages not downloaded for the videos that have subtitles in
+    /// them embedded
+    pub skip_embedded: Vec<String>,
+    /// Whether to keep the existing subtitles instead of overwriting them
+    pub skip_existing: bool,
+    /// Only print what would be downloaded
+    pub dry_run: bool,
+    /// Report the results as JSON instead of text
+    pub json: bool,
+    /// Whether to download the subtitles with the DownloadSubtitles call of
+    /// the XML-RPC API instead of their links
+    pub rpc_download: bool,
+    /// Whether to switch to the VIP endpoint for the VIP members
+    pub vip_endpoint: bool,
+    /// How many subtitles to download at the same time
+    pub jobs: usize,
+    /// Whether to report the time spent per phase
+    pub timings: bool,
+    /// Prioritize the files on this Trakt user's lists
+    pub trakt_user: Option<String>,
+    /// The Trakt API client id
+    pub trakt_client_id: Option<String>,
+    /// Only process the files on the Trakt lists
+    pub trakt_only: bool,
+    /// Queue the languages not found to search for them again after this
+    /// long, see the retry module
+    pub retry_missing: Option<Duration>,
+    /// How long to keep searching for the languages queued
+    pub retry_for: Duration,
+    /// The file recording the inputs processed, to skip them when run
+    /// again, see the state module
+    pub state: Option<PathBuf>,
+    /// The command run after each subtitle written, see the hook module
+    pub exec: Option<String>,
+    /// Whether to write the metadata of the subtitles next to them, see
+    /// the metadata module
+    pub metadata: bool,
+    /// The CSV file to write the results into too
+    pub report: Option<PathBuf>,
+    /// Whether to add the hashes of the videos only found by their names
+    /// to the database, see the contribute module
+    pub contribute_hashes: bool,
+}
@@ -0,0 +1,66 @@
+//! Where the downloaded subtitles are written
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Component, Path};
+
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+use crate::error::Error;
+
+/// The destination of the subtitles
+pub enum Output {
+    /// Files at the given paths
+    Files,
+    /// Members of a zip archive, named after the given paths
+    Archive(Box<ZipWriter<File>>),
+    /// Nowhere, for dry runs that would collect them into an archive
+    Nothing,
+}
+
+/// Turns the path into an archive member name, keeping only its
+/// relative, normal components
+fn member_name(path: &Path) -> String {
+    let parts: Vec<_> = path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    parts.join("/")
+}
+
+impl Output {
+    /// Creates a zip archive to collect the subtitles into
+    pub fn archive(path: &OsStr) -> Result<Output, Error> {
+        Ok(Output::Archive(Box::new(ZipWriter::new(File::create(
+            path,
+        )?))))
+    }
+
+    /// Writes the data of a subtitle
+    pub fn write(&mut self, path: &Path, data: &[u8]) -> Result<(), Error> {
+        match self {
+            Output::Files => File::create(path)?.write_all(data)?,
+            Output::Archive(ref mut zip) => {
+                zip.start_file(member_name(path), SimpleFileOptions::default())?;
+                zip.write_all(data)?;
+            }
+            Output::Nothing => {}
+        }
+
+        Ok(())
+    }
+
+    /// Completes the output, writing the archive directory if any
+    pub fn finish(self) -> Result<(), Error> {
+        if let Output::Archive(zip) = self {
+            zip.finish()?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+//! Per-user directories, following the XDG base directory specification
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Returns the ostdl directory under the directory in the environment
+/// variable, or under the fallback relative to the home directory
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    env::var_os(var)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME")
+                .or_else(|| env::var_os("USERPROFILE"))
+                .map(|home| Path::new(&home).join(fallback))
+        })
+        .map(|dir| dir.join("ostdl"))
+}
+
+/// The directory for persistent data, e.g. ~/.local/share/ostdl
+pub fn data_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// The directory for the configuration, e.g. ~/.config/ostdl
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// The directory for cached data, e.g. ~/.cache/ostdl
+pub fn cache_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
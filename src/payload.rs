@@ -0,0 +1,144 @@
+//! Unpacking the subtitles as served: gzipped by the XML-RPC API, as they
+//! are by the REST API, and now and then zipped
+
+use std::borrow::Cow;
+use std::io::{Cursor, Read, Write};
+use std::mem;
+use std::path::Path;
+
+use flate2::write::GzDecoder;
+use zip::ZipArchive;
+
+use crate::error::Error;
+
+/// The first bytes of gzipped data
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+
+/// The first bytes of a zip archive
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// How many bytes are looked at to tell whether the data is text
+const SNIFF_LEN: usize = 4096;
+
+/// The extensions of the subtitles in the archives, the other files are
+/// e.g. .nfo files
+const SUBTITLE_EXTENSIONS: &[&str] = &["srt", "sub", "ass", "ssa", "vtt", "smi", "txt", "mpl"];
+
+/// Unpacks the subtitle, telling how it was packed from its first bytes.
+/// Of the files of a zip archive the one in the format given is taken, or
+/// else the first subtitle; the format may be empty if unknown.
+pub fn unpack(data: &[u8], format: &str) -> Result<Vec<u8>, Error> {
+    let mut unpacker = Unpacker::new(format);
+    unpacker.feed(data)?;
+    unpacker.finish()
+}
+
+/// How the data arrived so far is unpacked
+enum State {
+    /// Too little has arrived to tell how it is packed
+    Unknown(Vec<u8>),
+    /// Gzipped, decompressed as it arrives
+    Gzip(Box<GzDecoder<Vec<u8>>>),
+    /// Zipped or not packed, kept until all of it has arrived
+    Whole(Vec<u8>),
+}
+
+/// Unpacks a subtitle while it is being downloaded, decompressing gzipped
+/// data chunk by chunk instead of keeping all of it first
+pub struct Unpacker<'a> {
+    format: &'a str,
+    state: State,
+}
+
+impl<'a> Unpacker<'a> {
+    /// Unpacks the subtitle in the format given, see unpack
+    pub fn new(format: &'a str) -> Unpacker<'a> {
+        Unpacker {
+            format,
+            state: State::Unknown(Vec::new()),
+        }
+    }
+
+    /// Takes the next chunk of the data
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        match self.state {
+            State::Unknown(ref mut head) => {
+                head.extend_from_slice(chunk);
+                if head.len() >= GZIP_MAGIC.len() {
+                    let head = mem::take(head);
+                    self.state = if head.starts_with(GZIP_MAGIC) {
+                        let mut decoder = Box::new(GzDecoder::new(Vec::new()));
+                        decoder.write_all(&head).map_err(Error::Decompress)?;
+                        State::Gzip(decoder)
+                    } else {
+                        State::Whole(head)
+                    };
+                }
+            }
+            State::Gzip(ref mut decoder) => decoder.write_all(chunk).map_err(Error::Decompress)?,
+            State::Whole(ref mut data) => data.extend_from_slice(chunk),
+        }
+
+        Ok(())
+    }
+
+    /// Returns the unpacked subtitle once all the data has arrived
+    pub fn finish(self) -> Result<Vec<u8>, Error> {
+        match self.state {
+            State::Gzip(decoder) => decoder.finish().map_err(Error::Decompress),
+            State::Unknown(data) | State::Whole(data) => {
+                if data.starts_with(ZIP_MAGIC) {
+                    unzip(&data, self.format)
+                } else if is_text(&data) {
+                    Ok(data)
+                } else {
+                    Err(Error::Invalid(
+                        "the downloaded subtitle is neither gzipped, zipped nor text".into(),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Whether the data looks like text: UTF-16 or without NUL bytes
+fn is_text(data: &[u8]) -> bool {
+    data.starts_with(b"\xff\xfe")
+        || data.starts_with(b"\xfe\xff")
+        || !data[..data.len().min(SNIFF_LEN)].contains(&0)
+}
+
+/// Extracts the subtitle from the zip archive
+fn unzip(data: &[u8], format: &str) -> Result<Vec<u8>, Error> {
+    let broken = |e| Error::Invalid(format!("the downloaded zip archive is broken: {}", e).into());
+    let mut archive = ZipArchive::new(Cursor::new(data)).map_err(broken)?;
+
+    let extension = |name: &str| {
+        Path::new(name)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default()
+    };
+    let names = archive
+        .file_names()
+        .map(|name| name.map(Cow::into_owned))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(broken)?;
+    let name = names
+        .iter()
+        .find(|name| !format.is_empty() && extension(name).eq_ignore_ascii_case(format))
+        .or_else(|| {
+            names
+                .iter()
+                .find(|name| SUBTITLE_EXTENSIONS.contains(&extension(name).as_str()))
+        })
+        .ok_or_else(|| Error::Invalid("the downloaded zip archive has no subtitle in it".into()))?;
+
+    let mut content = Vec::new();
+    archive
+        .by_name(name)
+        .map_err(broken)?
+        .read_to_end(&mut content)?;
+
+    Ok(content)
+}
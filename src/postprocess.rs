@@ -0,0 +1,154 @@
+//! Adjusting the downloaded subtitles to what the players expect before
+//! writing them
+
+use std::str::FromStr;
+
+use regex::bytes::RegexSet;
+
+use crate::error::Error;
+
+/// The UTF-8 byte order mark
+const BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// The lines of the advertisements the sites inject into the subtitles,
+/// unless configured otherwise
+pub const AD_PATTERNS: &[&str] = &[
+    r"(?i)opensubtitles",
+    r"(?i)\b(www\.|https?://)",
+    r"(?i)subtitles? (downloaded|provided|brought to you) (from|by)",
+    r"(?i)advertise your (product|brand)",
+    r"(?i)(become|get) (a )?vip member",
+    r"(?i)^\W*(re)?sync(ed|hed)?( (and|&) correct(ed|ions))? by\b",
+];
+
+/// The line endings to write the subtitles with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    /// \r\n, as on Windows
+    Crlf,
+    /// \n, as on Unix
+    Lf,
+}
+
+impl FromStr for LineEnding {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<LineEnding, Self::Err> {
+        match s {
+            "crlf" => Ok(LineEnding::Crlf),
+            "lf" => Ok(LineEnding::Lf),
+            _ => Err("invalid line endings, use crlf or lf"),
+        }
+    }
+}
+
+impl LineEnding {
+    /// Rewrites all the line endings of the subtitle, \r\n, \n or a lone
+    /// \r, as this one. UTF-16 subtitles are left as they are.
+    pub fn apply(self, data: &[u8]) -> Vec<u8> {
+        if data.starts_with(b"\xff\xfe") || data.starts_with(b"\xfe\xff") {
+            return data.to_vec();
+        }
+
+        let eol: &[u8] = match self {
+            LineEnding::Crlf => b"\r\n",
+            LineEnding::Lf => b"\n",
+        };
+        let mut out = Vec::with_capacity(data.len());
+        let mut bytes = data.iter().peekable();
+        while let Some(&b) = bytes.next() {
+            match b {
+                b'\r' => {
+                    bytes.next_if_eq(&&b'\n');
+                    out.extend_from_slice(eol);
+                }
+                b'\n' => out.extend_from_slice(eol),
+                _ => out.push(b),
+            }
+        }
+        out
+    }
+}
+
+/// Removes the lines of the advertisements from SRT subtitles
+#[derive(Debug, Clone)]
+pub struct AdFilter {
+    patterns: RegexSet,
+}
+
+impl AdFilter {
+    /// The filter removing the lines matching any of the regular
+    /// expressions
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<AdFilter, Error> {
+        let patterns = RegexSet::new(patterns)
+            .map_err(|e| Error::Ost(format!("invalid ad pattern: {}", e).into()))?;
+
+        Ok(AdFilter { patterns })
+    }
+
+    /// Removes the matching lines from the text of the cues, and the cues
+    /// left empty, numbering the rest again
+    pub fn apply(&self, data: &[u8]) -> Vec<u8> {
+        let (bom, data) = match data.strip_prefix(BOM) {
+            Some(data) => (BOM, data),
+            None => (&b""[..], data),
+        };
+        let eol: &[u8] = if data.windows(2).any(|w| w == b"\r\n") {
+            b"\r\n"
+        } else {
+            b"\n"
+        };
+        let lines: Vec<&[u8]> = data
+            .split(|&b| b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .collect();
+
+        let mut out = bom.to_vec();
+        let mut number = 0;
+        for block in lines.split(|line| line.trim_ascii().is_empty()) {
+            if block.is_empty() {
+                continue;
+            }
+            let is_timing = |line: &[u8]| line.windows(3).any(|w| w == b"-->");
+            let (numbered, timing, text) = match block {
+                [_, timing, text @ ..] if is_timing(timing) => (true, timing, text),
+                [timing, text @ ..] if is_timing(timing) => (false, timing, text),
+                // not a cue, kept as it is
+                _ => {
+                    for line in block {
+                        out.extend_from_slice(line);
+                        out.extend_from_slice(eol);
+                    }
+                    out.extend_from_slice(eol);
+                    continue;
+                }
+            };
+            let kept: Vec<&&[u8]> = text
+                .iter()
+                .filter(|line| !self.patterns.is_match(line))
+                .collect();
+            if kept.is_empty() && !text.is_empty() {
+                continue;
+            }
+
+            number += 1;
+            if numbered {
+                out.extend_from_slice(number.to_string().as_bytes());
+                out.extend_from_slice(eol);
+            }
+            for line in std::iter::once(timing).chain(kept) {
+                out.extend_from_slice(line);
+                out.extend_from_slice(eol);
+            }
+            out.extend_from_slice(eol);
+        }
+
+        out
+    }
+}
+
+/// The subtitle without the UTF-8 byte order mark, which some players show
+/// as garbage
+pub fn strip_bom(data: &[u8]) -> &[u8] {
+    data.strip_prefix(BOM).unwrap_or(data)
+}
@@ -0,0 +1,85 @@
+//! Looking into the video files with ffprobe, when it is installed
+
+use std::ffi::OsStr;
+use std::process::{Command, Stdio};
+
+use log::debug;
+
+/// Runs ffprobe on the file, returning what it printed, or None if it is
+/// not installed or fails
+fn ffprobe(fname: &OsStr, args: &[&str]) -> Option<String> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error"])
+        .args(args)
+        .arg(fname)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output();
+
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8(output.stdout).ok(),
+        Ok(output) => {
+            debug!(
+                "ffprobe failed on {}: {}",
+                fname.to_string_lossy(),
+                output.status
+            );
+            None
+        }
+        Err(e) => {
+            debug!("cannot run ffprobe: {}", e);
+            None
+        }
+    }
+}
+
+/// Parses a frame rate as ffprobe prints it, e.g. 24000/1001
+fn parse_rate(rate: &str) -> Option<f64> {
+    let fps = match rate.trim().split_once('/') {
+        Some((num, den)) => num.parse::<f64>().ok()? / den.parse::<f64>().ok()?,
+        None => rate.trim().parse().ok()?,
+    };
+
+    (fps.is_finite() && fps > 0f64).then_some(fps)
+}
+
+/// The frame rate of the first video stream of the file
+pub fn fps(fname: &OsStr) -> Option<f64> {
+    let rate = ffprobe(
+        fname,
+        &[
+            "-select_streams",
+            "v:0",
+            "-show_entries",
+            "stream=r_frame_rate",
+            "-of",
+            "default=noprint_wrappers=1:nokey=1",
+        ],
+    )?;
+
+    parse_rate(&rate)
+}
+
+/// The languages of the subtitle streams in the file as tagged, e.g. eng
+/// or und, or None if it cannot be probed
+pub fn subtitle_langs(fname: &OsStr) -> Option<Vec<String>> {
+    let langs = ffprobe(
+        fname,
+        &[
+            "-select_streams",
+            "s",
+            "-show_entries",
+            "stream_tags=language",
+            "-of",
+            "default=noprint_wrappers=1:nokey=1",
+        ],
+    )?;
+
+    Some(
+        langs
+            .lines()
+            .map(|lang| lang.trim().to_lowercase())
+            .filter(|lang| !lang.is_empty())
+            .collect(),
+    )
+}
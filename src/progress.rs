@@ -0,0 +1,126 @@
+//! A progress line on stderr for runs over many files, counting the files
+//! processed, the subtitles downloaded and the failures
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::Error;
+use crate::report::Reporter;
+use crate::style::{Painter, Style};
+use crate::subtitle::Sub;
+
+/// The width of the bar, when the number of files is known
+const BAR_WIDTH: usize = 24;
+
+/// Whether the progress line is on the screen
+static SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// Removes the progress line, so that something else can be written to the
+/// terminal. It is drawn again with the next result.
+pub fn clear() {
+    if SHOWN.swap(false, Ordering::SeqCst) {
+        eprint!("\r\x1b[K");
+    }
+}
+
+/// Passes the results on to another reporter, keeping the progress line
+/// up to date below them
+pub struct Progress {
+    inner: Box<dyn Reporter>,
+    /// The number of files, if known up front
+    total: Option<usize>,
+    files: usize,
+    downloaded: usize,
+    failed: usize,
+    painter: Painter,
+}
+
+impl Progress {
+    /// Wraps the reporter and shows the progress, meant for when stderr is
+    /// a terminal
+    pub fn new(inner: Box<dyn Reporter>, total: Option<usize>) -> Progress {
+        let progress = Progress {
+            inner,
+            total,
+            files: 0,
+            downloaded: 0,
+            failed: 0,
+            painter: Painter::stderr(),
+        };
+        progress.draw();
+        progress
+    }
+
+    fn draw(&self) {
+        let mut line = String::from("\r\x1b[K");
+        match self.total {
+            Some(total) if total > 0 => {
+                let done = BAR_WIDTH * self.files.min(total) / total;
+                line += &format!(
+                    "[{}{}] {}/{} files",
+                    "=".repeat(done),
+                    " ".repeat(BAR_WIDTH - done),
+                    self.files,
+                    total
+                );
+            }
+            _ => line += &format!("{} files", self.files),
+        }
+        line += &format!(", {} subtitles", self.downloaded);
+        if self.failed > 0 {
+            let failed = format!("{} failed", self.failed);
+            line += &format!(", {}", self.painter.paint(Style::Red, failed));
+        }
+
+        let mut stderr = io::stderr();
+        let _ = write!(stderr, "{}", line);
+        let _ = stderr.flush();
+        SHOWN.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Reporter for Progress {
+    fn error(&mut self, file: &Path, error: &Error) {
+        clear();
+        self.failed += 1;
+        self.inner.error(file, error);
+    }
+
+    fn missing(&mut self, file: &Path, lang: &str, reason: Option<&str>) {
+        clear();
+        self.inner.missing(file, lang, reason);
+    }
+
+    fn kept(&mut self, file: &Path, lang: &str, path: &Path, reason: &str) {
+        clear();
+        self.inner.kept(file, lang, path, reason);
+    }
+
+    fn planned(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        clear();
+        self.inner.planned(file, path, sub);
+    }
+
+    fn saved(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        clear();
+        self.downloaded += 1;
+        self.inner.saved(file, path, sub);
+    }
+
+    fn done(&mut self, file: &Path) {
+        clear();
+        self.files += 1;
+        self.inner.done(file);
+        self.draw();
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        // the final counts stay on the screen
+        if SHOWN.swap(false, Ordering::SeqCst) {
+            eprintln!();
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! The sources of the subtitles. OpenSubtitles, with either of its APIs,
+//! is one of them; the rest of the crate only searches and fetches through
+//! the SubtitleProvider trait.
+
+use std::collections::BTreeMap;
+
+use futures::future::{join_all, BoxFuture};
+use futures::FutureExt;
+use log::warn;
+
+use crate::api::{self, Backend, Comment, Query, Session};
+use crate::error::Error;
+use crate::napiprojekt::Napiprojekt;
+use crate::payload;
+use crate::quota;
+use crate::rest;
+use crate::subtitle::{fetch, xmlrpc_search, xmlrpc_search_batch, Sub, Subs};
+
+/// The names of the built-in providers
+pub const PROVIDERS: &[&str] = &["opensubtitles", "napiprojekt"];
+
+/// A source of subtitles
+pub trait SubtitleProvider: Send + Sync {
+    /// The name of the provider, e.g. in the messages
+    fn name(&self) -> &str;
+
+    /// Searches for the subtitles matching the query in the languages,
+    /// comma separated ids, see the lang module
+    fn search<'a>(&'a self, query: &'a Query, langs: &'a str)
+        -> BoxFuture<'a, Result<Subs, Error>>;
+
+    /// Searches for several queries at once, returning the hits per query,
+    /// or None if the provider cannot, in which case the queries are
+    /// searched for one by one
+    fn search_batch<'a>(
+        &'a self,
+        _queries: &'a [Query],
+        _langs: &'a str,
+    ) -> Option<BoxFuture<'a, Result<Vec<Subs>, Error>>> {
+        None
+    }
+
+    /// Fetches the subtitle found by the search, returning its unpacked
+    /// content
+    fn fetch<'a>(&'a self, sub: &'a Sub) -> BoxFuture<'a, Result<Vec<u8>, Error>>;
+
+    /// The comments of the users on the subtitles, by their ids
+    fn comments<'a>(
+        &'a self,
+        _ids: &'a [&'a str],
+    ) -> BoxFuture<'a, Result<BTreeMap<String, Vec<Comment>>, Error>> {
+        let err = Error::Ost(format!("{} has no comments on the subtitles", self.name()).into());
+        async move { Err(err) }.boxed()
+    }
+
+    /// Reports that the subtitle found by the hash of the video is not for
+    /// the video
+    fn report_wrong<'a>(&'a self, _sub: &'a Sub) -> BoxFuture<'a, Result<(), Error>> {
+        let err = Error::Ost(format!("{} takes no reports of wrong matches", self.name()).into());
+        async move { Err(err) }.boxed()
+    }
+}
+
+/// OpenSubtitles, through the API of the session
+#[derive(Debug, Clone, Copy)]
+pub struct OpenSubtitles<'a> {
+    session: &'a Session,
+    rpc_download: bool,
+}
+
+impl<'a> OpenSubtitles<'a> {
+    /// The provider searching and downloading with the logged in session,
+    /// downloading with the DownloadSubtitles call of the XML-RPC API
+    /// instead of the links if rpc_download is set
+    pub fn new(session: &'a Session, rpc_download: bool) -> OpenSubtitles<'a> {
+        OpenSubtitles {
+            session,
+            rpc_download,
+        }
+    }
+}
+
+impl SubtitleProvider for OpenSubtitles<'_> {
+    fn name(&self) -> &str {
+        "opensubtitles"
+    }
+
+    fn search<'a>(
+        &'a self,
+        query: &'a Query,
+        langs: &'a str,
+    ) -> BoxFuture<'a, Result<Subs, Error>> {
+        async move {
+            match self.session.backend {
+                Backend::XmlRpc => xmlrpc_search(query, langs, &self.session.token).await,
+                Backend::Rest { ref api_key } => {
+                    rest::search(self.session, api_key, query, langs).await
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn search_batch<'a>(
+        &'a self,
+        queries: &'a [Query],
+        langs: &'a str,
+    ) -> Option<BoxFuture<'a, Result<Vec<Subs>, Error>>> {
+        // only the XML-RPC API takes several queries in one request
+        match self.session.backend {
+            Backend::XmlRpc => {
+                Some(xmlrpc_search_batch(queries, langs, &self.session.token).boxed())
+            }
+            Backend::Rest { .. } => None,
+        }
+    }
+
+    fn fetch<'a>(&'a self, sub: &'a Sub) -> BoxFuture<'a, Result<Vec<u8>, Error>> {
+        async move {
+            quota::check()?;
+            match self.session.backend {
+                Backend::XmlRpc => {
+                    let data = if self.rpc_download && !sub.file_id.is_empty() {
+                        payload::unpack(
+                            &api::download_file(&sub.file_id, &self.session.token).await?,
+                            &sub.format,
+                        )?
+                    } else {
+                        fetch(&sub.url, &sub.format).await?
+                    };
+                    quota::used();
+                    Ok(data)
+                }
+                // the server tells how many downloads are left
+                Backend::Rest { ref api_key } => {
+                    let link = rest::download_link(self.session, api_key, &sub.id).await?;
+                    fetch(&link, &sub.format).await
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn comments<'a>(
+        &'a self,
+        ids: &'a [&'a str],
+    ) -> BoxFuture<'a, Result<BTreeMap<String, Vec<Comment>>, Error>> {
+        async move {
+            if self.session.backend != Backend::XmlRpc {
+                return Err("comments are only available with the XML-RPC API".into());
+            }
+            api::get_comments(ids, &self.session.token).await
+        }
+        .boxed()
+    }
+
+    fn report_wrong<'a>(&'a self, sub: &'a Sub) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            if self.session.backend != Backend::XmlRpc || sub.movie_file_id.is_empty() {
+                return Err(Error::Ost(
+                    format!("subtitle {} was not found by the hash", sub.id).into(),
+                ));
+            }
+            api::report_wrong_hash(&sub.movie_file_id, &self.session.token).await
+        }
+        .boxed()
+    }
+}
+
+/// Several providers searched together, the subtitles found being fetched
+/// from the one that found them. Failures of the searches are only
+/// reported as long as one of the providers succeeds.
+pub struct Providers<'a> {
+    providers: Vec<Box<dyn SubtitleProvider + 'a>>,
+    /// The names of the providers joined by +
+    name: String,
+}
+
+impl<'a> Providers<'a> {
+    /// The providers named, see PROVIDERS, OpenSubtitles searching with
+    /// the session
+    pub fn new<S: AsRef<str>>(
+        names: &[S],
+        session: Option<&'a Session>,
+        rpc_download: bool,
+    ) -> Result<Providers<'a>, Error> {
+        let mut providers: Vec<Box<dyn SubtitleProvider + 'a>> = Vec::new();
+        for name in names.iter().map(AsRef::as_ref) {
+            match (name, session) {
+                ("opensubtitles", Some(session)) => {
+                    providers.push(Box::new(OpenSubtitles::new(session, rpc_download)))
+                }
+                ("opensubtitles", None) => return Err("OpenSubtitles needs a session".into()),
+                ("napiprojekt", _) => providers.push(Box::new(Napiprojekt)),
+                _ => {
+                    return Err(Error::Ost(
+                        format!("unknown provider: {}, use {}", name, PROVIDERS.join(" or "))
+                            .into(),
+                    ))
+                }
+            }
+        }
+
+        let name = names
+            .iter()
+            .map(AsRef::as_ref)
+            .collect::<Vec<_>>()
+            .join("+");
+
+        Ok(Providers { providers, name })
+    }
+
+    /// Adds the provider, e.g. an external command, after the others
+    pub fn add(&mut self, provider: Box<dyn SubtitleProvider + 'a>) {
+        if !self.name.is_empty() {
+            self.name.push('+');
+        }
+        self.name.push_str(provider.name());
+        self.providers.push(provider);
+    }
+
+    /// The results of the providers that succeeded, reporting the
+    /// failures unless all of them failed
+    fn succeeded<T>(&self, results: Vec<Result<T, Error>>) -> Result<Vec<T>, Error> {
+        let mut found = Vec::new();
+        let mut first_err = None;
+        for (provider, res) in self.providers.iter().zip(results) {
+            match res {
+                Ok(res) => found.push(res),
+                Err(e) if self.providers.len() == 1 => return Err(e),
+                Err(e) => {
+                    warn!("{} search failed: {}", provider.name(), e);
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+
+        match first_err {
+            Some(e) if found.is_empty() => Err(e),
+            _ => Ok(found),
+        }
+    }
+}
+
+impl SubtitleProvider for Providers<'_> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn search<'a>(
+        &'a self,
+        query: &'a Query,
+        langs: &'a str,
+    ) -> BoxFuture<'a, Result<Subs, Error>> {
+        async move {
+            let results = join_all(self.providers.iter().map(|p| p.search(query, langs))).await;
+            Ok(self.succeeded(results)?.into_iter().flatten().collect())
+        }
+        .boxed()
+    }
+
+    fn search_batch<'a>(
+        &'a self,
+        queries: &'a [Query],
+        langs: &'a str,
+    ) -> Option<BoxFuture<'a, Result<Vec<Subs>, Error>>> {
+        if let [provider] = &self.providers[..] {
+            return provider.search_batch(queries, langs);
+        }
+
+        // the providers that cannot search for them at once search one by one
+        Some(
+            async move {
+                let searches = self.providers.iter().map(|p| async move {
+                    match p.search_batch(queries, langs) {
+                        Some(batch) => batch.await,
+                        None => join_all(queries.iter().map(|query| p.search(query, langs)))
+                            .await
+                            .into_iter()
+                            .collect(),
+                    }
+                });
+                let results = join_all(searches).await;
+
+                let mut found = vec![Vec::new(); queries.len()];
+                for per_query in self.succeeded(results)? {
+                    for (subs, new) in found.iter_mut().zip(per_query) {
+                        subs.extend(new);
+                    }
+                }
+
+                Ok(found)
+            }
+            .boxed(),
+        )
+    }
+
+    fn fetch<'a>(&'a self, sub: &'a Sub) -> BoxFuture<'a, Result<Vec<u8>, Error>> {
+        let provider = self
+            .providers
+            .iter()
+            .find(|p| p.name() == sub.provider)
+            .or_else(|| self.providers.first());
+        match provider {
+            Some(provider) => provider.fetch(sub),
+            None => async { Err("no providers".into()) }.boxed(),
+        }
+    }
+
+    fn comments<'a>(
+        &'a self,
+        ids: &'a [&'a str],
+    ) -> BoxFuture<'a, Result<BTreeMap<String, Vec<Comment>>, Error>> {
+        match self.providers.first() {
+            Some(provider) => provider.comments(ids),
+            None => async { Err("no providers".into()) }.boxed(),
+        }
+    }
+
+    fn report_wrong<'a>(&'a self, sub: &'a Sub) -> BoxFuture<'a, Result<(), Error>> {
+        match self.providers.iter().find(|p| p.name() == sub.provider) {
+            Some(provider) => provider.report_wrong(sub),
+            None => async { Err("no providers".into()) }.boxed(),
+        }
+    }
+}
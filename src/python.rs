@@ -0,0 +1,140 @@
+//! Python bindings, built with `--features python` (see pyproject.toml)
+
+use std::path::PathBuf;
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+
+use crate::api::{self, Backend, Credentials, Session, DEFAULT_USER_AGENT};
+use crate::error::Error;
+use crate::filter::Filter;
+use crate::hash::size_and_hash;
+use crate::runtime::block_on;
+use crate::subtitle::{download_to_file, find_subtitles, get_lang, Sub};
+
+create_exception!(ostdl, OstdlError, PyException);
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> PyErr {
+        OstdlError::new_err(e.describe())
+    }
+}
+
+/// A subtitle found by `search`
+#[pyclass(name = "Subtitle", frozen)]
+struct Subtitle(Sub);
+
+#[pymethods]
+impl Subtitle {
+    #[getter]
+    fn id(&self) -> &str {
+        &self.0.id
+    }
+
+    #[getter]
+    fn url(&self) -> &str {
+        &self.0.url
+    }
+
+    #[getter]
+    fn score(&self) -> f64 {
+        self.0.score
+    }
+
+    #[getter]
+    fn lang(&self) -> &str {
+        &self.0.lang
+    }
+
+    #[getter]
+    fn format(&self) -> &str {
+        &self.0.format
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Subtitle(lang={:?}, format={:?}, score={})",
+            self.0.lang, self.0.format, self.0.score
+        )
+    }
+}
+
+/// Returns the (size, hash) of the file, the hash as a hex string
+#[pyfunction]
+fn hash(py: Python, path: PathBuf) -> PyResult<(u64, String)> {
+    let (size, hash) = py.detach(|| size_and_hash(path.as_os_str()).map_err(Error::from))?;
+
+    Ok((size, format!("{:016x}", hash)))
+}
+
+/// Logs into the API, anonymously unless a username and password are
+/// given, and returns the access token
+#[pyfunction]
+#[pyo3(signature = (username = None, password = None))]
+fn login(py: Python, username: Option<String>, password: Option<String>) -> PyResult<String> {
+    let creds = username.map(|username| Credentials {
+        username,
+        password: password.unwrap_or_default(),
+    });
+    let session = py.detach(|| {
+        block_on(api::login(
+            &Backend::XmlRpc,
+            creds.as_ref(),
+            DEFAULT_USER_AGENT,
+        ))
+    })?;
+
+    Ok(session.token)
+}
+
+/// Searches for the subtitles of the file in the given (comma separated)
+/// languages, logging in first if no token is given
+#[pyfunction]
+#[pyo3(signature = (path, langs = "eng", token = None))]
+fn search(
+    py: Python,
+    path: PathBuf,
+    langs: &str,
+    token: Option<String>,
+) -> PyResult<Vec<Subtitle>> {
+    let subs = py.detach(|| {
+        let session = match token {
+            Some(token) => Session::from_token(token),
+            None => block_on(api::login(&Backend::XmlRpc, None, DEFAULT_USER_AGENT))?,
+        };
+        block_on(find_subtitles(path.as_os_str(), langs, &session))
+    })?;
+
+    Ok(subs.into_iter().map(Subtitle).collect())
+}
+
+/// Returns the subtitles for the given language, best score first
+#[pyfunction]
+fn rank(subs: Vec<PyRef<Subtitle>>, lang: &str) -> Vec<Subtitle> {
+    let subs: Vec<Sub> = subs.iter().map(|s| s.0.clone()).collect();
+
+    get_lang(&subs, lang, &Filter::default())
+        .into_iter()
+        .map(|s| Subtitle(s.clone()))
+        .collect()
+}
+
+/// Downloads the subtitle into the given file
+#[pyfunction]
+fn download(py: Python, sub: PyRef<Subtitle>, path: PathBuf) -> PyResult<()> {
+    let url = sub.0.url.clone();
+    Ok(py.detach(|| block_on(download_to_file(&url, path.as_os_str())))?)
+}
+
+#[pymodule]
+fn ostdl(m: &Bound<PyModule>) -> PyResult<()> {
+    m.add("OstdlError", m.py().get_type::<OstdlError>())?;
+    m.add_class::<Subtitle>()?;
+    m.add_function(wrap_pyfunction!(hash, m)?)?;
+    m.add_function(wrap_pyfunction!(login, m)?)?;
+    m.add_function(wrap_pyfunction!(search, m)?)?;
+    m.add_function(wrap_pyfunction!(rank, m)?)?;
+    m.add_function(wrap_pyfunction!(download, m)?)?;
+    Ok(())
+}
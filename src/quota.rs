@@ -0,0 +1,44 @@
+//! The downloads left for the day, as reported by OpenSubtitles and
+//! counted down as the subtitles are downloaded
+
+use std::sync::Mutex;
+
+use crate::error::Error;
+
+/// The downloads allowed in 24 hours and how many of them are left
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub remaining: u64,
+    /// None if the server didn't tell
+    pub limit: Option<u64>,
+}
+
+static QUOTA: Mutex<Option<Quota>> = Mutex::new(None);
+
+/// Records the downloads left as reported by the server, keeping the
+/// limit known if it is not given
+pub fn set(remaining: u64, limit: Option<u64>) {
+    let mut quota = QUOTA.lock().unwrap_or_else(|e| e.into_inner());
+    let limit = limit.or_else(|| quota.and_then(|q| q.limit));
+    *quota = Some(Quota { remaining, limit });
+}
+
+/// The quota as known, None if the server hasn't reported it
+pub fn get() -> Option<Quota> {
+    *QUOTA.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Counts down a download
+pub(crate) fn used() {
+    if let Some(ref mut quota) = *QUOTA.lock().unwrap_or_else(|e| e.into_inner()) {
+        quota.remaining = quota.remaining.saturating_sub(1);
+    }
+}
+
+/// Fails without asking the server if no downloads are left
+pub(crate) fn check() -> Result<(), Error> {
+    match get() {
+        Some(Quota { remaining: 0, .. }) => Err(Error::Quota("download limit reached".into())),
+        _ => Ok(()),
+    }
+}
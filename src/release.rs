@@ -0,0 +1,70 @@
+//! The release a video or a subtitle is of, as told by its name, e.g.
+//! Show.S01E02.1080p.AMZN.WEB-DL.DDP5.1.H.264-NTb is the WEB-DL of the
+//! NTb group
+
+/// Where the video was ripped from, with the words naming it
+const SOURCES: &[(&str, &[&str])] = &[
+    ("webdl", &["webdl", "web-dl"]),
+    ("webrip", &["webrip", "web-rip"]),
+    (
+        "bluray",
+        &["bluray", "blu-ray", "bdrip", "brrip", "bdremux"],
+    ),
+    ("hdtv", &["hdtv"]),
+    ("dvdrip", &["dvdrip", "dvd"]),
+];
+
+/// The release of a video, as far as the name tells
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Release {
+    /// The group that released it, e.g. NTb
+    pub group: Option<String>,
+    /// Where it was ripped from, e.g. webdl
+    pub source: Option<&'static str>,
+}
+
+impl Release {
+    /// Parses the release from a file name, with or without the extension
+    pub fn parse(name: &str) -> Release {
+        let stem = match name.trim().rsplit_once('.') {
+            Some((stem, ext))
+                if ext.len() <= 4 && ext.chars().all(|c| c.is_ascii_alphanumeric()) =>
+            {
+                stem
+            }
+            _ => name.trim(),
+        };
+
+        let lower = stem.to_lowercase();
+        let source = SOURCES.iter().find_map(|(source, words)| {
+            lower
+                .split(['.', '_', ' '])
+                .any(|w| words.contains(&w))
+                .then_some(*source)
+        });
+
+        // the group follows the last dash, unless it is part of a tag
+        let group = stem
+            .rsplit_once('-')
+            .map(|(_, group)| group)
+            .filter(|g| !g.is_empty() && g.chars().all(|c| c.is_ascii_alphanumeric()))
+            .filter(|g| !["dl", "rip", "ray"].contains(&g.to_lowercase().as_str()))
+            .map(String::from);
+
+        Release { group, source }
+    }
+
+    /// Whether the other release is the same as this one: by the group if
+    /// known, else by the source. Everything matches a release telling
+    /// neither.
+    pub fn matches(&self, other: &Release) -> bool {
+        match (&self.group, self.source) {
+            (Some(group), _) => other
+                .group
+                .as_ref()
+                .is_some_and(|g| g.eq_ignore_ascii_case(group)),
+            (None, Some(source)) => other.source == Some(source),
+            (None, None) => true,
+        }
+    }
+}
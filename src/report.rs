@@ -0,0 +1,494 @@
+//! Reporting what happened to the processed files, as text for people or
+//! as JSON for other programs, and as CSV for spreadsheets
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use log::{error, info, log_enabled, warn, Level};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::error::Error;
+use crate::lang::LangTags;
+use crate::style::{Painter, Style};
+use crate::subtitle::Sub;
+
+/// Receives the results of processing the files
+pub trait Reporter {
+    /// Processing the file failed
+    fn error(&mut self, file: &Path, error: &Error);
+    /// No subtitles were found for the file in the language, or with the
+    /// reason none of the ones found were accepted
+    fn missing(&mut self, file: &Path, lang: &str, reason: Option<&str>);
+    /// The existing subtitle at the path is kept, for the reason given
+    fn kept(&mut self, file: &Path, lang: &str, path: &Path, reason: &str);
+    /// The subtitle would be written to the path, in a dry run
+    fn planned(&mut self, file: &Path, path: &Path, sub: &Sub);
+    /// The subtitle was written to the path
+    fn saved(&mut self, file: &Path, path: &Path, sub: &Sub);
+    /// Everything about the file has been reported
+    fn done(&mut self, _file: &Path) {}
+}
+
+/// How well a run went, from best to worst. The value is the exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Outcome {
+    /// All the subtitles asked for were downloaded
+    Complete = 0,
+    /// Some of them were not found, or could not be downloaded or written
+    Incomplete = 1,
+    /// Talking to the server failed, or it refused the session
+    Failed = 2,
+}
+
+/// The counts of what happened in a run
+#[derive(Debug, Default, Clone)]
+pub struct Summary {
+    pub files: usize,
+    pub saved: usize,
+    pub planned: usize,
+    pub kept: usize,
+    /// The languages not found, counted per file
+    pub missing: usize,
+    pub errors: usize,
+    /// The files that got no subtitles at all, neither written nor kept
+    pub empty: Vec<PathBuf>,
+}
+
+impl Summary {
+    /// Logs the counts and the files that got nothing, for the runs over
+    /// more than one file
+    pub fn log(&self) {
+        if self.files < 2 {
+            return;
+        }
+        let subtitles = if self.planned > 0 {
+            format!("{} subtitles to download", self.planned)
+        } else {
+            format!("{} subtitles downloaded", self.saved)
+        };
+        info!(
+            "{} files processed, {}, {} kept, {} languages missing, {} errors",
+            self.files, subtitles, self.kept, self.missing, self.errors
+        );
+        if !self.empty.is_empty() {
+            warn!("{} files got nothing:", self.empty.len());
+            for file in &self.empty {
+                warn!("  {}", file.to_string_lossy());
+            }
+        }
+    }
+}
+
+/// Passes the results on to another reporter, working out the outcome and
+/// the summary
+pub struct Tally<'a> {
+    inner: &'a mut dyn Reporter,
+    pub outcome: Outcome,
+    pub summary: Summary,
+    /// The files that got something so far
+    served: HashSet<PathBuf>,
+}
+
+impl<'a> Tally<'a> {
+    pub fn new(inner: &'a mut dyn Reporter) -> Tally<'a> {
+        Tally {
+            inner,
+            outcome: Outcome::Complete,
+            summary: Summary::default(),
+            served: HashSet::new(),
+        }
+    }
+
+    fn worsen(&mut self, outcome: Outcome) {
+        self.outcome = self.outcome.max(outcome);
+    }
+}
+
+impl Reporter for Tally<'_> {
+    fn error(&mut self, file: &Path, error: &Error) {
+        self.worsen(if error.is_remote() {
+            Outcome::Failed
+        } else {
+            Outcome::Incomplete
+        });
+        self.summary.errors += 1;
+        self.inner.error(file, error);
+    }
+
+    fn missing(&mut self, file: &Path, lang: &str, reason: Option<&str>) {
+        self.worsen(Outcome::Incomplete);
+        self.summary.missing += 1;
+        self.inner.missing(file, lang, reason);
+    }
+
+    fn kept(&mut self, file: &Path, lang: &str, path: &Path, reason: &str) {
+        self.summary.kept += 1;
+        self.served.insert(file.to_path_buf());
+        self.inner.kept(file, lang, path, reason);
+    }
+
+    fn planned(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        self.summary.planned += 1;
+        self.served.insert(file.to_path_buf());
+        self.inner.planned(file, path, sub);
+    }
+
+    fn saved(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        self.summary.saved += 1;
+        self.served.insert(file.to_path_buf());
+        self.inner.saved(file, path, sub);
+    }
+
+    fn done(&mut self, file: &Path) {
+        self.summary.files += 1;
+        if !self.served.remove(file) {
+            self.summary.empty.push(file.to_path_buf());
+        }
+        self.inner.done(file);
+    }
+}
+
+/// Reports the written subtitles on stdout, unless quiet, and logs
+/// everything else. On a terminal the results are grouped by file
+/// instead, aligned and colored.
+pub struct Text {
+    quiet: bool,
+    grouped: bool,
+    painter: Painter,
+    /// The results of the files not done yet when grouping
+    rows: BTreeMap<PathBuf, Vec<String>>,
+}
+
+impl Text {
+    pub fn new(quiet: bool) -> Text {
+        Text {
+            quiet,
+            grouped: io::stdout().is_terminal(),
+            painter: Painter::stdout(),
+            rows: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a line to the group of the file
+    fn push(&mut self, file: &Path, line: String) {
+        self.rows.entry(file.to_path_buf()).or_default().push(line);
+    }
+
+    /// Adds a result of the file to its group
+    fn row(&mut self, file: &Path, lang: &str, status: &str, text: &str) {
+        let lang = self.painter.paint(Style::Cyan, format!("{:<4}", lang));
+        self.push(file, format!("  {} {} {}", lang, status, text));
+    }
+
+    fn subtitle(&mut self, file: &Path, path: &Path, sub: &Sub, url: bool) {
+        let score = self
+            .painter
+            .paint(Style::Green, format!("{:>5.1}", sub.score));
+        let mut text = path.to_string_lossy().into_owned();
+        if url {
+            text = format!("{} {}", text, self.painter.paint(Style::Dim, &sub.url));
+        }
+        self.row(file, &sub.lang, &score, &text);
+    }
+}
+
+impl Reporter for Text {
+    fn error(&mut self, file: &Path, error: &Error) {
+        if self.grouped {
+            let line = self
+                .painter
+                .paint(Style::Red, format!("error {}", error.describe()));
+            self.push(file, format!("  {}", line));
+        } else {
+            error!("{}: {}", file.to_string_lossy(), error.describe());
+        }
+    }
+
+    fn missing(&mut self, file: &Path, lang: &str, reason: Option<&str>) {
+        let reason = reason.map(|r| format!(", {}", r)).unwrap_or_default();
+        if !self.grouped {
+            warn!(
+                "{}: No {} subtitles{}",
+                file.to_string_lossy(),
+                lang,
+                reason
+            );
+        } else if log_enabled!(Level::Warn) {
+            let status = self.painter.paint(Style::Yellow, "    -");
+            let text = self
+                .painter
+                .paint(Style::Yellow, format!("no subtitles{}", reason));
+            self.row(file, lang, &status, &text);
+        }
+    }
+
+    fn kept(&mut self, file: &Path, lang: &str, path: &Path, reason: &str) {
+        if !self.grouped {
+            info!("{}: kept, {}", path.to_string_lossy(), reason);
+        } else if log_enabled!(Level::Info) {
+            let status = self.painter.paint(Style::Dim, " kept");
+            let text = format!(
+                "{} {}",
+                path.to_string_lossy(),
+                self.painter.paint(Style::Dim, format!("({})", reason))
+            );
+            self.row(file, lang, &status, &text);
+        }
+    }
+
+    fn planned(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        if self.grouped {
+            self.subtitle(file, path, sub, true);
+        } else {
+            println!("{} {:2.1} {}", path.to_string_lossy(), sub.score, sub.url);
+        }
+    }
+
+    fn saved(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        if self.quiet {
+            return;
+        }
+        if self.grouped {
+            self.subtitle(file, path, sub, false);
+        } else {
+            println!("{} {:2.1}", path.to_string_lossy(), sub.score);
+        }
+    }
+
+    fn done(&mut self, file: &Path) {
+        if let Some(rows) = self.rows.remove(file) {
+            println!(
+                "{}",
+                self.painter.paint(Style::Bold, file.to_string_lossy())
+            );
+            for row in rows {
+                println!("{}", row);
+            }
+        }
+    }
+}
+
+/// The results of a language of a file
+#[derive(Debug, Default, Serialize)]
+struct LangResult {
+    /// The BCP 47 tag of the language, as in the file names
+    tag: String,
+    subtitles: Vec<Value>,
+    kept: Vec<Value>,
+    missing: bool,
+    /// Why none of the subtitles found were accepted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+/// The results of a file
+#[derive(Debug, Default, Serialize)]
+struct FileResult {
+    path: String,
+    langs: BTreeMap<String, LangResult>,
+    errors: Vec<String>,
+}
+
+/// Prints a JSON object per file on stdout when it is done, the languages
+/// keyed by their API ids, e.g.
+///
+/// ```json
+/// {"path": "a.mkv", "langs": {"eng": {"tag": "en", "subtitles": [{"path":
+///  "a.eng.srt", "id": "1954512345", "score": 10.0, "url": "...",
+///  "written": true}], "kept": [], "missing": false}}, "errors": []}
+/// ```
+#[derive(Debug)]
+pub struct Json {
+    files: BTreeMap<PathBuf, FileResult>,
+    tags: LangTags,
+}
+
+impl Json {
+    pub fn new(tags: LangTags) -> Json {
+        Json {
+            files: BTreeMap::new(),
+            tags,
+        }
+    }
+
+    fn file(&mut self, file: &Path) -> &mut FileResult {
+        self.files
+            .entry(file.to_path_buf())
+            .or_insert_with(|| FileResult {
+                path: file.to_string_lossy().into_owned(),
+                ..FileResult::default()
+            })
+    }
+
+    fn lang(&mut self, file: &Path, lang: &str) -> &mut LangResult {
+        let tag = self.tags.tag(lang).to_string();
+        self.file(file)
+            .langs
+            .entry(lang.to_string())
+            .or_insert_with(|| LangResult {
+                tag,
+                ..LangResult::default()
+            })
+    }
+
+    fn subtitle(&mut self, file: &Path, path: &Path, sub: &Sub, written: bool) {
+        let entry = json!({
+            "path": path.to_string_lossy(),
+            "id": sub.id,
+            "score": sub.score,
+            "format": sub.format,
+            "url": sub.url,
+            "written": written,
+        });
+        self.lang(file, &sub.lang).subtitles.push(entry);
+    }
+}
+
+impl Reporter for Json {
+    fn error(&mut self, file: &Path, error: &Error) {
+        self.file(file).errors.push(error.describe());
+    }
+
+    fn missing(&mut self, file: &Path, lang: &str, reason: Option<&str>) {
+        let result = self.lang(file, lang);
+        result.missing = true;
+        result.reason = reason.map(String::from);
+    }
+
+    fn kept(&mut self, file: &Path, lang: &str, path: &Path, reason: &str) {
+        let entry = json!({ "path": path.to_string_lossy(), "reason": reason });
+        self.lang(file, lang).kept.push(entry);
+    }
+
+    fn planned(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        self.subtitle(file, path, sub, false);
+    }
+
+    fn saved(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        self.subtitle(file, path, sub, true);
+    }
+
+    fn done(&mut self, file: &Path) {
+        let result = self.files.remove(file).unwrap_or_else(|| FileResult {
+            path: file.to_string_lossy().into_owned(),
+            ..FileResult::default()
+        });
+        match serde_json::to_string(&result) {
+            Ok(line) => println!("{}", line),
+            Err(e) => error!("{}: {}", file.to_string_lossy(), e),
+        }
+    }
+}
+
+/// Quotes the field for CSV if needed
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Passes the results on to another reporter, writing them into a CSV
+/// file too, a row per subtitle, language missing or error:
+///
+/// ```text
+/// file,lang,status,score,id,path,reason
+/// a.mkv,eng,saved,10.0,1954512345,a.eng.srt,
+/// a.mkv,hun,missing,,,,none of the 2 found are trusted
+/// ```
+///
+/// The status is saved, planned, kept, missing or error. The rows of a file
+/// are flushed when it is done.
+pub struct Csv {
+    inner: Box<dyn Reporter>,
+    out: BufWriter<File>,
+}
+
+impl Csv {
+    /// Wraps the reporter, writing the header into the file
+    pub fn new(inner: Box<dyn Reporter>, file: File) -> Result<Csv, Error> {
+        let mut out = BufWriter::new(file);
+        writeln!(out, "file,lang,status,score,id,path,reason")?;
+
+        Ok(Csv { inner, out })
+    }
+
+    fn row(
+        &mut self,
+        file: &Path,
+        lang: &str,
+        status: &str,
+        sub: Option<&Sub>,
+        path: &str,
+        reason: &str,
+    ) {
+        let (score, id) = match sub {
+            Some(sub) => (format!("{:.1}", sub.score), sub.id.as_str()),
+            None => (String::new(), ""),
+        };
+        let fields = [
+            csv_field(&file.to_string_lossy()),
+            csv_field(lang),
+            status.to_string(),
+            score,
+            csv_field(id),
+            csv_field(path),
+            csv_field(reason),
+        ];
+        if let Err(e) = writeln!(self.out, "{}", fields.join(",")) {
+            warn!("cannot write the report: {}", e);
+        }
+    }
+}
+
+impl Reporter for Csv {
+    fn error(&mut self, file: &Path, error: &Error) {
+        self.row(file, "", "error", None, "", &error.describe());
+        self.inner.error(file, error);
+    }
+
+    fn missing(&mut self, file: &Path, lang: &str, reason: Option<&str>) {
+        self.row(file, lang, "missing", None, "", reason.unwrap_or(""));
+        self.inner.missing(file, lang, reason);
+    }
+
+    fn kept(&mut self, file: &Path, lang: &str, path: &Path, reason: &str) {
+        self.row(file, lang, "kept", None, &path.to_string_lossy(), reason);
+        self.inner.kept(file, lang, path, reason);
+    }
+
+    fn planned(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        self.row(
+            file,
+            &sub.lang,
+            "planned",
+            Some(sub),
+            &path.to_string_lossy(),
+            "",
+        );
+        self.inner.planned(file, path, sub);
+    }
+
+    fn saved(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        self.row(
+            file,
+            &sub.lang,
+            "saved",
+            Some(sub),
+            &path.to_string_lossy(),
+            "",
+        );
+        self.inner.saved(file, path, sub);
+    }
+
+    fn done(&mut self, file: &Path) {
+        if let Err(e) = self.out.flush() {
+            warn!("cannot write the report: {}", e);
+        }
+        self.inner.done(file);
+    }
+}
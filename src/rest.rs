@@ -0,0 +1,339 @@
+//! The REST API of api.opensubtitles.com
+//!
+//! It needs an API key and reports no score, so the score of a subtitle
+//! is derived from whether it matched the hash, its rating and its
+//! download count.
+
+use reqwest::header::{AUTHORIZATION, USER_AGENT};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde_json::{json, Value};
+
+use crate::api::{vip_endpoint, Backend, Credentials, Query, Session};
+use crate::error::{Error, E_INV_REST_RESP};
+use crate::lang::{self, LANGUAGES};
+use crate::net::{api_url, client, send};
+use crate::quota::{self, Quota};
+use crate::subtitle::{is_trusted_rank, Sub, Subs};
+
+/// opensubtitles REST API entry point
+pub(crate) const REST_API_URL: &str = "https://api.opensubtitles.com/api/v1";
+
+/// The entry point for the VIP members
+pub(crate) const REST_VIP_API_URL: &str = "https://vip-api.opensubtitles.com/api/v1";
+
+/// The entry point of the API in use, without the trailing slash
+fn base_url() -> &'static str {
+    match api_url() {
+        Some(url) => url.trim_end_matches('/'),
+        None if vip_endpoint() => REST_VIP_API_URL,
+        None => REST_API_URL,
+    }
+}
+
+/// The REST API uses ISO 639-1 codes, except for a few languages
+fn rest_lang(id: &str) -> Option<&'static str> {
+    match id {
+        "pob" => Some("pt-br"),
+        "por" => Some("pt-pt"),
+        "chi" => Some("zh-cn"),
+        "zht" => Some("zh-tw"),
+        _ => lang::find(id).map(|l| l.alpha2),
+    }
+}
+
+/// Converts a REST API language code back to the id used everywhere else
+fn lang_id(code: &str) -> String {
+    LANGUAGES
+        .iter()
+        .find(|l| rest_lang(l.id) == Some(code))
+        .map_or_else(|| code.to_string(), |l| l.id.to_string())
+}
+
+/// Adds the headers needed by every request
+fn prepare(req: RequestBuilder, session: &Session, api_key: &str) -> RequestBuilder {
+    let req = req
+        .header("Api-Key", api_key)
+        .header(USER_AGENT, session.user_agent.as_str());
+    if session.token.is_empty() {
+        req
+    } else {
+        req.header(AUTHORIZATION, format!("Bearer {}", session.token))
+    }
+}
+
+/// Checks the status of the response, turning the message of failed
+/// requests into an Error
+async fn check(resp: Response) -> Result<Value, Error> {
+    let status = resp.status();
+    let body: Value = resp.json().await.map_err(|_| E_INV_REST_RESP)?;
+
+    let message = body.get("message").and_then(Value::as_str).unwrap_or("");
+    if status.is_success() {
+        Ok(body)
+    } else if status == StatusCode::NOT_ACCEPTABLE {
+        // the download quota is used up
+        quota::set(0, None);
+        Err(Error::Quota(
+            format!("REST request failed: {} {}", status, message).into(),
+        ))
+    } else if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        Err(Error::Auth(
+            format!("REST request failed: {} {}", status, message).into(),
+        ))
+    } else {
+        Err(Error::Ost(
+            format!("REST request failed: {} {}", status, message).into(),
+        ))
+    }
+}
+
+/// logs into the REST API, as a registered user if credentials are given.
+/// Anonymous sessions need no request at all.
+pub(crate) async fn login(
+    api_key: &str,
+    creds: Option<&Credentials>,
+    user_agent: &str,
+) -> Result<Session, Error> {
+    let mut session = Session {
+        token: String::new(),
+        authenticated: false,
+        vip: false,
+        backend: Backend::Rest {
+            api_key: api_key.to_string(),
+        },
+        user_agent: user_agent.to_string(),
+    };
+
+    if let Some(creds) = creds {
+        let req = client()
+            .post(format!("{}/login", base_url()))
+            .json(&json!({ "username": creds.username, "password": creds.password }));
+        let resp = check(send(prepare(req, &session, api_key)).await?).await?;
+
+        session.token = resp
+            .get("token")
+            .and_then(Value::as_str)
+            .ok_or(E_INV_REST_RESP)?
+            .to_string();
+        session.authenticated = true;
+        session.vip = resp
+            .get("user")
+            .and_then(|user| user.get("vip"))
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+    }
+
+    Ok(session)
+}
+
+/// Asks the server whether it still accepts the token of the session
+pub(crate) async fn token_valid(session: &Session, api_key: &str) -> Result<bool, Error> {
+    if session.token.is_empty() {
+        return Ok(true);
+    }
+
+    let req = client().get(format!("{}/infos/user", base_url()));
+    let resp = send(prepare(req, session, api_key)).await?;
+    if resp.status() == StatusCode::UNAUTHORIZED {
+        return Ok(false);
+    }
+    check(resp).await?;
+
+    Ok(true)
+}
+
+/// The downloads left for the day of the logged in user, None for the
+/// anonymous sessions
+pub(crate) async fn quota(session: &Session, api_key: &str) -> Result<Option<Quota>, Error> {
+    if session.token.is_empty() {
+        return Ok(None);
+    }
+
+    let req = client().get(format!("{}/infos/user", base_url()));
+    let resp = check(send(prepare(req, session, api_key)).await?).await?;
+    let data = resp.get("data").ok_or(E_INV_REST_RESP)?;
+    let remaining = match data.get("remaining_downloads").and_then(Value::as_i64) {
+        Some(remaining) => remaining.max(0) as u64,
+        None => return Ok(None),
+    };
+
+    Ok(Some(Quota {
+        remaining,
+        limit: data.get("allowed_downloads").and_then(Value::as_u64),
+    }))
+}
+
+/// Converts a search result into a Sub, if it has all the data needed
+fn result_to_sub(v: &Value) -> Option<Sub> {
+    let attrs = v.get("attributes")?;
+    let file = attrs.get("files")?.as_array()?.first()?;
+
+    let id = file.get("file_id")?.as_u64()?.to_string();
+
+    let url = attrs
+        .get("url")
+        .and_then(Value::as_str)
+        .unwrap_or("")
+        .into();
+
+    let lang = lang_id(
+        attrs
+            .get("language")
+            .and_then(Value::as_str)
+            .unwrap_or("nolang"),
+    );
+
+    let hash_match = attrs
+        .get("moviehash_match")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    let rating = attrs.get("ratings").and_then(Value::as_f64).unwrap_or(0f64);
+    let downloads = attrs
+        .get("download_count")
+        .and_then(Value::as_f64)
+        .unwrap_or(0f64);
+    let score = if hash_match { 10f64 } else { 0f64 } + rating + (downloads + 1f64).log10();
+
+    let uploader = attrs
+        .get("uploader")
+        .and_then(|u| u.get("name"))
+        .and_then(Value::as_str)
+        .unwrap_or("")
+        .into();
+
+    let trusted = attrs
+        .get("from_trusted")
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+        || attrs
+            .get("uploader")
+            .and_then(|u| u.get("rank"))
+            .and_then(Value::as_str)
+            .is_some_and(is_trusted_rank);
+
+    let added = attrs
+        .get("upload_date")
+        .and_then(Value::as_str)
+        .unwrap_or("")
+        .into();
+
+    Some(Sub {
+        file_id: id.clone(),
+        id,
+        url,
+        score,
+        lang,
+        format: "srt".into(),
+        encoding: String::new(),
+        md5: String::new(),
+        uploader,
+        downloads: downloads as u64,
+        rating,
+        added,
+        hearing_impaired: attrs
+            .get("hearing_impaired")
+            .and_then(Value::as_bool)
+            .unwrap_or(false),
+        forced: attrs
+            .get("foreign_parts_only")
+            .and_then(Value::as_bool)
+            .unwrap_or(false),
+        fps: attrs
+            .get("fps")
+            .and_then(Value::as_f64)
+            .filter(|&fps| fps > 0f64),
+        release: attrs
+            .get("release")
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .into(),
+        trusted,
+        machine_translated: ["machine_translated", "ai_translated"]
+            .iter()
+            .any(|field| attrs.get(*field).and_then(Value::as_bool) == Some(true)),
+        cd: 1,
+        cds: 1,
+        provider: "opensubtitles".into(),
+        // the REST API takes no reports of wrong matches
+        movie_file_id: String::new(),
+        imdb_id: attrs
+            .get("feature_details")
+            .and_then(|f| f.get("imdb_id"))
+            .and_then(Value::as_u64),
+    })
+}
+
+/// Searches for the subtitles by the query and languages, fetching all
+/// the pages of the result
+pub(crate) async fn search(
+    session: &Session,
+    api_key: &str,
+    query: &Query,
+    langs: &str,
+) -> Result<Subs, Error> {
+    let mut langs: Vec<&str> = langs.split(',').filter_map(rest_lang).collect();
+    // the API redirects unless the languages are sorted
+    langs.sort_unstable();
+    let langs = langs.join(",");
+    let mut params = vec![("languages", langs)];
+    if let Some((_, hash)) = query.size_hash {
+        params.push(("moviehash", format!("{:016x}", hash)));
+    }
+    if let Some(imdb_id) = query.imdb_id {
+        params.push(("imdb_id", imdb_id.to_string()));
+    }
+    if let Some(ref text) = query.text {
+        params.push(("query", text.clone()));
+    }
+    if let Some(season) = query.season {
+        params.push(("season_number", season.to_string()));
+    }
+    if let Some(episode) = query.episode {
+        params.push(("episode_number", episode.to_string()));
+    }
+
+    let mut subs = Vec::new();
+    let mut page = 1;
+    loop {
+        let req = client()
+            .get(format!("{}/subtitles", base_url()))
+            .query(&params)
+            .query(&[("page", page)]);
+        let resp = check(send(prepare(req, session, api_key)).await?).await?;
+
+        let data = resp
+            .get("data")
+            .and_then(Value::as_array)
+            .ok_or(E_INV_REST_RESP)?;
+        subs.extend(data.iter().filter_map(result_to_sub));
+
+        let total_pages = resp.get("total_pages").and_then(Value::as_u64).unwrap_or(1);
+        if page >= total_pages {
+            break;
+        }
+        page += 1;
+    }
+
+    Ok(subs)
+}
+
+/// Requests the temporary download link of a subtitle file
+pub(crate) async fn download_link(
+    session: &Session,
+    api_key: &str,
+    file_id: &str,
+) -> Result<String, Error> {
+    let file_id: u64 = file_id.parse().map_err(|_| E_INV_REST_RESP)?;
+    let req = client()
+        .post(format!("{}/download", base_url()))
+        .json(&json!({ "file_id": file_id }));
+    let resp = check(send(prepare(req, session, api_key)).await?).await?;
+
+    if let Some(remaining) = resp.get("remaining").and_then(Value::as_i64) {
+        quota::set(remaining.max(0) as u64, None);
+    }
+    resp.get("link")
+        .and_then(Value::as_str)
+        .map(String::from)
+        .ok_or(E_INV_REST_RESP)
+}
@@ -0,0 +1,249 @@
+//! The files some subtitles of which were not found, to search for them
+//! again later, e.g. for new episodes nobody has made subtitles for yet
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::paths::data_dir;
+use crate::report::Reporter;
+use crate::subtitle::Sub;
+
+/// How long the languages queued are searched for by default
+pub const DEFAULT_DEADLINE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How often the languages are searched for by default when the retry
+/// subcommand queues them
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// The languages missing for a file and when to search for them again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// The language ids, see the lang module
+    pub langs: Vec<String>,
+    /// How long to wait between the searches, in seconds
+    pub interval: u64,
+    /// When to search again, in seconds since the epoch
+    pub next: u64,
+    /// When to give up, in seconds since the epoch
+    pub deadline: u64,
+}
+
+/// The current time in seconds since the epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Parses a duration like 90s, 30m, 6h or 7d, a number alone being
+/// minutes
+pub fn parse_duration(s: &str) -> Result<Duration, Error> {
+    let s = s.trim();
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "m"),
+    };
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(n) if n > 0 && secs > 0 => Ok(Duration::from_secs(n * secs)),
+        _ => Err(Error::Ost(
+            format!("invalid duration: {}, e.g. 6h", s).into(),
+        )),
+    }
+}
+
+/// The files to search for again keyed by their absolute path
+#[derive(Debug, Default)]
+pub struct Queue {
+    path: Option<PathBuf>,
+    entries: BTreeMap<String, Entry>,
+}
+
+/// The key of a video
+fn key(path: &Path) -> String {
+    fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl Queue {
+    /// Loads the queue from the data directory, an empty one if it does
+    /// not exist yet
+    pub fn load() -> Result<Queue, Error> {
+        let path = data_dir()
+            .ok_or("cannot find the data directory")?
+            .join("retry.json");
+
+        let entries = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))
+                .map_err(|e| Error::Ost(format!("invalid retry queue: {}", e).into()))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Queue {
+            path: Some(path),
+            entries,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Queues the language of the file to be searched for after the
+    /// interval, until the deadline. The files already queued keep their
+    /// interval and deadline.
+    pub fn missing(&mut self, file: &Path, lang: &str, interval: Duration, deadline: Duration) {
+        let now = now();
+        let entry = self.entries.entry(key(file)).or_insert_with(|| Entry {
+            langs: Vec::new(),
+            interval: interval.as_secs(),
+            next: 0,
+            deadline: now + deadline.as_secs(),
+        });
+        entry.next = now + entry.interval;
+        if !entry.langs.iter().any(|l| l == lang) {
+            entry.langs.push(lang.to_string());
+        }
+    }
+
+    /// Removes the language of the file from the queue
+    pub fn found(&mut self, file: &Path, lang: &str) {
+        let key = key(file);
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.langs.retain(|l| l != lang);
+            if entry.langs.is_empty() {
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    /// Removes the files past their deadline and the ones gone, returning
+    /// them
+    pub fn expire(&mut self) -> Vec<PathBuf> {
+        let now = now();
+        let expired: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(key, entry)| entry.deadline <= now || !Path::new(key).exists())
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            self.entries.remove(key);
+        }
+
+        expired.into_iter().map(PathBuf::from).collect()
+    }
+
+    /// The files due to be searched for again, grouped by the languages
+    /// missing, comma separated. They are postponed by their interval, so
+    /// that the ones failing are not searched for again right away.
+    pub fn take_due(&mut self) -> BTreeMap<String, Vec<PathBuf>> {
+        let now = now();
+        let mut due: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for (key, entry) in self.entries.iter_mut() {
+            if entry.next <= now {
+                entry.next = now + entry.interval;
+                due.entry(entry.langs.join(","))
+                    .or_default()
+                    .push(PathBuf::from(key));
+            }
+        }
+
+        due
+    }
+
+    /// How long until the next file is due, None if the queue is empty
+    pub fn next_due(&self) -> Option<Duration> {
+        let now = now();
+        self.entries
+            .values()
+            .map(|entry| Duration::from_secs(entry.next.saturating_sub(now)))
+            .min()
+    }
+
+    /// Writes the queue back into the data directory
+    pub fn save(&self) -> Result<(), Error> {
+        if let Some(ref path) = self.path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let file = BufWriter::new(File::create(path)?);
+            serde_json::to_writer_pretty(file, &self.entries)
+                .map_err(|e| Error::Ost(format!("cannot write retry queue: {}", e).into()))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Passes the results on to another reporter, queueing the languages not
+/// found and taking the ones found off the queue
+pub struct Recorder<'a> {
+    inner: &'a mut dyn Reporter,
+    queue: &'a mut Queue,
+    interval: Duration,
+    deadline: Duration,
+}
+
+impl<'a> Recorder<'a> {
+    /// The files are searched for again after the interval, until the
+    /// deadline from now
+    pub fn new(
+        inner: &'a mut dyn Reporter,
+        queue: &'a mut Queue,
+        interval: Duration,
+        deadline: Duration,
+    ) -> Recorder<'a> {
+        Recorder {
+            inner,
+            queue,
+            interval,
+            deadline,
+        }
+    }
+}
+
+impl Reporter for Recorder<'_> {
+    fn error(&mut self, file: &Path, error: &Error) {
+        self.inner.error(file, error);
+    }
+
+    fn missing(&mut self, file: &Path, lang: &str, reason: Option<&str>) {
+        self.queue.missing(file, lang, self.interval, self.deadline);
+        self.inner.missing(file, lang, reason);
+    }
+
+    fn kept(&mut self, file: &Path, lang: &str, path: &Path, reason: &str) {
+        self.queue.found(file, lang);
+        self.inner.kept(file, lang, path, reason);
+    }
+
+    fn planned(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        self.inner.planned(file, path, sub);
+    }
+
+    fn saved(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        self.queue.found(file, &sub.lang);
+        self.inner.saved(file, path, sub);
+    }
+
+    fn done(&mut self, file: &Path) {
+        self.inner.done(file);
+    }
+}
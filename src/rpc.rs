@@ -0,0 +1,202 @@
+//! JSON-RPC 2.0 server over line delimited streams, used by `ostdl rpc`
+//!
+//! Every request is a single line of JSON, every response is written as
+//! a single line. Supported methods:
+//!
+//! * `identify` `{"path"}` → `{"size", "hash"}`, the path being a file or a URL
+//! * `search` `{"path", "langs"}` →
+//!   `[{"id", "url", "score", "lang", "tag", "format"}]`, `tag` being the
+//!   BCP 47 tag of the language
+//! * `download` `{"url", "path"}` → `null`
+
+use serde_json::{json, Map, Value};
+use std::ffi::OsStr;
+use std::time::Instant;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::api::{keep_alive, login, Backend, Credentials, Session, KEEP_ALIVE};
+use crate::error::Error;
+use crate::hash::search_hash;
+use crate::lang::LangTags;
+use crate::options::Options;
+use crate::subtitle::{download_to_file, find_subtitles};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// An error to be sent back to the client
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new<S: Into<String>>(code: i64, message: S) -> RpcError {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<Error> for RpcError {
+    fn from(e: Error) -> RpcError {
+        RpcError::new(SERVER_ERROR, e.describe())
+    }
+}
+
+/// State kept between requests
+struct Server {
+    backend: Backend,
+    credentials: Option<Credentials>,
+    user_agent: String,
+    session: Option<Session>,
+    /// When the session was last used
+    used: Option<Instant>,
+    lang_tags: LangTags,
+}
+
+/// Returns a string parameter
+fn param<'a>(params: &'a Map<String, Value>, name: &str) -> Result<&'a str, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("missing parameter: {}", name)))
+}
+
+impl Server {
+    /// Logs in on first use, then reuses the session, keeping it alive
+    /// when it has not been used for a while
+    async fn session(&mut self) -> Result<&Session, Error> {
+        match self.session {
+            None => {
+                let session =
+                    login(&self.backend, self.credentials.as_ref(), &self.user_agent).await?;
+                self.session = Some(session);
+            }
+            Some(ref mut session) if self.used.is_some_and(|t| t.elapsed() >= KEEP_ALIVE) => {
+                keep_alive(session, self.credentials.as_ref()).await?
+            }
+            Some(_) => {}
+        }
+        self.used = Some(Instant::now());
+
+        Ok(self.session.as_ref().expect("logged in"))
+    }
+
+    async fn identify(&mut self, params: &Map<String, Value>) -> Result<Value, RpcError> {
+        let path = param(params, "path")?;
+        let (size, hash) = search_hash(OsStr::new(path)).await?;
+
+        Ok(json!({ "size": size, "hash": format!("{:016x}", hash) }))
+    }
+
+    async fn search(&mut self, params: &Map<String, Value>) -> Result<Value, RpcError> {
+        let path = param(params, "path")?;
+        let langs = params.get("langs").and_then(Value::as_str).unwrap_or("eng");
+        let subs = find_subtitles(OsStr::new(path), langs, self.session().await?).await?;
+
+        let subs: Vec<Value> = subs
+            .iter()
+            .map(|s| json!({ "id": s.id, "url": s.url, "score": s.score, "lang": s.lang, "tag": self.lang_tags.tag(&s.lang), "format": s.format }))
+            .collect();
+        Ok(Value::Array(subs))
+    }
+
+    async fn download(&mut self, params: &Map<String, Value>) -> Result<Value, RpcError> {
+        let url = param(params, "url")?;
+        let path = param(params, "path")?;
+        download_to_file(url, OsStr::new(path)).await?;
+
+        Ok(Value::Null)
+    }
+
+    /// Handles a single request line, returns the response if one is due
+    async fn handle(&mut self, line: &str) -> Option<Value> {
+        let req: Value = match serde_json::from_str(line) {
+            Ok(req) => req,
+            Err(e) => {
+                return Some(error_response(
+                    Value::Null,
+                    RpcError::new(PARSE_ERROR, e.to_string()),
+                ))
+            }
+        };
+
+        let id = req.get("id").cloned();
+        let method = match req.get("method").and_then(Value::as_str) {
+            Some(method) => method,
+            None => {
+                let err = RpcError::new(INVALID_REQUEST, "invalid request");
+                return Some(error_response(id.unwrap_or(Value::Null), err));
+            }
+        };
+        let empty = Map::new();
+        let params = match req.get("params") {
+            Some(Value::Object(params)) => params,
+            None => &empty,
+            Some(_) => {
+                let err = RpcError::new(INVALID_PARAMS, "params must be an object");
+                return id.map(|id| error_response(id, err));
+            }
+        };
+
+        let res = match method {
+            "identify" => self.identify(params).await,
+            "search" => self.search(params).await,
+            "download" => self.download(params).await,
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method: {}", method),
+            )),
+        };
+
+        // requests without an id are notifications, they get no response
+        let id = id?;
+        Some(match res {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(err) => error_response(id, err),
+        })
+    }
+}
+
+fn error_response(id: Value, err: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": err.code, "message": err.message },
+    })
+}
+
+/// Serves requests read from the input until it is closed, logging in
+/// with the API, credentials and user agent of the options
+pub async fn serve<R, W>(input: R, mut output: W, opts: &Options) -> Result<(), Error>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut server = Server {
+        backend: opts.backend.clone(),
+        credentials: opts.credentials.clone(),
+        user_agent: opts.user_agent.clone(),
+        session: None,
+        used: None,
+        lang_tags: opts.lang_tags.clone(),
+    };
+
+    let mut lines = input.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(resp) = server.handle(&line).await {
+            output.write_all(format!("{}\n", resp).as_bytes()).await?;
+            output.flush().await?;
+        }
+    }
+
+    Ok(())
+}
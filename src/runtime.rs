@@ -0,0 +1,20 @@
+//! The runtime the blocking interfaces run the async API on
+
+use std::future::Future;
+use std::sync::OnceLock;
+
+use tokio::runtime::{Builder, Runtime};
+
+/// Runs the future to completion on the runtime shared by the calls
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+    RUNTIME
+        .get_or_init(|| {
+            Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .expect("failed to start the runtime")
+        })
+        .block_on(future)
+}
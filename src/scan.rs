@@ -0,0 +1,173 @@
+//! Finding the video files to process
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+use glob::glob;
+use log::debug;
+
+use crate::error::print_err;
+
+/// The extensions of the video files processed, unless given by --ext
+pub const VIDEO_EXTENSIONS: &[&str] = &[
+    "3g2", "3gp", "avi", "divx", "flv", "m2ts", "m4v", "mkv", "mov", "mp4", "mpeg", "mpg", "mts",
+    "ogm", "ogv", "rm", "rmvb", "ts", "vob", "webm", "wmv",
+];
+
+/// The video extensions, unless given by --ext
+pub fn default_extensions() -> Vec<String> {
+    VIDEO_EXTENSIONS.iter().map(|ext| ext.to_string()).collect()
+}
+
+/// Parses the comma separated list of extensions, with or without the
+/// leading dots; * stands for any extension
+pub fn parse_extensions(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+        .filter(|ext| !ext.is_empty())
+        .collect()
+}
+
+/// Checks whether the file has one of the video extensions
+pub fn is_video(path: &Path, extensions: &[String]) -> bool {
+    if extensions.iter().any(|ext| ext == "*") {
+        return true;
+    }
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| extensions.contains(&ext))
+}
+
+/// Collects the video files in the directory, descending into the
+/// subdirectories if recursive
+fn scan_dir(
+    dir: &Path,
+    recursive: bool,
+    extensions: &[String],
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        // symlinked directories are not followed to avoid loops
+        if entry.file_type()?.is_dir() {
+            if recursive {
+                if let Err(e) = scan_dir(&path, recursive, extensions, files) {
+                    print_err(format!("{}: {}", path.to_string_lossy(), e));
+                }
+            }
+        } else if path.is_file() && is_video(&path, extensions) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Expands the input if it is a glob pattern not naming an existing file,
+/// for when the shell doesn't do it. Patterns without matches are kept as
+/// they are so the error is reported for them later.
+fn expand_glob(input: PathBuf) -> Vec<PathBuf> {
+    let pattern = match input.to_str() {
+        Some(p) if !input.exists() && p.contains(&['*', '?', '['][..]) => p,
+        _ => return vec![input],
+    };
+
+    let paths: Vec<PathBuf> = match glob(pattern) {
+        Ok(paths) => paths
+            .filter_map(|res| res.map_err(|e| print_err(e.to_string())).ok())
+            .collect(),
+        Err(e) => {
+            print_err(format!("{}: {}", pattern, e));
+            Vec::new()
+        }
+    };
+
+    if paths.is_empty() {
+        vec![input]
+    } else {
+        paths
+    }
+}
+
+/// Replaces the directory with the video files in it (in the whole tree
+/// if recursive). Other files are dropped unless they are videos or all
+/// files are kept, the rest of the inputs (e.g. URLs) are kept as they are.
+fn expand_dir(
+    input: PathBuf,
+    recursive: bool,
+    extensions: &[String],
+    all_files: bool,
+) -> Vec<PathBuf> {
+    if input.is_file() {
+        if all_files || is_video(&input, extensions) {
+            return vec![input];
+        }
+        debug!("{}: skipped, not a video", input.to_string_lossy());
+        return Vec::new();
+    }
+    if !input.is_dir() {
+        return vec![input];
+    }
+
+    let mut files = Vec::new();
+    if let Err(e) = scan_dir(&input, recursive, extensions, &mut files) {
+        print_err(format!("{}: {}", input.to_string_lossy(), e));
+    }
+
+    files
+}
+
+/// Expands the inputs: glob patterns are expanded, files are kept if they
+/// have one of the extensions or all_files is set, directories are
+/// replaced with the video files in them (in the whole tree if recursive).
+/// The inputs are expanded as they are consumed.
+pub fn expand<I: IntoIterator<Item = PathBuf>>(
+    inputs: I,
+    recursive: bool,
+    extensions: Vec<String>,
+    all_files: bool,
+) -> impl Iterator<Item = PathBuf> {
+    inputs
+        .into_iter()
+        .flat_map(expand_glob)
+        .flat_map(move |input| expand_dir(input, recursive, &extensions, all_files))
+}
+
+/// Converts a raw entry of a file list into a path
+#[cfg(unix)]
+fn bytes_to_path(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+/// Converts a raw entry of a file list into a path
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Reads the paths listed one per line, or NUL separated (as printed by
+/// find -print0), as they are consumed. Reading stops at the first error.
+pub fn read_list<R: BufRead>(reader: R, null_separated: bool) -> impl Iterator<Item = PathBuf> {
+    let separator = if null_separated { b'\0' } else { b'\n' };
+
+    reader
+        .split(separator)
+        .map_while(|res| res.map_err(|e| print_err(format!("file list: {}", e))).ok())
+        .filter_map(move |mut entry| {
+            if !null_separated && entry.last() == Some(&b'\r') {
+                entry.pop();
+            }
+            if entry.is_empty() {
+                None
+            } else {
+                Some(bytes_to_path(entry))
+            }
+        })
+}
@@ -0,0 +1,109 @@
+//! The inputs a batch has processed, so that an interrupted run can go on
+//! where it left off
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+use crate::error::Error;
+use crate::report::Reporter;
+use crate::subtitle::Sub;
+
+/// The key of an input, its absolute path if it exists
+pub fn key(path: &Path) -> String {
+    fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The state file, listing the inputs processed one per line. The lines
+/// are appended as the inputs are done, so that nothing is lost when the
+/// run is killed.
+#[derive(Debug)]
+pub struct State {
+    file: File,
+    processed: HashSet<String>,
+}
+
+impl State {
+    /// Opens the state file, creating it if it does not exist yet
+    pub fn open(path: &Path) -> Result<State, Error> {
+        let processed = match File::open(path) {
+            Ok(file) => BufReader::new(file)
+                .lines()
+                .collect::<Result<HashSet<String>, _>>()?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(State { file, processed })
+    }
+
+    /// The inputs processed by the earlier runs, to be skipped
+    pub fn take_processed(&mut self) -> HashSet<String> {
+        mem::take(&mut self.processed)
+    }
+
+    /// Records the input as processed, problems only being logged
+    fn record(&mut self, file: &Path) {
+        let line = format!("{}\n", key(file));
+        if let Err(e) = self.file.write_all(line.as_bytes()) {
+            warn!("cannot record {} in the state file: {}", line.trim_end(), e);
+        }
+    }
+}
+
+/// Passes the results on to another reporter, recording the inputs done
+/// without errors in the state
+pub struct Tracker<'a> {
+    inner: &'a mut dyn Reporter,
+    state: &'a mut State,
+    /// The inputs that had errors, they are tried again by the next run
+    failed: HashSet<PathBuf>,
+}
+
+impl<'a> Tracker<'a> {
+    pub fn new(inner: &'a mut dyn Reporter, state: &'a mut State) -> Tracker<'a> {
+        Tracker {
+            inner,
+            state,
+            failed: HashSet::new(),
+        }
+    }
+}
+
+impl Reporter for Tracker<'_> {
+    fn error(&mut self, file: &Path, error: &Error) {
+        self.failed.insert(file.to_path_buf());
+        self.inner.error(file, error);
+    }
+
+    fn missing(&mut self, file: &Path, lang: &str, reason: Option<&str>) {
+        self.inner.missing(file, lang, reason);
+    }
+
+    fn kept(&mut self, file: &Path, lang: &str, path: &Path, reason: &str) {
+        self.inner.kept(file, lang, path, reason);
+    }
+
+    fn planned(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        self.inner.planned(file, path, sub);
+    }
+
+    fn saved(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        self.inner.saved(file, path, sub);
+    }
+
+    fn done(&mut self, file: &Path) {
+        if !self.failed.remove(file) {
+            self.state.record(file);
+        }
+        self.inner.done(file);
+    }
+}
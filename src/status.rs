@@ -0,0 +1,92 @@
+//! The subtitles the videos already have next to them, found by their
+//! names without asking the servers
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::lang::{self, LangSuffix, LangTags};
+
+/// The extensions of the subtitle files looked for
+pub const SUBTITLE_EXTENSIONS: &[&str] = &["srt", "sub", "ass", "ssa", "vtt", "smi", "idx", "sup"];
+
+/// A subtitle file next to a video
+#[derive(Debug, Clone)]
+pub struct Sidecar {
+    pub path: PathBuf,
+    /// The parts of the name between the name of the video and the
+    /// extension, e.g. eng and forced for movie.eng.forced.srt
+    pub tags: Vec<String>,
+}
+
+impl Sidecar {
+    /// Whether the subtitle is in the language, named either way ostdl
+    /// names them or by any other code of the language
+    pub fn is_in(&self, lang: &str, suffix: LangSuffix, tags: &LangTags) -> bool {
+        let named = suffix.apply(lang, tags);
+        self.tags
+            .iter()
+            .any(|tag| tag.eq_ignore_ascii_case(&named) || lang::resolve(tag).ok() == Some(lang))
+    }
+
+    /// The language of the subtitle, if its name tells
+    pub fn lang(&self) -> Option<&'static str> {
+        self.tags.iter().find_map(|tag| lang::resolve(tag).ok())
+    }
+}
+
+/// Strips the index of the subtitles downloaded with --all, e.g. eng-2
+fn strip_index(tag: &str) -> &str {
+    match tag.rsplit_once('-') {
+        Some((lang, idx)) if !idx.is_empty() && idx.chars().all(|c| c.is_ascii_digit()) => lang,
+        _ => tag,
+    }
+}
+
+/// The subtitle files named after the video, in the directory given or
+/// next to the video, sorted by name
+pub fn sidecars(video: &Path, dir: Option<&Path>) -> Vec<Sidecar> {
+    let stem = match video.file_stem() {
+        Some(stem) => stem.to_string_lossy().into_owned(),
+        None => return Vec::new(),
+    };
+    let dir = dir
+        .or_else(|| video.parent())
+        .unwrap_or_else(|| Path::new(""));
+    // the paths are kept relative, as the videos are given
+    let listed = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let entries = match fs::read_dir(listed) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut found: Vec<Sidecar> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let rest = name.strip_prefix(&stem)?;
+            let (middle, extension) = rest.rsplit_once('.')?;
+            if !SUBTITLE_EXTENSIONS.contains(&extension.to_lowercase().as_str()) {
+                return None;
+            }
+            // movie.eng.srt, or movie.srt without a language
+            if !middle.is_empty() && !middle.starts_with('.') {
+                return None;
+            }
+            Some(Sidecar {
+                path: dir.join(entry.file_name()),
+                tags: middle
+                    .split('.')
+                    .filter(|tag| !tag.is_empty())
+                    .map(|tag| strip_index(tag).to_string())
+                    .collect(),
+            })
+        })
+        .collect();
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+
+    found
+}
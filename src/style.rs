@@ -0,0 +1,66 @@
+//! Colors for the terminal, used only when the output is a terminal and
+//! NO_COLOR is not set, see https://no-color.org
+
+use std::env;
+use std::fmt::Display;
+use std::io::{self, IsTerminal};
+
+/// The styles used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Bold,
+    Dim,
+    Red,
+    Green,
+    Yellow,
+    Cyan,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Bold => "1",
+            Style::Dim => "2",
+            Style::Red => "31",
+            Style::Green => "32",
+            Style::Yellow => "33",
+            Style::Cyan => "36",
+        }
+    }
+}
+
+/// Styles text written to a stream if it is a colored terminal
+#[derive(Debug, Clone, Copy)]
+pub struct Painter {
+    enabled: bool,
+}
+
+/// Whether colors are allowed at all
+fn allowed() -> bool {
+    env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+}
+
+impl Painter {
+    /// A painter for stdout
+    pub fn stdout() -> Painter {
+        Painter {
+            enabled: allowed() && io::stdout().is_terminal(),
+        }
+    }
+
+    /// A painter for stderr
+    pub fn stderr() -> Painter {
+        Painter {
+            enabled: allowed() && io::stderr().is_terminal(),
+        }
+    }
+
+    /// Returns the text in the style, or as it is without colors
+    pub fn paint<T: Display>(self, style: Style, text: T) -> String {
+        if self.enabled {
+            format!("\x1b[{}m{}\x1b[0m", style.code(), text)
+        } else {
+            text.to_string()
+        }
+    }
+}
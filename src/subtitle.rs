@@ -1,37 +1,229 @@
-use std::cmp::Ordering;
-use std::ffi::{OsStr, OsString};
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::Read;
-use std::path::PathBuf;
-
-use libflate::gzip::Decoder;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::time::Duration;
+
+use futures::future::{join_all, try_join_all};
+use futures::stream::{self, StreamExt};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
 use xmlrpc::{Request, Value};
 
-use crate::api::{make_req, val_to_response, OST_API_URL};
-use crate::error::{print_err, print_if_err, Error, E_INV_RESP};
-use crate::hash::size_and_hash;
+use crate::api::{
+    call, invalid_response, make_reqs, make_search_opts, val_to_response, Query, Session,
+    SEARCH_LIMIT,
+};
+use crate::convert::{self, Conversion};
+use crate::encoding;
+use crate::error::{print_if_err, Error, Operation};
+use crate::filter::Filter;
+use crate::hash::{self, search_hash};
+use crate::history::History;
+use crate::lang;
+use crate::metadata;
+use crate::net::{client, send};
+use crate::options::Options;
+use crate::output::Output;
+use crate::payload::Unpacker;
+use crate::postprocess;
+use crate::probe;
+use crate::provider::{OpenSubtitles, SubtitleProvider};
+use crate::report::{Outcome, Reporter, Tally};
+use crate::scan::is_video;
+use crate::template::Values;
+use crate::timing::{timed, timed_async, Timings};
+use crate::validate;
+
+/// How many of the top candidates to show the comments of
+const COMMENT_CANDIDATES: usize = 3;
+
+/// How many comments to show per candidate
+const COMMENTS_SHOWN: usize = 5;
+
+/// How many files are searched for in one request
+pub const SEARCH_BATCH: usize = 20;
 
 /// Sub data collected from the server
-#[derive(Debug)]
-struct Sub {
-    url: String,
-    score: f64,
-    lang: String,
-    format: String,
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sub {
+    /// The id to download the subtitle with or refer to it
+    pub id: String,
+    /// The id of the file of the subtitle, to download it with the
+    /// DownloadSubtitles call, empty if unknown
+    pub file_id: String,
+    /// Where the subtitle can be found
+    pub url: String,
+    /// How well the subtitle matches the file, the higher the better
+    pub score: f64,
+    /// The language id, see the lang module
+    pub lang: String,
+    /// The file format, e.g. srt
+    pub format: String,
+    /// The character encoding, e.g. CP1250, empty if unknown
+    pub encoding: String,
+    /// The MD5 hash of the content in hex, empty if unknown
+    pub md5: String,
+    /// The name of the user who uploaded it, empty if anonymous
+    pub uploader: String,
+    /// How many times it has been downloaded
+    pub downloads: u64,
+    /// The average rating given by the users, 0 if none
+    pub rating: f64,
+    /// When it was uploaded, e.g. 2010-12-10 02:46:13
+    pub added: String,
+    /// Whether it is for the hearing impaired, describing the sounds too
+    pub hearing_impaired: bool,
+    /// Whether it is forced, translating only the foreign parts
+    pub forced: bool,
+    /// The frame rate of the video it is timed for, if known
+    pub fps: Option<f64>,
+    /// The name of the release it was made for, e.g.
+    /// Movie.2019.1080p.WEB-DL.x264-GROUP
+    pub release: String,
+    /// Whether the uploader is trusted by the site: an administrator, a
+    /// trusted or a gold member
+    pub trusted: bool,
+    /// Whether it was translated by a machine
+    pub machine_translated: bool,
+    /// The CD this file is for, from 1, of a subtitle split across several
+    pub cd: u32,
+    /// How many CDs the subtitle is split across, one file for each
+    pub cds: u32,
+    /// The name of the provider it was found by, see the provider module
+    pub provider: String,
+    /// The id of the match of the hash of the video with the subtitle, to
+    /// report it as wrong, empty unless it was found by the hash
+    pub movie_file_id: String,
+    /// The IMDb id of the movie or episode it is for, if known
+    pub imdb_id: Option<u64>,
 }
 
 /// A vec of Sub-s
-type Subs = Vec<Sub>;
+pub type Subs = Vec<Sub>;
 
 /// A vec of Sub-refs
-type SubRefs<'a> = Vec<&'a Sub>;
+pub type SubRefs<'a> = Vec<&'a Sub>;
 
-/// What subtitles to download, only the best one or all of them
-#[derive(PartialEq, Clone, Copy)]
-pub(crate) enum Which {
+/// What subtitles to download: only the best one, all of them, the best
+/// few or the one picked on the terminal
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Which {
     Best,
     All,
+    Top(usize),
+    Select,
+}
+
+/// The header of the tables of candidates
+pub const TABLE_HEADER: &str = "  LANG IDX SCORE FORMAT DOWNLOADS ID         UPLOADER";
+
+/// A row of the table of candidates, idx being the rank of the subtitle
+/// within its language
+pub fn table_row(lang: &str, idx: usize, sub: &Sub) -> String {
+    let uploader = if sub.uploader.is_empty() {
+        "-"
+    } else {
+        &sub.uploader
+    };
+
+    format!(
+        "  {:<4} {:>3} {:>5.1} {:<6} {:>9} {:<10} {}",
+        lang, idx, sub.score, sub.format, sub.downloads, sub.id, uploader
+    )
+}
+
+/// What was answered when asked which candidate to download
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Choice {
+    /// Download the candidate of the index
+    Pick(usize),
+    /// Download none of them
+    Skip,
+    /// Report the candidate of the index as a wrong match of the hash
+    Wrong(usize),
+}
+
+/// Shows the candidates and asks on the terminal which one to download,
+/// or which one was wrongly matched by the hash of the video
+fn select(fname: &Path, lang: &str, subs: &[&Sub]) -> Choice {
+    eprintln!("{}", fname.to_string_lossy());
+    eprintln!("{}", TABLE_HEADER);
+    for (i, sub) in subs.iter().enumerate() {
+        eprintln!("{}", table_row(lang, i + 1, sub));
+    }
+    let reportable = subs.iter().any(|sub| !sub.movie_file_id.is_empty());
+
+    loop {
+        eprint!(
+            "Pick a {} subtitle [1-{}, Enter: 1, s: skip{}]: ",
+            lang,
+            subs.len(),
+            if reportable { ", w N: wrong match" } else { "" }
+        );
+        let mut answer = String::new();
+        match std::io::stdin().read_line(&mut answer) {
+            Ok(0) | Err(_) => return Choice::Skip,
+            Ok(_) => {}
+        }
+
+        let index = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n >= 1 && n <= subs.len())
+                .map(|n| n - 1)
+        };
+        match answer.trim() {
+            "" => return Choice::Pick(0),
+            "s" | "S" => return Choice::Skip,
+            n => match (n.strip_prefix(['w', 'W']), index(n)) {
+                (None, Some(i)) => return Choice::Pick(i),
+                (Some(n), _) if reportable => match index(n) {
+                    Some(i) if !subs[i].movie_file_id.is_empty() => return Choice::Wrong(i),
+                    Some(i) => eprintln!("candidate {} was not found by the hash", i + 1),
+                    None => eprintln!("invalid choice: {}", n.trim()),
+                },
+                _ => eprintln!("invalid choice: {}", n),
+            },
+        }
+    }
+}
+
+/// Asks on the terminal which candidate to download like select, reporting
+/// the ones wrongly matched by the hash and leaving them out. None if
+/// skipped.
+async fn pick<'a>(
+    fname: &Path,
+    lang: &str,
+    subs: &mut SubRefs<'a>,
+    provider: &dyn SubtitleProvider,
+) -> Option<usize> {
+    loop {
+        match select(fname, lang, subs) {
+            Choice::Pick(i) => return Some(i),
+            Choice::Skip => return None,
+            Choice::Wrong(i) => {
+                let sub = subs.remove(i);
+                match provider.report_wrong(sub).await {
+                    Ok(()) => info!("reported subtitle {} as a wrong match", sub.id),
+                    Err(e) => warn!("cannot report subtitle {}: {}", sub.id, e),
+                }
+                if subs.is_empty() {
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// Whether the rank of the user, e.g. gold member, is a trusted one
+pub(crate) fn is_trusted_rank(rank: &str) -> bool {
+    const TRUSTED: &[&str] = &["administrator", "trusted", "gold member", "platinum member"];
+
+    TRUSTED.contains(&rank.trim().to_lowercase().as_str())
 }
 
 /// Converts the API result into a Sub, if the result has all the data needed
@@ -40,6 +232,18 @@ fn match_to_sub(v: &Value) -> Option<Sub> {
 
     let url = data.get("SubDownloadLink").and_then(Value::as_str)?.into();
 
+    let id = data
+        .get("IDSubtitle")
+        .and_then(Value::as_str)
+        .unwrap_or("")
+        .into();
+
+    let file_id = data
+        .get("IDSubtitleFile")
+        .and_then(Value::as_str)
+        .unwrap_or("")
+        .into();
+
     let lang = data
         .get("SubLanguageID")
         .and_then(Value::as_str)
@@ -54,139 +258,1149 @@ fn match_to_sub(v: &Value) -> Option<Sub> {
         .unwrap_or("srt")
         .into();
 
+    let encoding = data
+        .get("SubEncoding")
+        .and_then(Value::as_str)
+        .unwrap_or("")
+        .into();
+
+    let md5 = data
+        .get("SubHash")
+        .and_then(Value::as_str)
+        .unwrap_or("")
+        .into();
+
+    let uploader = data
+        .get("UserNickName")
+        .and_then(Value::as_str)
+        .unwrap_or("")
+        .into();
+
+    let downloads = data
+        .get("SubDownloadsCnt")
+        .and_then(Value::as_str)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0);
+
+    let rating = data
+        .get("SubRating")
+        .and_then(Value::as_str)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0f64);
+
+    let added = data
+        .get("SubAddDate")
+        .and_then(Value::as_str)
+        .unwrap_or("")
+        .into();
+
+    let hearing_impaired = data.get("SubHearingImpaired").and_then(Value::as_str) == Some("1");
+    let forced = data.get("SubForeignPartsOnly").and_then(Value::as_str) == Some("1");
+
+    // 0 if unknown
+    let fps = data
+        .get("MovieFPS")
+        .and_then(Value::as_str)
+        .and_then(|n| n.parse().ok())
+        .filter(|&fps: &f64| fps > 0f64);
+
+    let release = data
+        .get("MovieReleaseName")
+        .and_then(Value::as_str)
+        .unwrap_or("")
+        .trim()
+        .into();
+
+    let trusted = data
+        .get("UserRank")
+        .and_then(Value::as_str)
+        .is_some_and(is_trusted_rank);
+
+    // the field has had several names
+    let machine_translated = [
+        "SubAutoTranslation",
+        "SubAutomaticTranslation",
+        "MachineTranslated",
+    ]
+    .iter()
+    .any(|field| data.get(*field).and_then(Value::as_str) == Some("1"));
+
+    let number = |field| {
+        data.get(field)
+            .and_then(Value::as_str)
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(1)
+    };
+    let cd = number("SubActualCD");
+    let cds = number("SubSumCD");
+
+    let movie_file_id = match data.get("MatchedBy").and_then(Value::as_str) {
+        Some("moviehash") => data
+            .get("IDSubMovieFile")
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .into(),
+        _ => String::new(),
+    };
+    let imdb_id = data
+        .get("IDMovieImdb")
+        .and_then(Value::as_str)
+        .and_then(|id| id.parse().ok())
+        .filter(|&id| id > 0);
+
     Some(Sub {
+        id,
+        file_id,
         url,
         score,
         lang,
         format,
+        encoding,
+        md5,
+        uploader,
+        downloads,
+        rating,
+        added,
+        hearing_impaired,
+        forced,
+        fps,
+        release,
+        trusted,
+        machine_translated,
+        cd,
+        cds,
+        provider: "opensubtitles".into(),
+        movie_file_id,
+        imdb_id,
     })
 }
 
-/// Searches for the subtitles for the given file / languages
-fn find_subtitles(path: &OsStr, langs: &str, token: &str) -> Result<Subs, Error> {
-    let (size, hash) = size_and_hash(path)?;
+/// Adds the subtitles not in the list yet, the CDs of a subtitle being
+/// different files
+fn add_new(subs: &mut Subs, new: Subs) {
+    for sub in new {
+        if !subs.iter().any(|s| s.id == sub.id && s.cd == sub.cd) {
+            subs.push(sub);
+        }
+    }
+}
+
+/// The index of the request body the hit belongs to
+fn query_number(v: &Value) -> usize {
+    let number = v.as_struct().and_then(|data| data.get("QueryNumber"));
 
-    let queries = Value::Array(vec![make_req(langs, size, hash)]);
+    match number {
+        Some(Value::String(ref n)) => n.parse().unwrap_or(0),
+        Some(Value::Int(n)) => *n as usize,
+        _ => 0,
+    }
+}
 
-    let search_resp = Request::new("SearchSubtitles")
+/// Runs a single search request for several queries, returning the hits
+/// per query and whether the server cut them at the limit
+async fn search_many(
+    langs: &str,
+    queries: &[Query],
+    token: &str,
+) -> Result<(Vec<Subs>, bool), Error> {
+    // the query each request body belongs to
+    let mut owners = Vec::new();
+    let mut reqs = Vec::new();
+    for (i, query) in queries.iter().enumerate() {
+        let query_reqs = make_reqs(langs, query);
+        owners.extend(query_reqs.iter().map(|_| i));
+        reqs.extend(query_reqs);
+    }
+
+    let req = Request::new("SearchSubtitles")
         .arg(token)
-        .arg(queries)
-        .call_url(OST_API_URL)?;
+        .arg(Value::Array(reqs))
+        .arg(make_search_opts());
+    let search_resp = call(&req).await?;
 
     let resp = val_to_response(&search_resp)?;
 
-    if let Value::Array(ref hits) = resp["data"] {
-        let subs: Vec<Sub> = hits
+    let mut found = vec![Vec::new(); queries.len()];
+    match resp.get("data") {
+        Some(Value::Array(ref hits)) => {
+            for hit in hits {
+                let owner = owners.get(query_number(hit)).copied().unwrap_or(0);
+                // a subtitle may match several of the criteria
+                add_new(&mut found[owner], match_to_sub(hit).into_iter().collect());
+            }
+            Ok((found, hits.len() >= SEARCH_LIMIT))
+        }
+        // no hits at all
+        Some(Value::Bool(false)) => Ok((found, false)),
+        _ => Err(invalid_response()),
+    }
+}
+
+/// Runs a single search request
+async fn search(langs: &str, query: &Query, token: &str) -> Result<(Subs, bool), Error> {
+    let (mut found, truncated) = search_many(langs, slice::from_ref(query), token).await?;
+
+    Ok((found.pop().unwrap_or_default(), truncated))
+}
+
+/// Searches for the subtitles for the given file / languages, which may
+/// be given as two letter codes too, see lang::resolve
+pub async fn find_subtitles(path: &OsStr, langs: &str, session: &Session) -> Result<Subs, Error> {
+    search_file(
+        path,
+        &Query::default(),
+        &lang::resolve_list(langs)?,
+        &OpenSubtitles::new(session, false),
+        &mut Timings::default(),
+    )
+    .await
+}
+
+/// Whether the word is a release tag that ends the title in a file name
+fn is_release_tag(word: &str) -> bool {
+    const TAGS: &[&str] = &[
+        "bdrip", "bluray", "brrip", "dvdrip", "h264", "h265", "hdr", "hdrip", "hdtv", "hevc",
+        "proper", "remux", "repack", "web", "webdl", "webrip", "x264", "x265", "xvid",
+    ];
+
+    let word = word.to_lowercase();
+    let resolution = word.len() > 1
+        && word.ends_with('p')
+        && word[..word.len() - 1].bytes().all(|b| b.is_ascii_digit());
+
+    resolution || TAGS.contains(&word.as_str())
+}
+
+/// Parses the season and episode from a word like S02E05 or 2x05
+fn parse_episode(word: &str) -> Option<(u32, u32)> {
+    let word = word.to_lowercase();
+    let (season, episode) = match word.strip_prefix('s') {
+        Some(rest) => rest.split_once('e')?,
+        None => word.split_once('x')?,
+    };
+
+    Some((season.parse().ok()?, episode.parse().ok()?))
+}
+
+/// The name of the file of a remote video given by its URL
+fn local_name(fname: &OsStr) -> Option<String> {
+    fname
+        .to_str()
+        .filter(|f| hash::is_url(f))
+        .and_then(hash::url_file_name)
+}
+
+/// Guesses the query from the file name, e.g. Show.Name.S02E05.720p.mkv:
+/// the title ends at the episode, the year or the first release tag
+fn guess_query(fname: &OsStr) -> Query {
+    let local = local_name(fname);
+    let path = local.as_deref().map_or(Path::new(fname), Path::new);
+    let stem = path.file_stem().unwrap_or(fname).to_string_lossy();
+
+    let mut query = Query {
+        tag: path.file_name().map(|n| n.to_string_lossy().into_owned()),
+        ..Query::default()
+    };
+
+    let mut title = Vec::new();
+    for word in stem
+        .split(&['.', '_', ' ', '-'][..])
+        .filter(|w| !w.is_empty())
+    {
+        if let Some((season, episode)) = parse_episode(word) {
+            query.season = Some(season);
+            query.episode = Some(episode);
+            break;
+        }
+        let is_year = word.len() == 4 && (word.starts_with("19") || word.starts_with("20"));
+        if (is_year && !title.is_empty()) || is_release_tag(word) {
+            break;
+        }
+        title.push(word);
+    }
+
+    if !title.is_empty() {
+        query.text = Some(title.join(" "));
+    }
+
+    query
+}
+
+/// Searches for the subtitles of the file by its hash and the base query.
+/// When nothing matches, the search is retried by the file name and the
+/// title guessed from it, as the slightest re-mux changes the hash.
+pub async fn search_file(
+    fname: &OsStr,
+    base: &Query,
+    langs: &str,
+    provider: &dyn SubtitleProvider,
+    timings: &mut Timings,
+) -> Result<Subs, Error> {
+    let query = file_query(fname, base, timings).await?;
+    search_query(fname, &query, langs, provider, timings).await
+}
+
+/// Searches for the subtitles of the file by the query made for it by
+/// file_query, falling back to its name like search_file
+async fn search_query(
+    fname: &OsStr,
+    query: &Query,
+    langs: &str,
+    provider: &dyn SubtitleProvider,
+    timings: &mut Timings,
+) -> Result<Subs, Error> {
+    if query.size_hash.is_none() && query.text.is_none() {
+        return search_by_name(fname, langs, provider, timings).await;
+    }
+    let subs = timed_async(&mut timings.search, provider.search(query, langs))
+        .await
+        .map_err(|e| e.context(Operation::Search, Path::new(fname)))?;
+
+    if !subs.is_empty() || query.text.is_some() {
+        return Ok(subs);
+    }
+
+    info!(
+        "{}: no hash matches, searching by name",
+        fname.to_string_lossy()
+    );
+    search_by_name(fname, langs, provider, timings).await
+}
+
+/// Searches for the subtitles of the file by its name, when its hash had
+/// no matches or it is too small to have one
+async fn search_by_name(
+    fname: &OsStr,
+    langs: &str,
+    provider: &dyn SubtitleProvider,
+    timings: &mut Timings,
+) -> Result<Subs, Error> {
+    let fallback = guess_query(fname);
+    timed_async(&mut timings.search, provider.search(&fallback, langs))
+        .await
+        .map_err(|e| e.context(Operation::Search, Path::new(fname)))
+}
+
+/// Searches for the subtitles of the files like search_file does, but
+/// if the provider can (e.g. the XML-RPC API) the hashes are sent in one
+/// request instead of one request per file. The results are in the order
+/// of the files, the time spent being added to their timings.
+pub async fn search_files(
+    fnames: &[PathBuf],
+    base: &Query,
+    langs: &str,
+    provider: &dyn SubtitleProvider,
+    timings: &mut [Timings],
+) -> Vec<Result<Subs, Error>> {
+    if base.text.is_some() || fnames.len() < 2 {
+        return search_each(fnames, base, langs, provider, timings).await;
+    }
+
+    let queries: Vec<Result<Query, Error>> = join_all(
+        fnames
             .iter()
-            .map(match_to_sub)
-            .filter(Option::is_some)
-            .map(Option::unwrap)
-            .collect();
-        Ok(subs)
-    } else {
-        Err(E_INV_RESP)
+            .zip(timings.iter_mut())
+            .map(|(fname, t)| file_query(fname.as_os_str(), base, t)),
+    )
+    .await;
+    let hashed: Vec<Query> = queries
+        .iter()
+        .flatten()
+        .filter(|query| query.size_hash.is_some())
+        .cloned()
+        .collect();
+
+    let batch = match provider.search_batch(&hashed, langs) {
+        Some(batch) => batch,
+        // searched for one by one, with the queries already made
+        None => {
+            let searches = queries
+                .into_iter()
+                .zip(fnames.iter().zip(timings.iter_mut()))
+                .map(|(query, (fname, t))| async move {
+                    search_query(fname.as_os_str(), &query?, langs, provider, t).await
+                });
+            return join_all(searches).await;
+        }
+    };
+    let mut search_time = Duration::default();
+    let found = timed_async(&mut search_time, batch).await;
+    for t in timings.iter_mut() {
+        t.search += search_time / fnames.len() as u32;
     }
+
+    let mut found = match found {
+        Ok(found) => found.into_iter(),
+        Err(e) => {
+            warn!("batch search failed, searching one by one: {}", e);
+            return search_each(fnames, base, langs, provider, timings).await;
+        }
+    };
+
+    let searches = queries
+        .into_iter()
+        .zip(fnames.iter().zip(timings.iter_mut()))
+        .map(|(query, (fname, t))| {
+            let subs = query
+                .as_ref()
+                .ok()
+                .filter(|query| query.size_hash.is_some())
+                .and_then(|_| found.next());
+            async move {
+                query?;
+                match subs {
+                    Some(subs) if !subs.is_empty() => Ok(subs),
+                    Some(_) => {
+                        info!(
+                            "{}: no hash matches, searching by name",
+                            fname.to_string_lossy()
+                        );
+                        search_by_name(fname.as_os_str(), langs, provider, t).await
+                    }
+                    None => search_by_name(fname.as_os_str(), langs, provider, t).await,
+                }
+            }
+        });
+
+    join_all(searches).await
 }
 
-/// Fetches the data from the url and gunzips it into the file
-/// specified by the path
-fn download_to_file(url: &str, path: &OsString) -> Result<(), Error> {
-    let mut res = reqwest::get(url)?;
-    let mut file = File::create(path)?;
-    let mut gzipped = Vec::new();
-    res.read_to_end(&mut gzipped)?;
+/// Searches for the subtitles of the files with a request per file, the
+/// requests running concurrently
+async fn search_each(
+    fnames: &[PathBuf],
+    base: &Query,
+    langs: &str,
+    provider: &dyn SubtitleProvider,
+    timings: &mut [Timings],
+) -> Vec<Result<Subs, Error>> {
+    let searches = fnames
+        .iter()
+        .zip(timings.iter_mut())
+        .map(|(fname, t)| search_file(fname.as_os_str(), base, langs, provider, t));
 
-    let mut decoder = Decoder::new(&gzipped[..]).unwrap();
-    let mut decoded_data = Vec::new();
-    decoder.read_to_end(&mut decoded_data).unwrap();
-    file.write_all(&decoded_data)?;
+    join_all(searches).await
+}
 
-    Ok(())
+/// Searches for the subtitles matching the query in the given languages
+/// on OpenSubtitles
+pub async fn search_subtitles(
+    query: &Query,
+    langs: &str,
+    session: &Session,
+) -> Result<Subs, Error> {
+    OpenSubtitles::new(session, false)
+        .search(query, langs)
+        .await
 }
 
-/// Downloads the given subtitle, constructing the file name based on the
-/// original filename, the language and the index
-fn download_subtitle(
-    fname_base: &PathBuf,
+/// Searches with the XML-RPC API
+pub(crate) async fn xmlrpc_search(query: &Query, langs: &str, token: &str) -> Result<Subs, Error> {
+    let (mut subs, truncated) = search(langs, query, token).await?;
+
+    // The server cuts the hits at the limit and has no paging, so when
+    // that happens the languages are fetched one by one, each of them
+    // getting a full page.
+    if truncated && langs.contains(',') {
+        subs.clear();
+        for lang in langs.split(',') {
+            let (found, truncated) = search(lang, query, token).await?;
+            if truncated {
+                warn_truncated(query, lang);
+            }
+            add_new(&mut subs, found);
+        }
+    } else if truncated {
+        warn_truncated(query, langs);
+    }
+
+    Ok(subs)
+}
+
+/// Warns that the search hit the limit of the server in the language, so
+/// the best subtitles may be among the ones cut
+fn warn_truncated(query: &Query, lang: &str) {
+    let what = query
+        .name()
+        .map_or_else(String::new, |name| format!("{}: ", name));
+    warn!(
+        "{}only the first {} subtitles in {} were returned, some may be missing",
+        what, SEARCH_LIMIT, lang
+    );
+}
+
+/// Searches for several queries in one request with the XML-RPC API. If
+/// the hits are cut at the limit, the queries are searched one by one,
+/// which also splits up the languages.
+pub(crate) async fn xmlrpc_search_batch(
+    queries: &[Query],
+    langs: &str,
+    token: &str,
+) -> Result<Vec<Subs>, Error> {
+    let (found, truncated) = search_many(langs, queries, token).await?;
+
+    match queries {
+        _ if !truncated => Ok(found),
+        // a single query in a single language has nothing to split
+        [query] if !langs.contains(',') => {
+            warn_truncated(query, langs);
+            Ok(found)
+        }
+        _ => {
+            try_join_all(
+                queries
+                    .iter()
+                    .map(|query| xmlrpc_search(query, langs, token)),
+            )
+            .await
+        }
+    }
+}
+
+/// Fetches the data from the url and unpacks it into the file
+/// specified by the path, transcoded to UTF-8 from the encoding guessed
+pub async fn download_to_file(url: &str, path: &OsStr) -> Result<(), Error> {
+    let path = Path::new(path);
+    let data = fetch(url, "")
+        .await
+        .map_err(|e| e.context(Operation::Download, path))?;
+    File::create(path)
+        .and_then(|mut file| file.write_all(&encoding::to_utf8(&data, "", "")))
+        .map_err(|e| Error::from(e).context(Operation::Write, path))
+}
+
+/// Downloads the subtitle from OpenSubtitles, returning its
+/// (decompressed) content
+pub async fn download(sub: &Sub, session: &Session) -> Result<Vec<u8>, Error> {
+    fetch_sub(sub, &OpenSubtitles::new(session, false)).await
+}
+
+/// Whether the content matches the MD5 hash reported by the server, if
+/// any
+pub(crate) fn md5_matches(sub: &Sub, data: &[u8]) -> bool {
+    sub.md5.is_empty() || format!("{:x}", md5::compute(data)).eq_ignore_ascii_case(&sub.md5)
+}
+
+/// Fetches the subtitle from the provider, returning its decoded content.
+/// It is fetched again once if it does not match its MD5 hash, corrupted
+/// on the way.
+async fn fetch_sub(sub: &Sub, provider: &dyn SubtitleProvider) -> Result<Vec<u8>, Error> {
+    let data = provider.fetch(sub).await?;
+    if md5_matches(sub, &data) {
+        return Ok(data);
+    }
+
+    warn!(
+        "subtitle {} does not match its MD5 hash, downloading it again",
+        sub.id
+    );
+    let data = provider.fetch(sub).await?;
+    if !md5_matches(sub, &data) {
+        return Err(Error::Invalid(
+            format!("subtitle {} does not match its MD5 hash", sub.id).into(),
+        ));
+    }
+
+    Ok(data)
+}
+
+/// Fetches the subtitle in the format given (empty if unknown) from the
+/// url, unpacking it as it arrives
+pub(crate) async fn fetch(url: &str, format: &str) -> Result<Vec<u8>, Error> {
+    let unpacked = async {
+        let mut res = send(client().get(url)).await?;
+        let mut unpacker = Unpacker::new(format);
+        while let Some(chunk) = res.chunk().await? {
+            unpacker.feed(&chunk)?;
+        }
+
+        unpacker.finish()
+    };
+
+    unpacked.await.map_err(|e| e.at(url))
+}
+
+/// Constructs the subtitle file name based on the original filename, the
+/// language suffix, the index, the flags of the subtitle and the format it
+/// is written in, or from the template of the options if given
+fn subtitle_path(
+    fname_base: &Path,
     lang: &str,
+    suffix: &str,
     idx: Option<usize>,
     sub: &Sub,
-) -> Result<(), Error> {
+    format: &str,
+    opts: &Options,
+) -> PathBuf {
+    let sdh = opts.mark_sdh && sub.hearing_impaired;
+    let default = opts.default_lang.as_deref() == Some(lang);
+    // all the CDs of the subtitle are downloaded unless the video is one of
+    // the CDs too, named so
+    let mut fname_base = fname_base.to_path_buf();
+    if sub.cds > 1 && cd_number(fname_base.as_os_str()).is_none() {
+        fname_base.as_mut_os_string().push(format!(".cd{}", sub.cd));
+    }
+
+    if let Some(ref template) = opts.name_template {
+        let values = Values {
+            stem: fname_base.file_name().unwrap_or_default(),
+            lang_id: lang,
+            lang: suffix,
+            idx: idx.unwrap_or(1),
+            format,
+            score: sub.score,
+            forced: sub.forced,
+            sdh,
+            default,
+        };
+        return fname_base.with_file_name(template.render(&values));
+    }
+
     let mut fname_os = fname_base.as_os_str().to_os_string();
+    fname_os.push(".");
+    fname_os.push(suffix);
     if let Some(i) = idx {
-        fname_os.push(format!(".{}-{}.{}", lang, i, &sub.format));
+        fname_os.push(format!("-{}", i));
+    }
+    // as media servers like Plex and Kodi recognize them
+    if default {
+        fname_os.push(".default");
+    }
+    if sub.forced {
+        fname_os.push(".forced");
+    }
+    if sdh {
+        fname_os.push(".sdh");
+    }
+    fname_os.push(".");
+    fname_os.push(format);
+
+    PathBuf::from(fname_os)
+}
+
+/// The number of the CD the video is, if named like Movie.CD2.avi
+fn cd_number(fname: &OsStr) -> Option<u32> {
+    let name = Path::new(fname)
+        .file_name()?
+        .to_string_lossy()
+        .to_lowercase();
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter_map(|word| word.strip_prefix("cd"))
+        .find_map(|n| n.parse().ok())
+}
+
+/// Sorts out the subtitles split across several CDs: for a video that is
+/// one of the CDs only the file for that CD is kept, for a single video
+/// they are left out as they don't fit it, otherwise (e.g. searching by
+/// title) they stand for all their CDs with the file of the first.
+/// Returns the subtitles kept and whether any were left out.
+fn sort_out_cds<'a>(
+    lang_subs: SubRefs<'a>,
+    video_cd: Option<u32>,
+    single: bool,
+) -> (SubRefs<'a>, bool) {
+    let mut left_out = false;
+    let kept = lang_subs
+        .into_iter()
+        .filter(|sub| {
+            if sub.cds <= 1 {
+                return true;
+            }
+            match video_cd {
+                Some(cd) => sub.cd == cd,
+                None if single => {
+                    left_out = true;
+                    false
+                }
+                None => sub.cd == 1,
+            }
+        })
+        .collect();
+
+    (kept, left_out)
+}
+
+/// The files of all the CDs of the subtitle, in order, or just the
+/// subtitle if it is not split
+fn cd_files<'a>(subs: &'a Subs, sub: &'a Sub, video_cd: Option<u32>) -> SubRefs<'a> {
+    if sub.cds <= 1 || video_cd.is_some() {
+        return vec![sub];
+    }
+
+    let mut files: SubRefs = subs.iter().filter(|s| s.id == sub.id).collect();
+    files.sort_by_key(|s| s.cd);
+    if files.len() < sub.cds as usize {
+        warn!(
+            "only {} of the {} CDs of the subtitle {} were found",
+            files.len(),
+            sub.cds,
+            sub.id
+        );
+    }
+    files
+}
+
+/// Decides whether the existing subtitle file is to be replaced with the
+/// candidate: only if the candidate scores higher than the recorded score
+/// by at least the margin. Returns why the file is kept otherwise.
+fn keep_reason(path: &Path, sub: &Sub, margin: f64, history: &History) -> Option<String> {
+    let recorded = match history.get(path) {
+        Some(entry) => entry.score,
+        None => return Some("not downloaded by ostdl".into()),
+    };
+
+    if sub.score < recorded + margin {
+        return Some(format!(
+            "{:2.1} is not better than {:2.1}",
+            sub.score, recorded
+        ));
+    }
+
+    None
+}
+
+/// Keeps the file about to be replaced with a .bak suffix, returning the
+/// path of the backup
+fn backup(path: &Path) -> Result<PathBuf, Error> {
+    let mut backup = path.as_os_str().to_os_string();
+    backup.push(".bak");
+    let backup = PathBuf::from(backup);
+    fs::rename(path, &backup)?;
+
+    Ok(backup)
+}
+
+/// A subtitle to download and where to
+#[derive(Debug)]
+pub struct Download<'a> {
+    /// The index of the video among the ones processed together
+    pub file: usize,
+    /// Where to write the subtitle
+    pub path: PathBuf,
+    /// The subtitle to download
+    pub sub: &'a Sub,
+    /// Whether an existing file is replaced, keeping it with a .bak suffix
+    pub replace: bool,
+    /// How the subtitle is converted before writing it, if at all
+    pub convert: Option<Conversion>,
+}
+
+impl Download<'_> {
+    /// The format the subtitle is written in
+    pub fn format(&self) -> &str {
+        self.convert
+            .map_or(self.sub.format.as_str(), |c| c.to.extension())
+    }
+}
+
+/// Fetches the subtitles, as many at the same time as the options allow,
+/// returning the decoded data in the order of the downloads along with the
+/// time each took
+pub async fn fetch_downloads(
+    downloads: &[Download<'_>],
+    provider: &dyn SubtitleProvider,
+    opts: &Options,
+) -> Vec<(Result<Vec<u8>, Error>, Duration)> {
+    stream::iter(downloads)
+        .map(|download| async move {
+            let mut time = Duration::default();
+            let fetch = fetch_sub(download.sub, provider);
+            let data = timed_async(&mut time, fetch)
+                .await
+                .map_err(|e| e.context(Operation::Download, &download.path));
+            (data, time)
+        })
+        .buffered(opts.jobs)
+        .collect()
+        .await
+}
+
+/// Writes the fetched subtitle into the output, processed as specified by
+/// the options, recording it in the history. Reporting it is up to the
+/// caller.
+pub fn save_download(
+    download: &Download,
+    data: &[u8],
+    opts: &Options,
+    output: &mut Output,
+    history: &mut History,
+    timings: &mut Timings,
+) -> Result<(), Error> {
+    let path = &download.path;
+    let sub = download.sub;
+
+    let data = timed(&mut timings.postprocess, || -> Result<Vec<u8>, Error> {
+        validate::check(&sub.format, data)?;
+        let mut data = data.to_vec();
+        if opts.utf8 {
+            data = encoding::to_utf8(&data, &sub.encoding, &sub.lang).into_owned();
+        }
+        if let Some(conversion) = download.convert {
+            data = conversion.apply(&sub.format, &data)?;
+        }
+        if let Some(ref ads) = opts.strip_ads {
+            if download.format().eq_ignore_ascii_case("srt") {
+                data = ads.apply(&data);
+            } else {
+                debug!("{}: only ads in srt subtitles are removed", path.display());
+            }
+        }
+        if opts.strip_bom {
+            data = postprocess::strip_bom(&data).to_vec();
+        }
+        if let Some(line_ending) = opts.line_ending {
+            data = line_ending.apply(&data);
+        }
+        Ok(data)
+    })
+    .map_err(|e| e.context(Operation::Write, path))?;
+    // the subtitle there is only moved aside once the new one is ready
+    let backup = if download.replace {
+        Some(backup(path).map_err(|e| e.context(Operation::Write, path))?)
     } else {
-        fname_os.push(format!(".{}.{}", lang, &sub.format));
+        None
+    };
+    if let Err(e) = output.write(path, &data) {
+        // put back the subtitle replaced rather than leaving none
+        if let Some(backup) = backup {
+            if let Err(e) = fs::rename(&backup, path) {
+                warn!("cannot restore {}: {}", backup.display(), e);
+            }
+        }
+        return Err(e.context(Operation::Write, path));
+    }
+    if opts.metadata {
+        let meta = metadata::path(path);
+        output
+            .write(&meta, &metadata::to_json(sub, download.format()))
+            .map_err(|e| e.context(Operation::Write, &meta))?;
+    }
+
+    if matches!(output, Output::Files) {
+        history.record(path, &sub.id, sub.score);
     }
 
-    download_to_file(&sub.url, &fname_os)?;
+    Ok(())
+}
+
+/// Prints the most recent user comments of the top candidates into the
+/// output, stdout when listing them and stderr along with the questions
+/// when downloading
+pub async fn print_comments(
+    fname: &Path,
+    lang: &str,
+    subs: &[&Sub],
+    provider: &dyn SubtitleProvider,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let subs = &subs[..subs.len().min(COMMENT_CANDIDATES)];
+    let ids: Vec<&str> = subs.iter().map(|s| s.id.as_str()).collect();
+    let comments = provider
+        .comments(&ids)
+        .await
+        .map_err(|e| e.context(Operation::Search, fname))?;
 
-    println!("{} {:2.1}", fname_os.to_string_lossy(), sub.score);
+    for (i, sub) in subs.iter().enumerate() {
+        writeln!(
+            out,
+            "{} {} candidate {} ({:2.1}):",
+            fname.to_string_lossy(),
+            lang,
+            i + 1,
+            sub.score
+        )?;
+        match comments.get(&sub.id) {
+            Some(list) if !list.is_empty() => {
+                for c in list.iter().rev().take(COMMENTS_SHOWN) {
+                    writeln!(out, "    {} {}: {}", c.created, c.user, c.text)?;
+                }
+            }
+            _ => writeln!(out, "    no comments")?,
+        }
+    }
 
     Ok(())
 }
 
-/// Downloads the subtitles for the given file, given languages, the ones
-/// that were requested (which)
-pub(crate) fn download_subtitles(
+/// The query to search the subtitles of the file with: the hash of the
+/// file is added to the base query, unless searching by title, in which
+/// case the file only names the subtitles. Files too small to be searched
+/// by hash get no hash, to be searched by name.
+async fn file_query(fname: &OsStr, base: &Query, timings: &mut Timings) -> Result<Query, Error> {
+    let mut query = base.clone();
+
+    if query.text.is_none() {
+        if !hash::is_url(&fname.to_string_lossy()) {
+            query.video = Some(PathBuf::from(fname));
+        }
+        match timed_async(&mut timings.hash, search_hash(fname)).await {
+            Ok(size_hash) => query.size_hash = Some(size_hash),
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::InvalidInput => {
+                info!("{}: {}, searching by name", fname.to_string_lossy(), e)
+            }
+            Err(e) => return Err(e.context(Operation::Hash, Path::new(fname))),
+        }
+    }
+
+    Ok(query)
+}
+
+/// Downloads the subtitles for the given file into the output as
+/// specified by the options, recording them in the history.
+/// The time spent is added to the timings. Returns whether all the
+/// subtitles asked for were downloaded.
+pub async fn download_subtitles(
     fname: &OsStr,
-    langs: &str,
-    which: Which,
-    token: &str,
-) -> Result<(), Error> {
-    let subs = find_subtitles(fname, langs, token)?;
+    opts: &Options,
+    provider: &dyn SubtitleProvider,
+    output: &mut Output,
+    history: &mut History,
+    timings: &mut Timings,
+    report: &mut dyn Reporter,
+) -> Result<Outcome, Error> {
+    let subs = search_file(fname, &opts.query, &opts.langs, provider, timings).await?;
+    let file = Path::new(fname);
+    let mut report = Tally::new(report);
+
+    let downloads = plan_downloads(0, fname, &subs, opts, provider, history, &mut report).await;
+    let fetched = fetch_downloads(&downloads, provider, opts).await;
+
+    for (download, (data, time)) in downloads.iter().zip(fetched) {
+        timings.download += time;
+        match data.and_then(|data| save_download(download, &data, opts, output, history, timings)) {
+            Ok(()) => report.saved(file, &download.path, download.sub),
+            Err(e) => report.error(file, &e),
+        }
+    }
+    report.done(file);
+
+    Ok(report.outcome)
+}
+
+/// Decides which of the subtitles found for the file are to be downloaded
+/// and where, as specified by the options, reporting the ones that are
+/// not. In upgrade mode existing files are only replaced with better
+/// ones. The file is the index of the video among the ones processed
+/// together.
+pub async fn plan_downloads<'a>(
+    file: usize,
+    fname: &OsStr,
+    subs: &'a Subs,
+    opts: &Options,
+    provider: &dyn SubtitleProvider,
+    history: &History,
+    report: &mut dyn Reporter,
+) -> Vec<Download<'a>> {
+    let langs = opts.langs.as_str();
+    let mut downloads = Vec::new();
 
     let fname_path = PathBuf::from(&fname);
-    let fname_base: PathBuf = fname_path
-        .file_stem()
+    // the subtitles of a remote video are named after its file, in the
+    // current directory
+    let local_path = local_name(fname).map_or_else(|| fname_path.clone(), PathBuf::from);
+    // there may be no video when searching by title, so only the extension
+    // of an existing file or a video file is replaced
+    let has_ext = local_path.exists() || is_video(&local_path, &opts.extensions);
+    let stem = if has_ext {
+        local_path.file_stem()
+    } else {
+        local_path.file_name()
+    };
+    let fname_base: PathBuf = stem
         .map(|stem| {
-            let parent = fname_path.parent().expect("Error getting parent path");
-            let mut stem_with_path = parent.to_path_buf();
+            let mut stem_with_path = match opts.output_dir {
+                Some(ref dir) => dir.clone(),
+                None => local_path
+                    .parent()
+                    .expect("Error getting parent path")
+                    .to_path_buf(),
+            };
             stem_with_path.push(stem);
             stem_with_path
         })
-        .unwrap_or_else(|| fname_path.clone());
+        .unwrap_or_else(|| local_path.clone());
 
+    // a video named e.g. Movie.CD1.avi is one of the CDs of a rip
+    let video_cd = cd_number(local_path.as_os_str());
+    let single_video = video_cd.is_none() && local_path.exists();
+
+    let filter = opts.filter.for_file(fname, subs);
+    // frame based subtitles are converted with the frame rate of the video
+    let video_fps = match opts.convert {
+        Some(_)
+            if filter.video_fps.is_none()
+                && subs.iter().any(|s| convert::is_frame_based(&s.format))
+                && fname_path.exists() =>
+        {
+            probe::fps(fname)
+        }
+        _ => filter.video_fps,
+    };
+    let conversion = |sub: &Sub| {
+        let to = opts
+            .convert
+            .filter(|to| !sub.format.eq_ignore_ascii_case(to.extension()))?;
+        if !convert::can_convert(&sub.format) {
+            warn!(
+                "{}: cannot convert {} subtitles to {}, keeping {}",
+                fname_path.to_string_lossy(),
+                sub.format,
+                to.extension(),
+                sub.id
+            );
+            return None;
+        }
+        Some(Conversion {
+            to,
+            fps: video_fps.or(sub.fps),
+        })
+    };
+    // the languages the video has subtitles in embedded, of the ones to skip
+    let embedded: Vec<&str> = if opts.skip_embedded.is_empty() || !fname_path.exists() {
+        Vec::new()
+    } else {
+        match probe::subtitle_langs(fname) {
+            Some(tagged) => opts
+                .skip_embedded
+                .iter()
+                .map(String::as_str)
+                .filter(|&lang| tagged.iter().any(|t| lang::resolve(t).ok() == Some(lang)))
+                .collect(),
+            None => {
+                warn!(
+                    "{}: cannot look for embedded subtitles, is ffprobe installed?",
+                    fname_path.to_string_lossy()
+                );
+                Vec::new()
+            }
+        }
+    };
     for lang in langs.split(',') {
-        let lang_subs = get_lang(&subs, lang);
+        if embedded.contains(&lang) {
+            let reason = format!("has {} subtitles embedded", lang);
+            report.kept(&fname_path, lang, &fname_path, &reason);
+            continue;
+        }
+        let (lang_subs, left_out) =
+            sort_out_cds(get_lang(subs, lang, &filter), video_cd, single_video);
+        let lang_suffix = opts.suffix.apply(lang, &opts.lang_tags);
         if lang_subs.is_empty() {
-            print_err(format!(
-                "{}: No {} subtitles",
-                &fname_path.to_string_lossy(),
-                lang
-            ));
-        } else if which == Which::Best {
-            let res = download_subtitle(&fname_base, &lang, None, &lang_subs[0]);
-            print_if_err(&res);
-        } else {
+            let found: SubRefs = subs.iter().filter(|s| s.lang == lang).collect();
+            let reason = if left_out {
+                "the ones found are split across several CDs, the video is a single file".into()
+            } else {
+                format!(
+                    "none of the {} found {}",
+                    found.len(),
+                    filter.describe(&found)
+                )
+            };
+            report.missing(
+                &fname_path,
+                lang,
+                (!found.is_empty()).then_some(reason.as_str()),
+            );
+            continue;
+        }
+        // forced subtitles are ranked and named apart from the full ones
+        let (forced, full): (SubRefs, SubRefs) = lang_subs.into_iter().partition(|s| s.forced);
+        for mut lang_subs in [full, forced] {
+            if lang_subs.is_empty() {
+                continue;
+            }
             for (i, sub) in lang_subs.iter().enumerate() {
-                let res = download_subtitle(&fname_base, &lang, Some(i + 1), &sub);
-                print_if_err(&res);
+                debug!(
+                    "{}: {} candidate {}: {:2.1} {} by {}{}",
+                    fname_path.to_string_lossy(),
+                    lang,
+                    i + 1,
+                    sub.score,
+                    sub.id,
+                    sub.uploader,
+                    if sub.movie_file_id.is_empty() {
+                        String::new()
+                    } else {
+                        format!(", hash match {}", sub.movie_file_id)
+                    }
+                );
+            }
+            if opts.with_comments {
+                let mut out = io::stderr();
+                let comments = print_comments(&fname_path, lang, &lang_subs, provider, &mut out);
+                print_if_err(&comments.await);
+            }
+            let pick = match opts.which {
+                Which::Best => Some(0),
+                Which::Select => match pick(&fname_path, lang, &mut lang_subs, provider).await {
+                    Some(i) => Some(i),
+                    None => continue,
+                },
+                Which::All | Which::Top(_) => None,
+            };
+            let limit = match opts.which {
+                Which::Top(n) => n,
+                _ => lang_subs.len(),
+            };
+            let candidate = |idx: Option<usize>, sub: &'a Sub| {
+                let convert = conversion(sub);
+                let format = convert.map_or(sub.format.as_str(), |c| c.to.extension());
+                let path = subtitle_path(&fname_base, lang, &lang_suffix, idx, sub, format, opts);
+                (path, sub, convert)
+            };
+            let candidates: Vec<_> = if let Some(i) = pick {
+                cd_files(subs, lang_subs[i], video_cd)
+                    .into_iter()
+                    .map(|sub| candidate(None, sub))
+                    .collect()
+            } else {
+                lang_subs
+                    .iter()
+                    .take(limit)
+                    .enumerate()
+                    .flat_map(|(i, sub)| {
+                        cd_files(subs, sub, video_cd)
+                            .into_iter()
+                            .map(move |sub| (i, sub))
+                    })
+                    .map(|(i, sub)| candidate(Some(i + 1), sub))
+                    .collect()
+            };
+            for (path, sub, convert) in candidates {
+                // archives are always written from scratch
+                let replace = opts.archive.is_none() && path.exists();
+                if replace && opts.skip_existing {
+                    report.kept(&fname_path, lang, &path, "already exists");
+                    continue;
+                }
+                if let (Some(margin), true) = (opts.upgrade, replace) {
+                    if let Some(reason) = keep_reason(&path, sub, margin, history) {
+                        report.kept(&fname_path, lang, &path, &reason);
+                        continue;
+                    }
+                }
+                downloads.push(Download {
+                    file,
+                    path,
+                    sub,
+                    replace: replace && opts.upgrade.is_some(),
+                    convert,
+                });
             }
         }
     }
 
-    Ok(())
-}
-
-/// orders two scores - higher or non-NaN first.
-fn score_cmp(a: &&Sub, b: &&Sub) -> Ordering {
-    let a = a.score;
-    let b = b.score;
-    match (a.is_nan(), b.is_nan()) {
-        (true, true) => Ordering::Equal,
-        (true, false) => Ordering::Greater,
-        (false, true) => Ordering::Less,
-        _ => b.partial_cmp(&a).unwrap(),
-    }
+    downloads
 }
 
-/// Returns the subtitles only for the given language
-/// sorted (higher score first)
-fn get_lang<'a>(subs: &'a Subs, lang: &str) -> SubRefs<'a> {
-    let mut lang_subs: SubRefs = subs.iter().filter(|i| i.lang == lang).collect();
+/// Returns the subtitles only for the given language that the filter
+/// accepts, sorted (the best first)
+pub fn get_lang<'a>(subs: &'a Subs, lang: &str, filter: &Filter) -> SubRefs<'a> {
+    let mut lang_subs: SubRefs = subs
+        .iter()
+        .filter(|i| i.lang == lang && filter.accepts(i))
+        .collect();
 
-    lang_subs.sort_by(score_cmp);
+    lang_subs.sort_by(|a, b| filter.compare(a, b));
 
     lang_subs
 }
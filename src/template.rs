@@ -0,0 +1,155 @@
+//! Templates of the subtitle file names, e.g. `{stem}.{lang2}.{format}`
+
+use std::ffi::{OsStr, OsString};
+use std::str::FromStr;
+
+use crate::error::Error;
+use crate::lang;
+
+/// A value a template can refer to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    /// The name of the video without the extension
+    Stem,
+    /// The language, named as --lang-suffix says
+    Lang,
+    /// The two letter code of the language
+    Lang2,
+    /// The rank of the subtitle among the ones of its language, from 1
+    Idx,
+    /// The format of the subtitle, e.g. srt
+    Format,
+    /// The score of the subtitle
+    Score,
+    /// .forced for forced subtitles, nothing for the others
+    Forced,
+    /// .sdh for the subtitles for the hearing impaired, nothing for the
+    /// others
+    Sdh,
+    /// .default for the subtitles in the default language, nothing for the
+    /// others
+    Default,
+}
+
+impl FromStr for Field {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Field, Error> {
+        match s {
+            "stem" => Ok(Field::Stem),
+            "lang" => Ok(Field::Lang),
+            "lang2" => Ok(Field::Lang2),
+            "idx" => Ok(Field::Idx),
+            "format" => Ok(Field::Format),
+            "score" => Ok(Field::Score),
+            "forced" => Ok(Field::Forced),
+            "sdh" => Ok(Field::Sdh),
+            "default" => Ok(Field::Default),
+            _ => Err(Error::Ost(
+                format!("unknown placeholder in the name template: {{{}}}", s).into(),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// A parsed file name template. `{{` and `}}` stand for literal braces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+/// The values of the fields for a subtitle
+pub struct Values<'a> {
+    pub stem: &'a OsStr,
+    /// The API id of the language
+    pub lang_id: &'a str,
+    /// The language as it is to appear in the file names
+    pub lang: &'a str,
+    pub idx: usize,
+    pub format: &'a str,
+    pub score: f64,
+    pub forced: bool,
+    pub sdh: bool,
+    pub default: bool,
+}
+
+impl FromStr for NameTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<NameTemplate, Error> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or("unclosed placeholder in the name template")?;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Field(rest[..end].parse()?));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err("unmatched } in the name template".into()),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(NameTemplate { parts })
+    }
+}
+
+impl NameTemplate {
+    /// Whether the template refers to the field
+    pub fn has(&self, field: Field) -> bool {
+        self.parts.contains(&Part::Field(field))
+    }
+
+    /// Fills in the template
+    pub fn render(&self, values: &Values) -> OsString {
+        let mut name = OsString::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => name.push(text),
+                Part::Field(Field::Stem) => name.push(values.stem),
+                Part::Field(Field::Lang) => name.push(values.lang),
+                Part::Field(Field::Lang2) => {
+                    name.push(lang::find(values.lang_id).map_or(values.lang_id, |l| l.alpha2))
+                }
+                Part::Field(Field::Idx) => name.push(values.idx.to_string()),
+                Part::Field(Field::Format) => name.push(values.format),
+                Part::Field(Field::Score) => name.push(format!("{:.1}", values.score)),
+                Part::Field(Field::Forced) if values.forced => name.push(".forced"),
+                Part::Field(Field::Forced) => {}
+                Part::Field(Field::Sdh) if values.sdh => name.push(".sdh"),
+                Part::Field(Field::Sdh) => {}
+                Part::Field(Field::Default) if values.default => name.push(".default"),
+                Part::Field(Field::Default) => {}
+            }
+        }
+
+        name
+    }
+}
@@ -0,0 +1,56 @@
+//! Time spent in the phases of processing the files
+
+use std::future::Future;
+use std::ops::AddAssign;
+use std::time::{Duration, Instant};
+
+use crate::progress;
+
+/// Time spent per phase
+#[derive(Default, Clone, Copy, Debug)]
+pub struct Timings {
+    pub hash: Duration,
+    pub search: Duration,
+    pub download: Duration,
+    pub postprocess: Duration,
+}
+
+impl Timings {
+    /// Prints the timings to stderr
+    pub fn report(&self, label: &str) {
+        progress::clear();
+        eprintln!(
+            "{}: hashing {:.3}s, searching {:.3}s, downloading {:.3}s, post-processing {:.3}s",
+            label,
+            self.hash.as_secs_f64(),
+            self.search.as_secs_f64(),
+            self.download.as_secs_f64(),
+            self.postprocess.as_secs_f64(),
+        );
+    }
+}
+
+impl AddAssign for Timings {
+    fn add_assign(&mut self, other: Timings) {
+        self.hash += other.hash;
+        self.search += other.search;
+        self.download += other.download;
+        self.postprocess += other.postprocess;
+    }
+}
+
+/// Runs f, adding the time it took to the counter
+pub fn timed<T, F: FnOnce() -> T>(counter: &mut Duration, f: F) -> T {
+    let start = Instant::now();
+    let res = f();
+    *counter += start.elapsed();
+    res
+}
+
+/// Awaits the future, adding the time it took to the counter
+pub async fn timed_async<T, F: Future<Output = T>>(counter: &mut Duration, f: F) -> T {
+    let start = Instant::now();
+    let res = f.await;
+    *counter += start.elapsed();
+    res
+}
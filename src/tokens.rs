@@ -0,0 +1,138 @@
+//! The login tokens kept between runs, so that not every run has to log in
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::api::{Backend, Credentials, Session};
+use crate::error::Error;
+use crate::net::api_url;
+use crate::paths::cache_dir;
+
+/// A token and when the server last accepted it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub token: String,
+    pub authenticated: bool,
+    #[serde(default)]
+    pub vip: bool,
+    /// In seconds since the epoch
+    pub time: u64,
+}
+
+/// The tokens keyed by the API, the user and the user agent
+#[derive(Debug, Default)]
+pub struct Tokens {
+    path: Option<PathBuf>,
+    entries: BTreeMap<String, Entry>,
+}
+
+/// The current time in seconds since the epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// How long a token may go unused before the server forgets it, in
+/// seconds
+fn max_idle(backend: &Backend) -> u64 {
+    match backend {
+        Backend::XmlRpc => 15 * 60,
+        Backend::Rest { .. } => 24 * 60 * 60,
+    }
+}
+
+/// The key of the token of a login, the tokens of other entry points
+/// being kept apart
+pub fn key(backend: &Backend, creds: Option<&Credentials>, user_agent: &str) -> String {
+    let mut api = match backend {
+        Backend::XmlRpc => "xmlrpc".to_string(),
+        Backend::Rest { ref api_key } => format!("rest:{}", api_key),
+    };
+    if let Some(url) = api_url() {
+        api = format!("{}@{}", api, url);
+    }
+    let user = creds.map_or("", |c| &c.username);
+
+    format!("{} {} {}", api, user, user_agent)
+}
+
+impl Tokens {
+    /// Loads the tokens from the cache directory, none if the file does
+    /// not exist yet
+    pub fn load() -> Result<Tokens, Error> {
+        let path = cache_dir()
+            .ok_or("cannot find the cache directory")?
+            .join("tokens.json");
+
+        let entries = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))
+                .map_err(|e| Error::Ost(format!("invalid token cache: {}", e).into()))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Tokens {
+            path: Some(path),
+            entries,
+        })
+    }
+
+    /// Returns the session of the token stored under the key, unless it
+    /// has been unused for too long to be still valid
+    pub fn get(&self, key: &str, backend: &Backend, user_agent: &str) -> Option<Session> {
+        let entry = self.entries.get(key)?;
+        if now().saturating_sub(entry.time) >= max_idle(backend) {
+            return None;
+        }
+
+        Some(Session {
+            token: entry.token.clone(),
+            authenticated: entry.authenticated,
+            vip: entry.vip,
+            backend: backend.clone(),
+            user_agent: user_agent.to_string(),
+        })
+    }
+
+    /// Records that the server has just accepted the token of the session
+    pub fn insert(&mut self, key: String, session: &Session) {
+        let entry = Entry {
+            token: session.token.clone(),
+            authenticated: session.authenticated,
+            vip: session.vip,
+            time: now(),
+        };
+        self.entries.insert(key, entry);
+    }
+
+    /// Writes the tokens back into the cache directory, readable only by
+    /// the user
+    pub fn save(&self) -> Result<(), Error> {
+        if let Some(ref path) = self.path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+
+            let mut options = OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+
+            let file = BufWriter::new(options.open(path)?);
+            serde_json::to_writer_pretty(file, &self.entries)
+                .map_err(|e| Error::Ost(format!("cannot write token cache: {}", e).into()))?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+//! Trakt lookups used to prioritize or limit the files to process
+
+use std::ffi::OsStr;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::error::{Error, E_INV_TRAKT_RESP};
+use crate::net::{client, send};
+
+/// Trakt API entry point
+const TRAKT_API_URL: &str = "https://api.trakt.tv";
+
+/// How many of the most recently collected items count as interesting
+const RECENTLY_COLLECTED: usize = 50;
+
+/// Titles on the user's watchlist or recently collected
+pub struct Watchlist {
+    titles: Vec<String>,
+}
+
+/// Lowercases the text and turns everything but letters and digits into
+/// single spaces, with a space at both ends for whole word matching
+fn normalize(s: &str) -> String {
+    let words: Vec<String> = s
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    format!(" {} ", words.join(" "))
+}
+
+/// Fetches a list from the Trakt API
+async fn get_list(client_id: &str, path: &str) -> Result<Vec<Value>, Error> {
+    let url = format!("{}{}", TRAKT_API_URL, path);
+    let req = client()
+        .get(&url)
+        .header("trakt-api-version", "2")
+        .header("trakt-api-key", client_id);
+    let resp: Value = send(req).await?.error_for_status()?.json().await?;
+
+    match resp {
+        Value::Array(items) => Ok(items),
+        _ => Err(E_INV_TRAKT_RESP.at(&url)),
+    }
+}
+
+/// Returns the title of the movie or show of a list item
+fn item_title(item: &Value) -> Option<&str> {
+    item.get("movie")
+        .or_else(|| item.get("show"))
+        .and_then(|m| m.get("title"))
+        .and_then(Value::as_str)
+}
+
+/// Returns when the item was collected, in ISO 8601 format
+fn collected_at(item: &Value) -> &str {
+    item.get("collected_at")
+        .or_else(|| item.get("last_collected_at"))
+        .and_then(Value::as_str)
+        .unwrap_or("")
+}
+
+impl Watchlist {
+    /// Fetches the public watchlist and recent collection of the user
+    pub async fn fetch(user: &str, client_id: &str) -> Result<Watchlist, Error> {
+        let watchlist_path = format!("/users/{}/watchlist", user);
+        let movies_path = format!("/users/{}/collection/movies", user);
+        let shows_path = format!("/users/{}/collection/shows", user);
+        let (watchlist, movies, shows) = futures::try_join!(
+            get_list(client_id, &watchlist_path),
+            get_list(client_id, &movies_path),
+            get_list(client_id, &shows_path),
+        )?;
+
+        let mut collected = movies;
+        collected.extend(shows);
+        collected.sort_by(|a, b| collected_at(b).cmp(collected_at(a)));
+        collected.truncate(RECENTLY_COLLECTED);
+
+        let titles = watchlist
+            .iter()
+            .chain(collected.iter())
+            .filter_map(item_title)
+            .map(normalize)
+            .filter(|t| t.trim() != "")
+            .collect();
+
+        Ok(Watchlist { titles })
+    }
+
+    /// Checks whether the file name contains any of the titles
+    pub fn matches(&self, path: &OsStr) -> bool {
+        let stem = Path::new(path)
+            .file_stem()
+            .map(|s| normalize(&s.to_string_lossy()))
+            .unwrap_or_default();
+
+        self.titles.iter().any(|t| stem.contains(t.as_str()))
+    }
+}
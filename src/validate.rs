@@ -0,0 +1,69 @@
+//! Checking that what was downloaded is a subtitle in the format the
+//! server says, not e.g. an HTML error page or truncated garbage
+
+use encoding_rs::Encoding;
+use regex::Regex;
+
+use crate::error::Error;
+
+/// How many bytes are looked at to tell whether the data is text
+const SNIFF_LEN: usize = 4096;
+
+/// The timing line of an SRT cue, e.g. 00:01:02,500 --> 00:01:04,000
+const SRT_TIMING: &str = r"(?m)^\s*\d+:\d{1,2}:\d{1,2}[,.]\d+\s*-->\s*\d+:\d{1,2}:\d{1,2}[,.]\d+";
+
+/// The timing line of a WebVTT cue, the hours are optional
+const VTT_TIMING: &str = r"(?m)^\s*(\d+:)?\d{1,2}:\d{1,2}\.\d+\s*-->";
+
+/// The start of a MicroDVD cue, e.g. {100}{150}
+const MICRODVD_CUE: &str = r"(?m)^\s*\{\d+\}\{\d*\}";
+
+/// The start of an SSA or ASS cue
+const SSA_CUE: &str = r"(?mi)^\s*Dialogue:";
+
+/// The error about the subtitle
+fn invalid(format: &str, problem: &str) -> Error {
+    Error::Invalid(format!("the downloaded {} subtitle {}", format, problem).into())
+}
+
+/// Checks that the subtitle is text in the format named by the server,
+/// with at least one cue if the format is known
+pub fn check(format: &str, data: &[u8]) -> Result<(), Error> {
+    let text = match Encoding::for_bom(data) {
+        Some((encoding, _)) => encoding.decode(data).0,
+        None => String::from_utf8_lossy(data),
+    };
+    let text = text.trim_start_matches('\u{feff}').trim();
+    if text.is_empty() {
+        return Err(invalid(format, "is empty"));
+    }
+
+    let head: String = text.chars().take(SNIFF_LEN).collect();
+    let lower = head.to_lowercase();
+    if lower.starts_with("<!doctype html") || lower.starts_with("<html") {
+        return Err(invalid(format, "is an HTML page"));
+    }
+    if head.contains('\0') {
+        return Err(invalid(format, "is not text"));
+    }
+
+    let (cue, section) = match format.to_ascii_lowercase().as_str() {
+        "srt" => (SRT_TIMING, None),
+        "sub" => (MICRODVD_CUE, None),
+        "ssa" | "ass" => (SSA_CUE, Some("[events]")),
+        "vtt" if !text.starts_with("WEBVTT") => {
+            return Err(invalid(format, "has no WEBVTT header"))
+        }
+        "vtt" => (VTT_TIMING, None),
+        // nothing is known about the others
+        _ => return Ok(()),
+    };
+    if section.is_some_and(|section| !text.to_lowercase().contains(section)) {
+        return Err(invalid(format, "has no events"));
+    }
+    if !Regex::new(cue).unwrap().is_match(text) {
+        return Err(invalid(format, "has no cues"));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,113 @@
+//! Voting for the subtitles, so that the good ones rank higher for
+//! everyone. The subtitles picked with --interactive are voted for at the
+//! end of the run, as only then is it known which ones were written.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+
+use crate::api::{self, Session};
+use crate::error::Error;
+use crate::report::Reporter;
+use crate::subtitle::Sub;
+
+/// Parses the score of a vote, from 1 to 10
+pub fn parse_score(score: &str) -> Result<u8, Error> {
+    match score.trim().parse() {
+        Ok(score) if (1..=10).contains(&score) => Ok(score),
+        _ => Err(Error::Ost(
+            format!("invalid score: {}, it is from 1 to 10", score).into(),
+        )),
+    }
+}
+
+/// A subtitle written, to be voted for
+#[derive(Debug, Clone)]
+pub struct Written {
+    pub path: PathBuf,
+    pub id: String,
+}
+
+/// Passes the results on to another reporter, collecting the subtitles of
+/// OpenSubtitles written
+pub struct Ballot<'a> {
+    inner: &'a mut dyn Reporter,
+    pub written: Vec<Written>,
+}
+
+impl<'a> Ballot<'a> {
+    pub fn new(inner: &'a mut dyn Reporter) -> Ballot<'a> {
+        Ballot {
+            inner,
+            written: Vec::new(),
+        }
+    }
+}
+
+impl Reporter for Ballot<'_> {
+    fn error(&mut self, file: &Path, error: &Error) {
+        self.inner.error(file, error);
+    }
+
+    fn missing(&mut self, file: &Path, lang: &str, reason: Option<&str>) {
+        self.inner.missing(file, lang, reason);
+    }
+
+    fn kept(&mut self, file: &Path, lang: &str, path: &Path, reason: &str) {
+        self.inner.kept(file, lang, path, reason);
+    }
+
+    fn planned(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        self.inner.planned(file, path, sub);
+    }
+
+    fn saved(&mut self, file: &Path, path: &Path, sub: &Sub) {
+        if sub.provider == "opensubtitles" {
+            self.written.push(Written {
+                path: path.to_path_buf(),
+                id: sub.id.clone(),
+            });
+        }
+        self.inner.saved(file, path, sub);
+    }
+
+    fn done(&mut self, file: &Path) {
+        self.inner.done(file);
+    }
+}
+
+/// Asks on the terminal whether the subtitles were good, voting for the
+/// ones given a score. The failed votes are only logged.
+pub async fn ask(written: &[Written], session: &Session) {
+    for sub in written {
+        let score = loop {
+            eprint!(
+                "Was {} good? [1-10, Enter: skip]: ",
+                sub.path.to_string_lossy()
+            );
+            let mut answer = String::new();
+            match io::stdin().read_line(&mut answer) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+            match answer.trim() {
+                "" => break None,
+                score => match parse_score(score) {
+                    Ok(score) => break Some(score),
+                    Err(e) => eprintln!("{}", e),
+                },
+            }
+        };
+
+        if let Some(score) = score {
+            match api::vote(&sub.id, score, &session.token).await {
+                Ok(rating) => info!(
+                    "subtitle {} is rated {:.1} from {} votes",
+                    sub.id, rating.score, rating.votes
+                ),
+                Err(e) => warn!("cannot vote for subtitle {}: {}", sub.id, e),
+            }
+        }
+    }
+}